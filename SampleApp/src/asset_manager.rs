@@ -0,0 +1,298 @@
+use anymap::AnyMap;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+// Reference to an asset of type T in an AssetManager. Handles are generational like entities so a handle
+// to an unloaded asset never refers to the asset that reused its slot.
+pub struct Handle<T> {
+    index: usize,
+    generation: u64,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle").field("index", &self.index).field("generation", &self.generation).finish()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoadState {
+    Loaded,
+    // The loader failed. The error is kept so it's only reported once.
+    Failed(String),
+    // The last reference was unloaded, or the handle belongs to another manager
+    Unloaded,
+}
+
+struct AssetEntry<T> {
+    name: String,
+    generation: u64,
+    ref_count: usize,
+    asset: std::result::Result<T, String>,
+}
+
+struct AssetStorage<T> {
+    entries: Vec<Option<AssetEntry<T>>>,
+    // Generation of the last entry of each slot, used when the slot is reused
+    generations: Vec<u64>,
+    free: Vec<usize>,
+    names: HashMap<String, usize>,
+}
+
+impl<T> AssetStorage<T> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            generations: Vec::new(),
+            free: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    fn entry(&self, handle: &Handle<T>) -> Option<&AssetEntry<T>> {
+        match self.entries.get(handle.index) {
+            Some(Some(entry)) if entry.generation == handle.generation => Some(entry),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, name: &str, asset: std::result::Result<T, String>) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.generations[index] += 1;
+                index
+            },
+            None => {
+                self.entries.push(None);
+                self.generations.push(0);
+                self.entries.len() - 1
+            }
+        };
+
+        let generation = self.generations[index];
+        self.entries[index] = Some(AssetEntry {
+            name: name.to_owned(),
+            generation,
+            ref_count: 1,
+            asset,
+        });
+        self.names.insert(name.to_owned(), index);
+
+        Handle { index, generation, marker: PhantomData }
+    }
+}
+
+// Stores assets of any type by name with a reference count. Every load must be matched by an unload, and unloading
+// the last reference drops the asset, which frees the gpu resources of meshes and textures.
+pub struct AssetManager {
+    storages: AnyMap,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self {
+            storages: AnyMap::new(),
+        }
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&AssetStorage<T>> {
+        self.storages.get::<AssetStorage<T>>()
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> &mut AssetStorage<T> {
+        self.storages.entry::<AssetStorage<T>>().or_insert_with(AssetStorage::new)
+    }
+
+    // Adds a reference to the asset, calling the loader only if the asset isn't loaded yet.
+    // A failed load still returns a handle so the failure is remembered until it is unloaded.
+    pub fn load<T: 'static, F: FnOnce(&str) -> Result<T>>(&mut self, name: &str, loader: F) -> Handle<T> {
+        if let Some(handle) = self.find::<T>(name) {
+            self.storage_mut::<T>().entries[handle.index].as_mut().unwrap().ref_count += 1;
+            return handle;
+        }
+
+        let asset = loader(name).map_err(|error| format!("{:?}", error));
+        if let Err(error) = &asset {
+            eprintln!("Failed to load the asset {}. Error: {}", name, error);
+        }
+
+        self.storage_mut::<T>().insert(name, asset)
+    }
+
+    // Adds an asset created in code. Replaces the asset if one with the same name is loaded.
+    pub fn insert<T: 'static>(&mut self, name: &str, asset: T) -> Handle<T> {
+        match self.find::<T>(name) {
+            Some(handle) => {
+                let entry = self.storage_mut::<T>().entries[handle.index].as_mut().unwrap();
+                entry.ref_count += 1;
+                entry.asset = Ok(asset);
+                handle
+            },
+            None => self.storage_mut::<T>().insert(name, Ok(asset)),
+        }
+    }
+
+    // Replaces a loaded asset, keeping its references and handles. Returns false and keeps the previous asset if it isn't loaded
+    // or the loader fails.
+    pub fn reload<T: 'static, F: FnOnce(&str) -> Result<T>>(&mut self, name: &str, loader: F) -> bool {
+        let handle = match self.find::<T>(name) {
+            Some(handle) => handle,
+            None => return false,
+        };
+
+        match loader(name) {
+            Ok(asset) => {
+                self.storage_mut::<T>().entries[handle.index].as_mut().unwrap().asset = Ok(asset);
+                true
+            },
+            Err(error) => {
+                eprintln!("Failed to reload the asset {}. Keeping the previous one. Error: {:?}", name, error);
+                false
+            }
+        }
+    }
+
+    // Names of the loaded assets of type T, including the ones that failed to load
+    pub fn names<T: 'static>(&self) -> Vec<String> {
+        match self.storage::<T>() {
+            Some(storage) => storage.names.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Finds the handle of a loaded asset without adding a reference
+    pub fn find<T: 'static>(&self, name: &str) -> Option<Handle<T>> {
+        let storage = self.storage::<T>()?;
+        let index = *storage.names.get(name)?;
+        let generation = storage.entries[index].as_ref()?.generation;
+
+        Some(Handle { index, generation, marker: PhantomData })
+    }
+
+    // None if the asset failed to load or was unloaded
+    pub fn get<T: 'static>(&self, handle: Handle<T>) -> Option<&T> {
+        self.storage::<T>()?.entry(&handle)?.asset.as_ref().ok()
+    }
+
+    pub fn get_by_name<T: 'static>(&self, name: &str) -> Option<&T> {
+        self.get(self.find::<T>(name)?)
+    }
+
+    pub fn load_state<T: 'static>(&self, handle: Handle<T>) -> LoadState {
+        match self.storage::<T>().and_then(|storage| storage.entry(&handle)) {
+            Some(AssetEntry { asset: Ok(_), .. }) => LoadState::Loaded,
+            Some(AssetEntry { asset: Err(error), .. }) => LoadState::Failed(error.clone()),
+            None => LoadState::Unloaded,
+        }
+    }
+
+    // Removes a reference. The asset is dropped when the last one is removed. Unloading a stale handle does nothing.
+    pub fn unload<T: 'static>(&mut self, handle: Handle<T>) {
+        let storage = self.storage_mut::<T>();
+        let ref_count = match storage.entries.get_mut(handle.index) {
+            Some(Some(entry)) if entry.generation == handle.generation => {
+                entry.ref_count -= 1;
+                entry.ref_count
+            },
+            _ => return,
+        };
+
+        if ref_count == 0 {
+            let entry = storage.entries[handle.index].take().unwrap();
+            storage.names.remove(&entry.name);
+            storage.free.push(handle.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn load_reuses_loaded_asset() {
+        let mut assets = AssetManager::new();
+        let first = assets.load("a", |_| Ok(1));
+        let second = assets.load("a", |_| -> Result<i32> { panic!("The asset is already loaded") });
+
+        assert_eq!(first, second);
+        assert_eq!(assets.get(first), Some(&1));
+    }
+
+    #[test]
+    fn unloading_last_reference_drops_asset() {
+        let mut assets = AssetManager::new();
+        let counter = Rc::new(());
+        let handle = assets.load("a", |_| Ok(counter.clone()));
+        assets.load("a", |_| Ok(counter.clone()));
+        assert_eq!(Rc::strong_count(&counter), 2);
+
+        assets.unload(handle);
+        assert_eq!(assets.load_state(handle), LoadState::Loaded);
+
+        assets.unload(handle);
+        assert_eq!(assets.load_state(handle), LoadState::Unloaded);
+        assert_eq!(Rc::strong_count(&counter), 1);
+        assert!(assets.find::<Rc<()>>("a").is_none());
+    }
+
+    #[test]
+    fn stale_handle_does_not_see_reused_slot() {
+        let mut assets = AssetManager::new();
+        let old = assets.load("a", |_| Ok(1));
+        assets.unload(old);
+        let new = assets.load("b", |_| Ok(2));
+
+        assert_ne!(old, new);
+        assert_eq!(assets.get(old), None);
+        assets.unload(old);
+        assert_eq!(assets.get(new), Some(&2));
+    }
+
+    #[test]
+    fn reload_keeps_handle() {
+        let mut assets = AssetManager::new();
+        let handle = assets.load("a", |_| Ok(1));
+
+        assert!(assets.reload("a", |_| Ok(2)));
+        assert_eq!(assets.get(handle), Some(&2));
+        assert!(!assets.reload("a", |_| -> Result<i32> { anyhow::bail!("Parse error") }));
+        assert_eq!(assets.get(handle), Some(&2));
+        assert!(!assets.reload("b", |_| Ok(3)));
+    }
+
+    #[test]
+    fn failed_load_is_remembered() {
+        let mut assets = AssetManager::new();
+        let handle = assets.load::<i32, _>("a", |name| anyhow::bail!("{} doesn't exist", name));
+
+        assert!(matches!(assets.load_state(handle), LoadState::Failed(_)));
+        assert_eq!(assets.get(handle), None);
+    }
+}
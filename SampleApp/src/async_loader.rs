@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const WORKER_COUNT: usize = 4;
+
+type Job<D> = Box<dyn FnOnce() -> (String, Result<D>) + Send>;
+
+// Reads and decodes assets on worker threads. The main thread polls the finished data to create
+// the assets, since gpu uploads and the asset managers stay on the main thread.
+pub struct AsyncLoader<D> {
+    jobs: Option<Sender<Job<D>>>,
+    results: Receiver<(String, Result<D>)>,
+    workers: Vec<thread::JoinHandle<()>>,
+    pending: HashSet<String>,
+    // Progress counts since the last time the loader was idle
+    requested: usize,
+    finished: usize,
+}
+
+impl<D: Send + 'static> AsyncLoader<D> {
+    pub fn new() -> Self {
+        let (job_sender, job_receiver) = channel::<Job<D>>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..WORKER_COUNT).map(|_| {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            thread::spawn(move || loop {
+                // The lock is released before running the job so the other workers can take jobs meanwhile
+                let job = job_receiver.lock().unwrap().recv();
+                match job {
+                    Ok(job) => {
+                        if result_sender.send(job()).is_err() {
+                            break;
+                        }
+                    },
+                    // The loader was dropped
+                    Err(_) => break,
+                }
+            })
+        }).collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results,
+            workers,
+            pending: HashSet::new(),
+            requested: 0,
+            finished: 0,
+        }
+    }
+
+    // Queues the read of an asset. Does nothing if the asset is already being read. A read that panics fails like one
+    // returning an error, so the asset can be requested again.
+    pub fn request(&mut self, name: &str, read: fn(&str) -> Result<D>) {
+        if !self.pending.insert(name.to_owned()) {
+            return;
+        }
+
+        let name = name.to_owned();
+        self.requested += 1;
+        // If every worker stopped the job is dropped, poll then fails the pending reads
+        let _ = self.jobs.as_ref().unwrap().send(Box::new(move || {
+            let result = catch_unwind(AssertUnwindSafe(|| read(&name))).unwrap_or_else(|_| Err(anyhow!("Reading {} panicked", name)));
+            (name, result)
+        }));
+    }
+
+    // Returns the assets finished since the last poll, without blocking
+    pub fn poll(&mut self) -> Vec<(String, Result<D>)> {
+        if self.pending.is_empty() {
+            self.requested = 0;
+            self.finished = 0;
+        }
+
+        let mut finished = Vec::new();
+        loop {
+            match self.results.try_recv() {
+                Ok(result) => finished.push(result),
+                Err(TryRecvError::Empty) => break,
+                // No worker is left to finish the pending reads
+                Err(TryRecvError::Disconnected) => {
+                    let pending = self.pending.iter().filter(|name| !finished.iter().any(|(finished_name, _)| finished_name == *name));
+                    let failed : Vec<(String, Result<D>)> = pending.map(|name| (name.clone(), Err(anyhow!("No worker is left to read {}", name)))).collect();
+                    finished.extend(failed);
+                    break;
+                }
+            }
+        }
+
+        for (name, _) in &finished {
+            self.pending.remove(name);
+        }
+        self.finished += finished.len();
+
+        finished
+    }
+
+    // Fraction of the requested assets that are finished, in the range [0, 1]. 1 when nothing is being loaded.
+    pub fn progress(&self) -> f32 {
+        match self.requested {
+            0 => 1.0,
+            requested => self.finished as f32/requested as f32,
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+impl<D> Drop for AsyncLoader<D> {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once the queued jobs are done
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_length(name: &str) -> Result<usize> {
+        Ok(name.len())
+    }
+
+    #[test]
+    fn finished_reads_are_polled_once() {
+        let mut loader = AsyncLoader::new();
+        loader.request("abc", read_length);
+        loader.request("abc", read_length);
+        loader.request("de", read_length);
+
+        let mut finished = Vec::new();
+        while loader.is_loading() {
+            finished.extend(loader.poll());
+            thread::yield_now();
+        }
+
+        finished.sort_by(|a, b| a.0.cmp(&b.0));
+        let lengths : Vec<(String, usize)> = finished.into_iter().map(|(name, result)| (name, result.unwrap())).collect();
+        assert_eq!(lengths, vec!(("abc".to_owned(), 3), ("de".to_owned(), 2)));
+        assert_eq!(loader.progress(), 1.0);
+    }
+
+    #[test]
+    fn panicking_read_fails_and_can_be_requested_again() {
+        fn panic_on_read(name: &str) -> Result<usize> {
+            panic!("Can't read {}", name);
+        }
+
+        let mut loader = AsyncLoader::new();
+        loader.request("abc", panic_on_read);
+
+        let mut finished = Vec::new();
+        while loader.is_loading() {
+            finished.extend(loader.poll());
+            thread::yield_now();
+        }
+        assert!(finished[0].1.is_err());
+
+        loader.request("abc", read_length);
+        while loader.is_loading() {
+            finished.extend(loader.poll());
+            thread::yield_now();
+        }
+        assert_eq!(finished[1].1.as_ref().unwrap(), &3);
+    }
+}
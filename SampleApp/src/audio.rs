@@ -0,0 +1,122 @@
+use crate::asset_manager::AssetManager;
+use anyhow::{anyhow, Result};
+use sdl2::mixer::{Channel, Chunk, InitFlag, Music, Sdl2MixerContext, MAX_VOLUME};
+
+// Number of sounds that can play at the same time. Sounds played while every channel is busy are skipped.
+const CHANNEL_COUNT: i32 = 16;
+
+fn sound_path(sound_name: &str) -> std::path::PathBuf {
+    let mut path = std::env::current_dir().unwrap();
+    path.push("src\\resources\\sounds");
+    path.push(sound_name);
+    path
+}
+
+fn load_sound(sound_name: &str) -> Result<Chunk> {
+    Chunk::from_file(sound_path(sound_name)).map_err(|error| anyhow!("Failed to load the sound {}. Error: {}", sound_name, error))
+}
+
+fn to_mixer_volume(volume: f32) -> i32 {
+    (volume.max(0.0).min(1.0)*MAX_VOLUME as f32) as i32
+}
+
+// Plays WAV and OGG sound effects and music from the sounds folder of the resources.
+// If the audio device can't be opened the game runs without sound and every call does nothing.
+pub struct Audio {
+    device: Option<(sdl2::AudioSubsystem, Sdl2MixerContext)>,
+    // Sound effects, loaded the first time they are played
+    pub assets: AssetManager,
+    music: Option<Music<'static>>,
+}
+
+impl Audio {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+        let device = Audio::open_device(sdl_context).map_err(|error| eprintln!("Audio is disabled. Error: {:?}", error)).ok();
+
+        Self {
+            device,
+            assets: AssetManager::new(),
+            music: None,
+        }
+    }
+
+    fn open_device(sdl_context: &sdl2::Sdl) -> Result<(sdl2::AudioSubsystem, Sdl2MixerContext)> {
+        let audio_subsystem = sdl_context.audio().map_err(|error| anyhow!(error))?;
+        sdl2::mixer::open_audio(sdl2::mixer::DEFAULT_FREQUENCY, sdl2::mixer::DEFAULT_FORMAT, sdl2::mixer::DEFAULT_CHANNELS, 1024).map_err(|error| anyhow!(error))?;
+        let mixer_context = sdl2::mixer::init(InitFlag::OGG).map_err(|error| anyhow!(error))?;
+        sdl2::mixer::allocate_channels(CHANNEL_COUNT);
+
+        Ok((audio_subsystem, mixer_context))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.device.is_some()
+    }
+
+    pub fn play_sound(&mut self, sound_name: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        if self.assets.find::<Chunk>(sound_name).is_none() {
+            self.assets.load(sound_name, load_sound);
+        }
+
+        if let Some(sound) = self.assets.get_by_name::<Chunk>(sound_name) {
+            let _ = Channel::all().play(sound, 0);
+        }
+    }
+
+    // Replaces the music being played
+    pub fn play_music(&mut self, music_name: &str, looping: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let music = match Music::from_file(sound_path(music_name)) {
+            Ok(music) => music,
+            Err(error) => {
+                eprintln!("Failed to load the music {}. Error: {}", music_name, error);
+                return;
+            }
+        };
+
+        // -1 loops forever
+        if let Err(error) = music.play(if looping { -1 } else { 1 }) {
+            eprintln!("Failed to play the music {}. Error: {}", music_name, error);
+        }
+        self.music = Some(music);
+    }
+
+    pub fn stop_music(&mut self) {
+        if self.is_enabled() {
+            Music::halt();
+        }
+        self.music = None;
+    }
+
+    // Volumes are in the range [0, 1]
+    pub fn set_sound_volume(&mut self, volume: f32) {
+        if self.is_enabled() {
+            Channel::all().set_volume(to_mixer_volume(volume));
+        }
+    }
+
+    pub fn set_music_volume(&mut self, volume: f32) {
+        if self.is_enabled() {
+            Music::set_volume(to_mixer_volume(volume));
+        }
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        // The music and sounds must be freed before the device is closed
+        self.music = None;
+        self.assets = AssetManager::new();
+
+        if self.device.is_some() {
+            sdl2::mixer::close_audio();
+        }
+    }
+}
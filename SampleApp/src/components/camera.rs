@@ -9,27 +9,66 @@ const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Orthographic {
     left: f32,
     right: f32,
     bottom: f32,
     top: f32,
+    // When set, left and right are recomputed from the window aspect ratio so the view is never stretched
+    #[serde(default)]
+    preserve_aspect: bool,
+    // Current zoom relative to the authored bounds
+    #[serde(default = "default_zoom")]
+    zoom: f32,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+fn default_zoom() -> f32 {
+    1.0
+}
+
+// Orthographic view centered on the eye, showing the same height in world units at any window size. The width follows the
+// aspect ratio of the viewport.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Ortho2D {
+    world_height: f32,
+    // Kept in sync with the viewport by the render system
+    #[serde(default = "default_aspect")]
+    aspect: f32,
+    #[serde(default = "default_zoom")]
+    zoom: f32,
+}
+
+fn default_aspect() -> f32 {
+    1.0
+}
+
+impl Ortho2D {
+    // Left, right, bottom and top relative to the eye
+    fn bounds(&self) -> (f32, f32, f32, f32) {
+        let half_height = self.world_height*0.5/self.zoom;
+        let half_width = half_height*self.aspect;
+        (-half_width, half_width, -half_height, half_height)
+    }
+}
+
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 10.0;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Perspective {
     aspect : f32,
     fovy: f32,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub enum CameraProperties {
     Ortho(Orthographic),
+    Ortho2D(Ortho2D),
     Persp(Perspective),
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -37,18 +76,330 @@ pub struct Camera {
     pub properties: CameraProperties,
     pub znear: f32,
     pub zfar: f32,
+    #[serde(deserialize_with = "deserialize_color")]
     pub clear_color: wgpu::Color,
+    // Normalized x, y, width and height of the area of the window the camera renders to. The origin is the top left corner.
+    #[serde(default = "default_viewport")]
+    pub viewport: (f32, f32, f32, f32),
+    // Cameras are rendered in increasing order
+    #[serde(default)]
+    pub order: i32,
+    // Renders into a texture instead of the window when set
+    #[serde(default)]
+    pub render_target: Option<CameraTarget>,
+    // Added to the eye and target when building the view, so effects like shaking don't move the camera itself
+    #[serde(skip, default = "zero_offset")]
+    pub view_offset: cgmath::Vector3<f32>,
+}
+
+// Texture a camera renders into, e.g. for a minimap or a mirror. Meshes show it by using the name as their diffuse_texture.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct CameraTarget {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Prefabs can write colors as { "r": .., "g": .., "b": .., "a": .. } in the range [0, 1], as a "#rrggbb" or "#rrggbbaa" string
+// or as an [r, g, b] or [r, g, b, a] array in the range [0, 255]. The alpha is 1 when omitted.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Color {
+    // Tried before Components since a struct can also be deserialized from an array of its fields
+    Bytes(Vec<u8>),
+    Components(wgpu::Color),
+    Hex(String),
+}
+
+fn deserialize_color<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<wgpu::Color, D::Error> {
+    use serde::Deserialize;
+    use serde::de::Error;
+
+    let bytes = match Color::deserialize(deserializer)? {
+        Color::Components(color) => return Ok(color),
+        Color::Hex(hex) => parse_hex_color(&hex).ok_or_else(|| D::Error::custom(format!("Invalid hex color {}", hex)))?,
+        Color::Bytes(bytes) => bytes,
+    };
+
+    match bytes.as_slice() {
+        [r, g, b] => Ok(color_from_bytes(*r, *g, *b, 255)),
+        [r, g, b, a] => Ok(color_from_bytes(*r, *g, *b, *a)),
+        _ => Err(D::Error::custom(format!("Expected 3 or 4 color components, got {}", bytes.len()))),
+    }
+}
+
+fn parse_hex_color(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex.strip_prefix('#')?;
+    if (digits.len() != 6 && digits.len() != 8) || !digits.is_ascii() {
+        return None;
+    }
+
+    (0..digits.len()).step_by(2)
+        .map(|start| u8::from_str_radix(&digits[start..start + 2], 16).ok())
+        .collect()
+}
+
+fn color_from_bytes(r: u8, g: u8, b: u8, a: u8) -> wgpu::Color {
+    wgpu::Color { r: r as f64/255.0, g: g as f64/255.0, b: b as f64/255.0, a: a as f64/255.0 }
+}
+
+fn zero_offset() -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(0.0, 0.0, 0.0)
+}
+
+fn default_viewport() -> (f32, f32, f32, f32) {
+    (0.0, 0.0, 1.0, 1.0)
 }
 
 impl Camera {
+    pub fn set_aspect_ratio(&mut self, aspect: f32) {
+        match &mut self.properties {
+            CameraProperties::Ortho(properties) => {
+                if properties.preserve_aspect {
+                    let center = (properties.left + properties.right)*0.5;
+                    let half_width = (properties.top - properties.bottom)*0.5*aspect;
+                    properties.left = center - half_width;
+                    properties.right = center + half_width;
+                }
+            },
+            CameraProperties::Ortho2D(properties) => properties.aspect = aspect,
+            CameraProperties::Persp(properties) => properties.aspect = aspect,
+        }
+    }
+
+    // Size of what the camera renders into, its render target or else the window
+    pub fn target_size(&self, window_size: (u32, u32)) -> (u32, u32) {
+        match &self.render_target {
+            Some(render_target) => (render_target.width, render_target.height),
+            None => window_size,
+        }
+    }
+
+    // The viewport in pixels for a window of the given size
+    pub fn viewport_rect(&self, window_size: (u32, u32)) -> (f32, f32, f32, f32) {
+        let (width, height) = (window_size.0 as f32, window_size.1 as f32);
+        (self.viewport.0*width, self.viewport.1*height, self.viewport.2*width, self.viewport.3*height)
+    }
+
+    // Scales the orthographic bounds around their center. A factor greater than one zooms in.
+    pub fn zoom(&mut self, factor: f32) {
+        match &mut self.properties {
+            CameraProperties::Ortho(properties) => {
+                let zoom = (properties.zoom*factor).max(MIN_ZOOM).min(MAX_ZOOM);
+                let scale = properties.zoom/zoom;
+                properties.zoom = zoom;
+
+                let center = ((properties.left + properties.right)*0.5, (properties.bottom + properties.top)*0.5);
+                properties.left = center.0 + (properties.left - center.0)*scale;
+                properties.right = center.0 + (properties.right - center.0)*scale;
+                properties.bottom = center.1 + (properties.bottom - center.1)*scale;
+                properties.top = center.1 + (properties.top - center.1)*scale;
+            },
+            CameraProperties::Ortho2D(properties) => properties.zoom = (properties.zoom*factor).clamp(MIN_ZOOM, MAX_ZOOM),
+            CameraProperties::Persp(_) => {},
+        }
+    }
+
+    // Moves the eye and target together so the view direction is kept
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let offset = cgmath::Vector3 { x: dx, y: dy, z: 0.0 };
+        self.eye += offset;
+        self.target += offset;
+    }
+
+    // Points the camera along the rotated -z axis, keeping the distance from the eye to the target. The rotation is applied
+    // around the x, y and z axes in that order.
+    pub fn set_orientation(&mut self, rotation: cgmath::Euler<cgmath::Deg<f32>>) {
+        use cgmath::InnerSpace;
+
+        let rotation = cgmath::Quaternion::from(rotation);
+        let distance = (self.target - self.eye).magnitude();
+        self.target = self.eye + rotation*cgmath::Vector3::new(0.0, 0.0, -distance);
+        self.up = rotation*cgmath::Vector3::unit_y();
+    }
+
+    // Size of a pixel in world units for orthographic cameras
+    pub fn world_units_per_pixel(&self, viewport: (u32, u32)) -> Option<(f32, f32)> {
+        match &self.properties {
+            CameraProperties::Ortho(properties) if viewport.0 > 0 && viewport.1 > 0 => {
+                Some(((properties.right - properties.left)/viewport.0 as f32, (properties.top - properties.bottom)/viewport.1 as f32))
+            },
+            CameraProperties::Ortho2D(properties) if viewport.0 > 0 && viewport.1 > 0 => {
+                let (left, right, bottom, top) = properties.bounds();
+                Some(((right - left)/viewport.0 as f32, (top - bottom)/viewport.1 as f32))
+            },
+            _ => None
+        }
+    }
+
+    // Converts a pixel position to world space on the near plane. For orthographic cameras x and y don't depend on the depth.
+    pub fn screen_to_world(&self, screen_pos: (f32, f32), viewport: (u32, u32)) -> cgmath::Point3<f32> {
+        self.unproject(screen_pos, viewport, 0.0)
+    }
+
+    // Returns the origin, on the near plane, and normalized direction of the ray going through a pixel.
+    pub fn screen_to_ray(&self, screen_pos: (f32, f32), viewport: (u32, u32)) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        use cgmath::InnerSpace;
+
+        let near = self.unproject(screen_pos, viewport, 0.0);
+        let far = self.unproject(screen_pos, viewport, 1.0);
+        (near, (far - near).normalize())
+    }
+
+    // The depth is in wgpu's normalized device coordinates, 0 being the near plane and 1 the far plane.
+    fn unproject(&self, screen_pos: (f32, f32), viewport: (u32, u32), depth: f32) -> cgmath::Point3<f32> {
+        use cgmath::SquareMatrix;
+
+        // Pixels have their origin at the top left while normalized device coordinates have it at the center with y up
+        let ndc = cgmath::Vector4 {
+            x: 2.0*screen_pos.0/viewport.0 as f32 - 1.0,
+            y: 1.0 - 2.0*screen_pos.1/viewport.1 as f32,
+            z: depth,
+            w: 1.0,
+        };
+
+        // The view projection matrix already includes OPENGL_TO_WGPU_MATRIX so its inverse maps wgpu's depth range back
+        let inverse_view_projection = self.build_view_projection_matrix().invert().unwrap();
+        let world = inverse_view_projection*ndc;
+        cgmath::Point3 { x: world.x/world.w, y: world.y/world.w, z: world.z/world.w }
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let projection = match &self.properties {
             CameraProperties::Ortho(properties) => cgmath::ortho(properties.left, properties.right, properties.bottom, properties.top, self.znear, self.zfar),
+            CameraProperties::Ortho2D(properties) => {
+                let (left, right, bottom, top) = properties.bounds();
+                cgmath::ortho(left, right, bottom, top, self.znear, self.zfar)
+            },
             CameraProperties::Persp(properties) => cgmath::perspective(cgmath::Deg(properties.fovy), properties.aspect, self.znear, self.zfar),
         };
 
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let view = cgmath::Matrix4::look_at_rh(self.eye + self.view_offset, self.target + self.view_offset, self.up);
 
         OPENGL_TO_WGPU_MATRIX*projection*view
     }
-}
\ No newline at end of file
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.build_view_projection_matrix())
+    }
+}
+
+// Volume seen by a camera, as six planes whose normals point inside
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Each plane is a combination of the rows of the matrix. The near plane is the third row alone since wgpu's depth starts at 0.
+    pub fn from_view_projection(matrix: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::{InnerSpace, Matrix};
+
+        let (x, y, z, w) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z];
+        Self {
+            planes: planes.map(|plane| plane/plane.truncate().magnitude()),
+        }
+    }
+
+    // Conservative near the edges, a sphere outside the corners may still be considered inside
+    pub fn intersects_sphere(&self, center: cgmath::Vector3<f32>, radius: f32) -> bool {
+        use cgmath::InnerSpace;
+
+        self.planes.iter().all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ortho_camera() -> Camera {
+        Camera {
+            eye: cgmath::Point3 { x: 10.0, y: 20.0, z: 1.0 },
+            target: cgmath::Point3 { x: 10.0, y: 20.0, z: 0.0 },
+            up: cgmath::Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            properties: CameraProperties::Ortho(Orthographic {
+                left: -100.0,
+                right: 100.0,
+                bottom: -50.0,
+                top: 50.0,
+                preserve_aspect: false,
+                zoom: 1.0,
+            }),
+            znear: 0.1,
+            zfar: 10.0,
+            clear_color: wgpu::Color::BLACK,
+            viewport: default_viewport(),
+            order: 0,
+            render_target: None,
+            view_offset: zero_offset(),
+        }
+    }
+
+    #[test]
+    fn clear_color_accepts_hex_and_byte_arrays() {
+        let parse = |color: serde_json::Value| {
+            let mut json = serde_json::to_value(ortho_camera()).unwrap();
+            json["clear_color"] = color;
+            serde_json::from_value::<Camera>(json).map(|camera| camera.clear_color)
+        };
+
+        let expected = color_from_bytes(0x20, 0x40, 0x80, 0xff);
+        assert_eq!(parse(serde_json::json!("#204080")).unwrap(), expected);
+        assert_eq!(parse(serde_json::json!([32, 64, 128, 255])).unwrap(), expected);
+        assert_eq!(parse(serde_json::json!({ "r": 0.5, "g": 0.5, "b": 0.5, "a": 1.0 })).unwrap(), wgpu::Color { r: 0.5, g: 0.5, b: 0.5, a: 1.0 });
+        assert!(parse(serde_json::json!("#2040")).is_err());
+    }
+
+    #[test]
+    fn render_target_replaces_the_window_size() {
+        let mut camera = ortho_camera();
+        camera.viewport = (0.5, 0.0, 0.5, 1.0);
+        assert_eq!(camera.viewport_rect(camera.target_size((800, 600))), (400.0, 0.0, 400.0, 600.0));
+
+        camera.render_target = Some(CameraTarget { name: "minimap".to_owned(), width: 256, height: 128 });
+        assert_eq!(camera.viewport_rect(camera.target_size((800, 600))), (128.0, 0.0, 128.0, 128.0));
+    }
+
+    #[test]
+    fn frustum_of_ortho_camera_is_its_view_rectangle() {
+        let frustum = ortho_camera().frustum();
+
+        assert!(frustum.intersects_sphere(cgmath::Vector3::new(10.0, 20.0, 0.0), 1.0));
+        assert!(frustum.intersects_sphere(cgmath::Vector3::new(112.0, 20.0, 0.0), 5.0));
+        assert!(!frustum.intersects_sphere(cgmath::Vector3::new(200.0, 20.0, 0.0), 5.0));
+        assert!(!frustum.intersects_sphere(cgmath::Vector3::new(10.0, -40.0, 0.0), 5.0));
+        // Behind the far plane
+        assert!(!frustum.intersects_sphere(cgmath::Vector3::new(10.0, 20.0, -20.0), 1.0));
+    }
+
+    #[test]
+    fn ortho_2d_keeps_its_height_in_world_units() {
+        let mut camera = ortho_camera();
+        camera.properties = serde_json::from_value(serde_json::json!({ "Ortho2D": { "world_height": 10.0 } })).unwrap();
+        camera.set_aspect_ratio(2.0);
+
+        let world = camera.screen_to_world((0.0, 0.0), (800, 400));
+        assert!((world.x - (10.0 - 10.0)).abs() < 1e-3);
+        assert!((world.y - (20.0 + 5.0)).abs() < 1e-3);
+
+        camera.zoom(2.0);
+        let (units_x, units_y) = camera.world_units_per_pixel((800, 400)).unwrap();
+        assert!((units_x - 10.0/800.0).abs() < 1e-6 && (units_y - 5.0/400.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn screen_center_unprojects_to_eye() {
+        let camera = ortho_camera();
+        let world = camera.screen_to_world((400.0, 300.0), (800, 600));
+        assert!((world.x - 10.0).abs() < 1e-3);
+        assert!((world.y - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn screen_top_left_unprojects_to_ortho_bounds() {
+        let camera = ortho_camera();
+        let world = camera.screen_to_world((0.0, 0.0), (800, 600));
+        assert!((world.x - (10.0 - 100.0)).abs() < 1e-3);
+        assert!((world.y - (20.0 + 50.0)).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,68 @@
+// Shakes the camera of the entity for duration seconds, then the component removes itself
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct CameraShake {
+    // Largest offset in world units, at the start of the shake. It decreases linearly to zero.
+    pub amplitude: f32,
+    pub duration: f32,
+    #[serde(default)]
+    pub elapsed: f32,
+    // State of the random generator. The same seed always produces the same shake.
+    #[serde(default = "default_seed")]
+    pub seed: u32,
+}
+
+fn default_seed() -> u32 {
+    0x9e37_79b9
+}
+
+impl CameraShake {
+    pub fn new(amplitude: f32, duration: f32) -> Self {
+        Self {
+            amplitude,
+            duration,
+            elapsed: 0.0,
+            seed: default_seed(),
+        }
+    }
+
+    // Advances the shake and returns the offset of the camera, or None once the shake is over
+    pub fn advance(&mut self, delta_time: f32) -> Option<cgmath::Vector3<f32>> {
+        self.elapsed += delta_time;
+        if self.elapsed >= self.duration {
+            return None;
+        }
+
+        let strength = self.amplitude*(1.0 - self.elapsed/self.duration);
+        Some(cgmath::Vector3::new(self.next_random()*strength, self.next_random()*strength, 0.0))
+    }
+
+    // Xorshift, in the range [-1, 1]
+    fn next_random(&mut self) -> f32 {
+        // Zero is the only state xorshift never leaves
+        if self.seed == 0 {
+            self.seed = default_seed();
+        }
+
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32/u32::MAX as f32*2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shake_decays_and_ends() {
+        let mut shake = CameraShake::new(2.0, 1.0);
+        let first = shake.advance(0.1).unwrap();
+        assert!(first.x.abs() <= 1.8 && first.y.abs() <= 1.8);
+
+        let mut same_seed = CameraShake::new(2.0, 1.0);
+        assert_eq!(same_seed.advance(0.1), Some(first));
+
+        assert!(shake.advance(1.0).is_none());
+    }
+}
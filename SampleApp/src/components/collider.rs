@@ -0,0 +1,12 @@
+// Axis aligned bounding box centered on the entity's Transform position
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Collider {
+    pub half_extents: cgmath::Vector2<f32>,
+}
+
+impl Collider {
+    pub fn overlaps(&self, position: cgmath::Vector3<f32>, other: &Collider, other_position: cgmath::Vector3<f32>) -> bool {
+        (position.x - other_position.x).abs() <= self.half_extents.x + other.half_extents.x &&
+        (position.y - other_position.y).abs() <= self.half_extents.y + other.half_extents.y
+    }
+}
@@ -1,59 +1,275 @@
 use crate::entity::EntityId;
+use std::collections::HashMap;
 
 pub struct ArrayEntry<T> {
     pub value: T,
     generation: u64,
 }
 
+enum Storage<T> {
+    // One slot per entity index. Fastest lookups, but allocates a slot for every entity index up to the highest one set.
+    Dense(Vec<Option<ArrayEntry<T>>>),
+    // Components packed together with the entity index of each one, and the position of each entity index in the packed vector.
+    // Only allocates for the entities that have the component.
+    Sparse {
+        values: Vec<ArrayEntry<T>>,
+        indices: Vec<usize>,
+        positions: HashMap<usize, usize>,
+    },
+}
+
 pub struct ComponentSet<T> {
-    pub entries: Vec<Option<ArrayEntry<T>>>,
+    storage: Storage<T>,
+    // One bit per entity index, set when the component is set or mutably accessed and cleared at the start of every frame
+    changed: Vec<u64>,
 }
 
 impl<T> ComponentSet<T> {
-    pub fn new(max_size: usize) -> Self {
+    // The slots of the first initial_size entities are allocated up front. The set grows when a component is set past them.
+    pub fn new(initial_size: usize) -> Self {
         let mut entries = Vec::new();
-        entries.resize_with(max_size, Default::default);
+        entries.resize_with(initial_size, Default::default);
+        ComponentSet {
+            storage: Storage::Dense(entries),
+            changed: Vec::new(),
+        }
+    }
+
+    // For components few entities have. Lookups go through a hash map instead of allocating a slot per entity.
+    pub fn new_sparse() -> Self {
         ComponentSet {
-            entries
+            storage: Storage::Sparse {
+                values: Vec::new(),
+                indices: Vec::new(),
+                positions: HashMap::new(),
+            },
+            changed: Vec::new(),
         }
     }
 
-    // Set value for some index. May overwrite past generation.
+    // Stores the component of the entity, or clears the slot when the value is None. May overwrite past generation.
     pub fn set(&mut self, gen_index: &EntityId, value: Option<T>) {
-        debug_assert!(gen_index.index < self.entries.len());
-
-        match value {
-            Some(v) => {
-                let new_entry = ArrayEntry {
-                    value: v,
-                    generation: gen_index.generation,
-                };
-                
-                self.entries[gen_index.index] = Some(new_entry);
+        match value.is_some() {
+            true => self.mark_changed(gen_index.index),
+            false => self.unmark_changed(gen_index.index),
+        }
+
+        let new_entry = value.map(|v| ArrayEntry {
+            value: v,
+            generation: gen_index.generation,
+        });
+
+        match &mut self.storage {
+            Storage::Dense(entries) => {
+                if gen_index.index >= entries.len() {
+                    if new_entry.is_none() {
+                        return;
+                    }
+
+                    // Doubling keeps the number of reallocations low when many entities are created
+                    let new_len = (entries.len()*2).max(gen_index.index + 1);
+                    entries.resize_with(new_len, Default::default);
+                }
+
+                entries[gen_index.index] = new_entry;
             },
-            None => {
-                self.entries[gen_index.index] = None; 
+            Storage::Sparse { values, indices, positions } => {
+                match (new_entry, positions.get(&gen_index.index).copied()) {
+                    (Some(new_entry), Some(position)) => values[position] = new_entry,
+                    (Some(new_entry), None) => {
+                        positions.insert(gen_index.index, values.len());
+                        values.push(new_entry);
+                        indices.push(gen_index.index);
+                    },
+                    // The last component is moved into the removed one's place to keep them packed
+                    (None, Some(position)) => {
+                        positions.remove(&gen_index.index);
+                        values.swap_remove(position);
+                        indices.swap_remove(position);
+                        if position < indices.len() {
+                            positions.insert(indices[position], position);
+                        }
+                    },
+                    (None, None) => {}
+                }
             }
-        }      
+        }
+    }
+
+    fn entry(&self, index: usize) -> Option<&ArrayEntry<T>> {
+        match &self.storage {
+            Storage::Dense(entries) => entries.get(index).and_then(Option::as_ref),
+            Storage::Sparse { values, positions, .. } => positions.get(&index).map(|position| &values[*position]),
+        }
+    }
+
+    fn entry_mut(&mut self, index: usize) -> Option<&mut ArrayEntry<T>> {
+        match &mut self.storage {
+            Storage::Dense(entries) => entries.get_mut(index).and_then(Option::as_mut),
+            Storage::Sparse { values, positions, .. } => {
+                let position = *positions.get(&index)?;
+                Some(&mut values[position])
+            },
+        }
     }
 
     // Gets a constant value for some generational index. The generation must match.
     pub fn get(&self, gen_index: &EntityId) -> Option<&T> {
-        debug_assert!(gen_index.index < self.entries.len());
-
-        match &self.entries[gen_index.index] {
+        match self.entry(gen_index.index) {
             None => None,
             Some(entry) => if entry.generation != gen_index.generation { None } else { Some(&entry.value) }
         }
     }
 
-    // Gets a mutable value for some generational index. The generation must match.
+    // Gets a mutable value for some generational index. The generation must match. The component is marked as changed even
+    // if the caller doesn't modify it.
     pub fn get_mut(&mut self, gen_index: &EntityId) -> Option<&mut T> {
-        debug_assert!(gen_index.index < self.entries.len());
+        self.get(gen_index)?;
+        self.mark_changed(gen_index.index);
+        self.entry_mut(gen_index.index).map(|entry| &mut entry.value)
+    }
 
-        match &mut self.entries[gen_index.index] {
-            None => None,
-            Some(entry) => if entry.generation != gen_index.generation { None } else { Some(&mut entry.value) }
+    // True if the component of the entity was set or mutably accessed since the last clear_changed, usually this frame
+    pub fn changed(&self, gen_index: &EntityId) -> bool {
+        let (word, bit) = (gen_index.index/64, gen_index.index%64);
+        matches!(self.changed.get(word), Some(bits) if bits & (1 << bit) != 0) && self.get(gen_index).is_some()
+    }
+
+    // Called by the system manager at the start of every frame
+    pub fn clear_changed(&mut self) {
+        self.changed.iter_mut().for_each(|bits| *bits = 0);
+    }
+
+    fn mark_changed(&mut self, index: usize) {
+        mark_changed(&mut self.changed, index);
+    }
+
+    fn unmark_changed(&mut self, index: usize) {
+        if let Some(bits) = self.changed.get_mut(index/64) {
+            *bits &= !(1 << (index%64));
+        }
+    }
+
+    // Number of entities that have the component
+    pub fn count(&self) -> usize {
+        match &self.storage {
+            Storage::Dense(entries) => entries.iter().filter(|entry| entry.is_some()).count(),
+            Storage::Sparse { values, .. } => values.len(),
+        }
+    }
+
+    // Visits the components in index order for dense sets and in no particular order for sparse ones.
+    // Components are cleared when their entity is destroyed, so every entity yielded is alive.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (EntityId, &T)> + '_> {
+        match &self.storage {
+            Storage::Dense(entries) => Box::new(entries.iter()
+                .enumerate()
+                .filter_map(|(index, entry)| entry.as_ref().map(|entry| (EntityId { index, generation: entry.generation }, &entry.value)))),
+            Storage::Sparse { values, indices, .. } => Box::new(indices.iter()
+                .zip(values.iter())
+                .map(|(index, entry)| (EntityId { index: *index, generation: entry.generation }, &entry.value))),
         }
     }
-}
\ No newline at end of file
+
+    // Marks the components as changed as they are visited
+    pub fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (EntityId, &mut T)> + '_> {
+        let changed = &mut self.changed;
+        match &mut self.storage {
+            Storage::Dense(entries) => Box::new(entries.iter_mut()
+                .enumerate()
+                .filter_map(move |(index, entry)| entry.as_mut().map(|entry| {
+                    mark_changed(changed, index);
+                    (EntityId { index, generation: entry.generation }, &mut entry.value)
+                }))),
+            Storage::Sparse { values, indices, .. } => Box::new(indices.iter()
+                .zip(values.iter_mut())
+                .map(move |(index, entry)| {
+                    mark_changed(changed, *index);
+                    (EntityId { index: *index, generation: entry.generation }, &mut entry.value)
+                })),
+        }
+    }
+}
+
+fn mark_changed(changed: &mut Vec<u64>, index: usize) {
+    let (word, bit) = (index/64, index%64);
+    if word >= changed.len() {
+        changed.resize(word + 1, 0);
+    }
+    changed[word] |= 1 << bit;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_yields_set_entries_with_their_generation() {
+        let mut set = ComponentSet::new(4);
+        set.set(&EntityId { index: 1, generation: 2 }, Some(10));
+        set.set(&EntityId { index: 3, generation: 0 }, Some(30));
+
+        for (_, value) in set.iter_mut() {
+            *value += 1;
+        }
+
+        let entries : Vec<(EntityId, &i32)> = set.iter().collect();
+        assert_eq!(entries, vec!((EntityId { index: 1, generation: 2 }, &11), (EntityId { index: 3, generation: 0 }, &31)));
+    }
+
+    #[test]
+    fn setting_none_clears_the_slot() {
+        let mut set = ComponentSet::new(2);
+        let entity = EntityId { index: 0, generation: 1 };
+        set.set(&entity, Some(5));
+        set.set(&entity, None);
+
+        assert_eq!(set.get(&entity), None);
+        assert_eq!(set.iter().count(), 0);
+    }
+
+    #[test]
+    fn dense_set_grows_past_initial_size() {
+        let mut set = ComponentSet::new(1);
+        let entity = EntityId { index: 5, generation: 0 };
+        assert_eq!(set.get(&entity), None);
+
+        set.set(&entity, Some(1));
+        assert_eq!(set.get(&entity), Some(&1));
+    }
+
+    #[test]
+    fn mutable_access_marks_the_component_changed() {
+        let mut set = ComponentSet::new(2);
+        let (a, b) = (EntityId { index: 0, generation: 0 }, EntityId { index: 70, generation: 0 });
+        set.set(&a, Some(1));
+        set.set(&b, Some(2));
+        assert!(set.changed(&a) && set.changed(&b));
+
+        set.clear_changed();
+        assert!(set.get(&a).is_some() && !set.changed(&a));
+        *set.get_mut(&b).unwrap() += 1;
+        assert!(!set.changed(&a) && set.changed(&b));
+        assert!(!set.changed(&EntityId { index: 70, generation: 1 }));
+
+        set.clear_changed();
+        set.iter_mut().count();
+        assert!(set.changed(&a) && set.changed(&b));
+    }
+
+    #[test]
+    fn sparse_set_keeps_entries_after_removal() {
+        let mut set = ComponentSet::new_sparse();
+        let (a, b, c) = (EntityId { index: 7, generation: 0 }, EntityId { index: 2, generation: 1 }, EntityId { index: 5, generation: 0 });
+        set.set(&a, Some('a'));
+        set.set(&b, Some('b'));
+        set.set(&c, Some('c'));
+        set.set(&a, None);
+
+        assert_eq!(set.get(&a), None);
+        assert_eq!(set.get(&b), Some(&'b'));
+        assert_eq!(set.get(&c), Some(&'c'));
+        assert_eq!(set.get(&EntityId { index: 2, generation: 0 }), None);
+        assert_eq!(set.iter().count(), 2);
+    }
+}
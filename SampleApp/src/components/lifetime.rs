@@ -0,0 +1,5 @@
+// Destroys the entity once the remaining seconds run out
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Lifetime {
+    pub remaining: f32,
+}
@@ -0,0 +1,55 @@
+use cgmath::InnerSpace;
+
+// Directional light, like the sun. Only the first light found is used by the RenderSystem.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Light {
+    // Direction the light travels in world space. A zero direction falls back to DEFAULT_DIRECTION.
+    pub direction: cgmath::Vector3<f32>,
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    // Fraction of the color lighting surfaces facing away from the light
+    #[serde(default = "default_ambient")]
+    pub ambient: f32,
+}
+
+// Straight into the screen, like the light used when there is no Light
+pub const DEFAULT_DIRECTION: cgmath::Vector3<f32> = cgmath::Vector3::new(0.0, 0.0, -1.0);
+
+fn default_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_ambient() -> f32 {
+    0.1
+}
+
+impl Light {
+    // The direction normalized. Directions that can't be normalized use DEFAULT_DIRECTION, instead of lighting everything with NaN.
+    pub fn unit_direction(&self) -> cgmath::Vector3<f32> {
+        let length = self.direction.magnitude();
+        match length.is_normal() {
+            true => self.direction/length,
+            false => DEFAULT_DIRECTION,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_direction_falls_back_to_the_default() {
+        let mut light : Light = serde_json::from_value(serde_json::json!({ "direction": { "x": 0.0, "y": 0.0, "z": 0.0 } })).unwrap();
+        assert_eq!(light.unit_direction(), DEFAULT_DIRECTION);
+
+        light.direction = cgmath::Vector3::new(0.0, -2.0, 0.0);
+        assert_eq!(light.unit_direction(), cgmath::Vector3::new(0.0, -1.0, 0.0));
+    }
+}
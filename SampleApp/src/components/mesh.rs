@@ -1,6 +1,37 @@
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+use crate::graphics::{BlendMode, ColorSpace, TextureFilter};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Mesh {
     pub mesh_name: String,
     pub shader_name: String,
     pub diffuse_texture: String,
+    #[serde(default)]
+    pub diffuse_color_space: ColorSpace,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    #[serde(default)]
+    pub filter: TextureFilter,
+    // Sub rectangle of the texture mapped to the 0..1 texture coordinates of the mesh, so several sprites can share an atlas
+    #[serde(default)]
+    pub uv_offset: [f32; 2],
+    #[serde(default = "default_uv_scale")]
+    pub uv_scale: [f32; 2],
+    // Multiplied with the texture color. Lowering the alpha fades meshes drawn with alpha blending.
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    // Meshes with a lower order are drawn first, so a background at the same depth stays behind the sprites in front of it
+    #[serde(default)]
+    pub order: i32,
+    // Meshes are sorted by layer before their order, so the order only ranks the meshes of a layer. Lower layers are drawn
+    // first, like a background layer at -1 behind the default layer 0.
+    #[serde(default)]
+    pub layer: i32,
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_uv_scale() -> [f32; 2] {
+    [1.0, 1.0]
 }
@@ -0,0 +1,7 @@
+use crate::entity::EntityId;
+
+// Links an entity to the entity it belongs to, like the children of a composite prefab. Children are destroyed with their parent.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Parent {
+    pub entity: EntityId,
+}
@@ -0,0 +1,29 @@
+use cgmath::Zero;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct RigidBody {
+    #[serde(default = "zero")]
+    pub velocity: cgmath::Vector3<f32>,
+    // Degrees per second around the z axis
+    #[serde(default)]
+    pub angular_velocity: f32,
+    // Accumulated during a step by systems such as the control system and cleared by the physics system after integrating it
+    #[serde(default = "zero")]
+    pub acceleration: cgmath::Vector3<f32>,
+    // Fraction of the velocity kept after one second, whatever the length of the steps
+    #[serde(default = "default_damping")]
+    pub damping: f32,
+    #[serde(default = "zero")]
+    pub gravity: cgmath::Vector3<f32>,
+    // Unbounded when absent. Saved worlds can't hold an infinite speed since JSON has no infinity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed: Option<f32>,
+}
+
+fn zero() -> cgmath::Vector3<f32> {
+    cgmath::Vector3::zero()
+}
+
+fn default_damping() -> f32 {
+    1.0
+}
@@ -0,0 +1,242 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+// Most bones a skinned mesh can use. Matches the size of the palette in skinned.vert.
+pub const MAX_BONES: usize = 64;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BoneTransform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl BoneTransform {
+    pub fn matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)*cgmath::Matrix4::from(self.rotation)*cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Bone {
+    pub name: String,
+    // Index of the parent in the bones of the skeleton
+    pub parent: Option<usize>,
+    // Transform relative to the parent when no animation moves the bone
+    pub rest: BoneTransform,
+    // Brings the vertices from the space of the mesh to the space of the bone
+    pub inverse_bind_matrix: cgmath::Matrix4<f32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub enum TrackValues {
+    Translation(Vec<cgmath::Vector3<f32>>),
+    Rotation(Vec<cgmath::Quaternion<f32>>),
+    Scale(Vec<cgmath::Vector3<f32>>),
+}
+
+// Keyframes of one property of a bone. Values are interpolated linearly between the times, which are in increasing order.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Track {
+    pub bone: usize,
+    pub times: Vec<f32>,
+    pub values: TrackValues,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SkeletalClip {
+    pub name: String,
+    pub duration: f32,
+    pub tracks: Vec<Track>,
+}
+
+// Bone hierarchy deforming the skinned mesh of the entity, and the clips that animate it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Skeleton {
+    pub bones: Vec<Bone>,
+    #[serde(default)]
+    pub clips: Vec<SkeletalClip>,
+    // Index of the clip playing. The skeleton stays in its rest pose when None.
+    #[serde(default)]
+    pub clip: Option<usize>,
+    #[serde(default)]
+    pub looping: bool,
+    #[serde(default)]
+    pub time: f32,
+    // Matrix of every bone moving the vertices from their bind pose to the current pose, in the space of the mesh
+    #[serde(skip)]
+    pub palette: Vec<[[f32; 4]; 4]>,
+}
+
+impl Skeleton {
+    // Plays the clip from the start. Returns false if the skeleton has no clip with that name.
+    pub fn play(&mut self, clip_name: &str, looping: bool) -> bool {
+        match self.clips.iter().position(|clip| clip.name == clip_name) {
+            Some(clip) => {
+                self.clip = Some(clip);
+                self.looping = looping;
+                self.time = 0.0;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Advances the time, keeping it inside the duration of a loop. Non looping clips stop on their last pose.
+    pub fn advance(&mut self, delta_time: f32) {
+        let duration = match self.clip.and_then(|clip| self.clips.get(clip)) {
+            Some(clip) => clip.duration,
+            None => return,
+        };
+
+        self.time += delta_time;
+        if duration > 0.0 {
+            self.time = match self.looping {
+                true => self.time%duration,
+                false => self.time.min(duration),
+            };
+        }
+    }
+
+    // Transform of every bone relative to its parent at the current time
+    pub fn pose(&self) -> Vec<BoneTransform> {
+        let mut pose : Vec<BoneTransform> = self.bones.iter().map(|bone| bone.rest).collect();
+
+        if let Some(clip) = self.clip.and_then(|clip| self.clips.get(clip)) {
+            for track in &clip.tracks {
+                let bone = match pose.get_mut(track.bone) {
+                    Some(bone) => bone,
+                    None => continue,
+                };
+
+                // Tracks without keyframes leave the bone as it is
+                match &track.values {
+                    TrackValues::Translation(values) => if let Some(translation) = sample(&track.times, values, self.time, |a, b, t| a + (b - a)*t) {
+                        bone.translation = translation;
+                    },
+                    TrackValues::Rotation(values) => if let Some(rotation) = sample(&track.times, values, self.time, |a, b, t| a.slerp(b, t).normalize()) {
+                        bone.rotation = rotation;
+                    },
+                    TrackValues::Scale(values) => if let Some(scale) = sample(&track.times, values, self.time, |a, b, t| a + (b - a)*t) {
+                        bone.scale = scale;
+                    },
+                }
+            }
+        }
+
+        pose
+    }
+
+    // Recomputes the palette from the pose at the current time
+    pub fn update_palette(&mut self) {
+        let pose = self.pose();
+
+        // Parents may come after their children, so the global matrices are computed on demand
+        let mut globals : Vec<Option<cgmath::Matrix4<f32>>> = vec!(None; self.bones.len());
+        for bone in 0..self.bones.len() {
+            self.global_matrix(bone, &pose, &mut globals);
+        }
+
+        self.palette = globals.iter().zip(&self.bones)
+            .take(MAX_BONES)
+            .map(|(global, bone)| (global.unwrap()*bone.inverse_bind_matrix).into())
+            .collect();
+    }
+
+    fn global_matrix(&self, bone: usize, pose: &[BoneTransform], globals: &mut [Option<cgmath::Matrix4<f32>>]) -> cgmath::Matrix4<f32> {
+        if let Some(global) = globals[bone] {
+            return global;
+        }
+
+        // A bone that is its own ancestor is treated as a root
+        globals[bone] = Some(cgmath::Matrix4::identity());
+        let parent = match self.bones[bone].parent {
+            Some(parent) if parent < self.bones.len() => self.global_matrix(parent, pose, globals),
+            _ => cgmath::Matrix4::identity(),
+        };
+
+        let global = parent*pose[bone].matrix();
+        globals[bone] = Some(global);
+        global
+    }
+}
+
+// Value of the keyframes at the time, None if there are none. Times before the first keyframe or after the last one take its value.
+fn sample<T: Copy>(times: &[f32], values: &[T], time: f32, interpolate: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let count = times.len().min(values.len());
+    let next = times[..count].partition_point(|keyframe_time| *keyframe_time <= time);
+
+    match next {
+        _ if count == 0 => None,
+        0 => Some(values[0]),
+        _ if next == count => Some(values[count - 1]),
+        _ => {
+            let (start, end) = (times[next - 1], times[next]);
+            Some(interpolate(values[next - 1], values[next], (time - start)/(end - start)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::One;
+
+    fn bone(parent: Option<usize>, translation: cgmath::Vector3<f32>) -> Bone {
+        Bone {
+            name: String::new(),
+            parent,
+            rest: BoneTransform {
+                translation,
+                rotation: cgmath::Quaternion::one(),
+                scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            },
+            inverse_bind_matrix: cgmath::Matrix4::identity(),
+        }
+    }
+
+    #[test]
+    fn palette_of_bind_pose_is_identity() {
+        let mut skeleton = Skeleton {
+            bones: vec!(bone(Some(1), cgmath::Vector3::new(0.0, 2.0, 0.0)), bone(None, cgmath::Vector3::new(1.0, 0.0, 0.0))),
+            clips: Vec::new(),
+            clip: None,
+            looping: false,
+            time: 0.0,
+            palette: Vec::new(),
+        };
+        skeleton.bones[0].inverse_bind_matrix = cgmath::Matrix4::from_translation(cgmath::Vector3::new(-1.0, -2.0, 0.0));
+        skeleton.bones[1].inverse_bind_matrix = cgmath::Matrix4::from_translation(cgmath::Vector3::new(-1.0, 0.0, 0.0));
+
+        skeleton.update_palette();
+
+        let identity : [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+        assert_eq!(skeleton.palette, vec!(identity, identity));
+    }
+
+    #[test]
+    fn translation_track_is_interpolated() {
+        let mut skeleton = Skeleton {
+            bones: vec!(bone(None, cgmath::Vector3::new(0.0, 0.0, 0.0))),
+            clips: vec!(SkeletalClip {
+                name: "walk".to_owned(),
+                duration: 2.0,
+                tracks: vec!(Track {
+                    bone: 0,
+                    times: vec!(0.0, 2.0),
+                    values: TrackValues::Translation(vec!(cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(4.0, 0.0, 0.0))),
+                }),
+            }),
+            clip: None,
+            looping: false,
+            time: 0.0,
+            palette: Vec::new(),
+        };
+
+        assert!(skeleton.play("walk", false));
+        skeleton.advance(0.5);
+        assert_eq!(skeleton.pose()[0].translation, cgmath::Vector3::new(1.0, 0.0, 0.0));
+
+        skeleton.advance(5.0);
+        assert_eq!(skeleton.pose()[0].translation, cgmath::Vector3::new(4.0, 0.0, 0.0));
+    }
+}
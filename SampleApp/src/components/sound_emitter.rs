@@ -0,0 +1,5 @@
+// Plays the sound when the entity starts colliding with another entity
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SoundEmitter {
+    pub collision_sound: String,
+}
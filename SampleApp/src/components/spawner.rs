@@ -0,0 +1,8 @@
+// Creates an entity of the prefab every interval seconds at the position of the spawner's Transform
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Spawner {
+    pub prefab: String,
+    pub interval: f32,
+    #[serde(default)]
+    pub accumulator: f32,
+}
@@ -0,0 +1,66 @@
+// Flipbook animation. Replaces the diffuse texture of the entity's Mesh with the current frame.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SpriteAnimation {
+    // Texture names
+    pub frames: Vec<String>,
+    pub fps: f32,
+    #[serde(default)]
+    pub looping: bool,
+    #[serde(default)]
+    pub time: f32,
+}
+
+impl SpriteAnimation {
+    // None if there are no frames. Non looping animations stay on the last frame once they end.
+    pub fn current_frame(&self) -> Option<usize> {
+        if self.frames.is_empty() {
+            return None;
+        }
+
+        let frame = (self.time*self.fps).max(0.0) as usize;
+        match self.looping {
+            true => Some(frame%self.frames.len()),
+            false => Some(frame.min(self.frames.len() - 1)),
+        }
+    }
+
+    // Advances the time, keeping it inside the duration of a loop so it doesn't lose precision
+    pub fn advance(&mut self, delta_time: f32) {
+        self.time += delta_time;
+
+        if self.looping && self.fps > 0.0 {
+            let duration = self.frames.len() as f32/self.fps;
+            if duration > 0.0 {
+                self.time %= duration;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(looping: bool) -> SpriteAnimation {
+        SpriteAnimation {
+            frames: vec!("a.png".to_owned(), "b.png".to_owned(), "c.png".to_owned()),
+            fps: 10.0,
+            looping,
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn looping_animation_wraps_around() {
+        let mut animation = animation(true);
+        animation.advance(0.35);
+        assert_eq!(animation.current_frame(), Some(0));
+    }
+
+    #[test]
+    fn non_looping_animation_stops_on_last_frame() {
+        let mut animation = animation(false);
+        animation.advance(0.35);
+        assert_eq!(animation.current_frame(), Some(2));
+    }
+}
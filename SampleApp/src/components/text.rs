@@ -0,0 +1,18 @@
+// Text drawn with the built in monospace bitmap font. The first glyph's top left corner is at the entity's Transform position.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Text {
+    pub text: String,
+    // Width and height of each glyph in local units. The Transform scale is applied on top of it.
+    #[serde(default = "default_size")]
+    pub size: f32,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+}
+
+fn default_size() -> f32 {
+    16.0
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
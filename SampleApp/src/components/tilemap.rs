@@ -0,0 +1,122 @@
+use crate::graphics::{TextureFilter, Vertex};
+use std::ops::Range;
+
+// Grid of tiles from an atlas texture, drawn in a single draw call. The first row is at the top and the top left corner of
+// the map is at the origin of the entity. Tile i of the atlas is at column i%atlas_columns and row i/atlas_columns.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Tilemap {
+    pub texture: String,
+    #[serde(default)]
+    pub filter: TextureFilter,
+    pub atlas_columns: u32,
+    pub atlas_rows: u32,
+    // Number of tiles per row
+    pub width: u32,
+    pub tile_size: f32,
+    // Atlas index of every tile, row after row. None leaves the tile empty.
+    tiles: Vec<Option<u32>>,
+    // Set when the tiles change so the renderer rebuilds the geometry. New tilemaps always need it built.
+    #[serde(skip, default = "default_changed")]
+    changed: bool,
+}
+
+fn default_changed() -> bool {
+    true
+}
+
+impl Tilemap {
+    // Rows are full, tiles past the last full row are ignored
+    pub fn height(&self) -> u32 {
+        match self.width {
+            0 => 0,
+            width => self.tiles.len() as u32/width,
+        }
+    }
+
+    pub fn tile(&self, x: u32, y: u32) -> Option<u32> {
+        self.index(x, y).and_then(|index| self.tiles[index])
+    }
+
+    // Does nothing outside of the map
+    pub fn set_tile(&mut self, x: u32, y: u32, tile: Option<u32>) {
+        if let Some(index) = self.index(x, y) {
+            if self.tiles[index] != tile {
+                self.tiles[index] = tile;
+                self.changed = true;
+            }
+        }
+    }
+
+    // True if the geometry must be rebuilt since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    // Appends one quad per tile that isn't empty, in the space of the entity. Returns the range of indices of each row so the
+    // rows out of view can be skipped.
+    pub fn build_geometry(&self, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) -> Vec<Range<u32>> {
+        let (columns, rows) = (self.atlas_columns.max(1) as f32, self.atlas_rows.max(1) as f32);
+        let size = self.tile_size;
+
+        (0..self.height()).map(|y| {
+            let start = indices.len() as u32;
+            for x in 0..self.width {
+                let tile = match self.tile(x, y) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+
+                let (column, row) = ((tile%self.atlas_columns.max(1)) as f32, (tile/self.atlas_columns.max(1)) as f32);
+                let (uv_min, uv_max) = ([column/columns, row/rows], [(column + 1.0)/columns, (row + 1.0)/rows]);
+                let (left, top) = (x as f32*size, -(y as f32)*size);
+                let base_index = vertices.len() as u32;
+
+                vertices.push(Vertex::new([left, top, 0.0], [0.0, 0.0, 1.0], [uv_min[0], uv_min[1]]));
+                vertices.push(Vertex::new([left + size, top, 0.0], [0.0, 0.0, 1.0], [uv_max[0], uv_min[1]]));
+                vertices.push(Vertex::new([left, top - size, 0.0], [0.0, 0.0, 1.0], [uv_min[0], uv_max[1]]));
+                vertices.push(Vertex::new([left + size, top - size, 0.0], [0.0, 0.0, 1.0], [uv_max[0], uv_max[1]]));
+
+                // Same winding as the quad mesh
+                indices.extend([2, 1, 0, 1, 2, 3].iter().map(|index| base_index + index));
+            }
+
+            start..indices.len() as u32
+        })
+        .collect()
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        match x < self.width && y < self.height() {
+            true => Some((y*self.width + x) as usize),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_tiles_have_no_quad() {
+        let mut tilemap : Tilemap = serde_json::from_value(serde_json::json!({
+            "texture": "tiles.png",
+            "atlas_columns": 2,
+            "atlas_rows": 2,
+            "width": 2,
+            "tile_size": 1.0,
+            "tiles": [0, null, 3, 1]
+        })).unwrap();
+        assert!(tilemap.take_changed());
+
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        let rows = tilemap.build_geometry(&mut vertices, &mut indices);
+        assert_eq!(rows, vec!(0..6, 6..18));
+        assert_eq!(vertices.len(), 12);
+
+        tilemap.set_tile(1, 0, Some(2));
+        assert!(tilemap.take_changed());
+        tilemap.set_tile(5, 0, Some(2));
+        assert!(!tilemap.take_changed());
+    }
+}
@@ -1,12 +1,167 @@
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+use cgmath::{One, Zero};
+use std::cell::Cell;
+
+// Fields missing from a prefab take their identity value
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Transform {
     pub position: cgmath::Vector3<f32>,
     pub scale: cgmath::Vector3<f32>,
+    #[serde(deserialize_with = "deserialize_rotation")]
     pub rotation: cgmath::Quaternion<f32>,
+    // Position and rotation before the last fixed step, set by the physics system so rendering can interpolate between steps
+    #[serde(skip)]
+    previous: Option<(cgmath::Vector3<f32>, cgmath::Quaternion<f32>)>,
+    // Last model matrix built and the position, scale and rotation it was built from. The fields are modified directly by the
+    // systems, so comparing them tells whether the transform changed without every system having to flag it.
+    #[serde(skip)]
+    cached_matrix: Cell<Option<CachedMatrix>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CachedMatrix {
+    position: cgmath::Vector3<f32>,
+    scale: cgmath::Vector3<f32>,
+    rotation: cgmath::Quaternion<f32>,
+    matrix: cgmath::Matrix4<f32>,
+}
+
+// Prefabs can write the rotation as a quaternion or as { "euler": { "x": .., "y": .., "z": .. } } in degrees
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Rotation {
+    Quaternion(cgmath::Quaternion<f32>),
+    Euler { euler: cgmath::Vector3<f32> },
+}
+
+fn deserialize_rotation<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<cgmath::Quaternion<f32>, D::Error> {
+    use serde::Deserialize;
+
+    Ok(match Rotation::deserialize(deserializer)? {
+        Rotation::Quaternion(quaternion) => quaternion,
+        Rotation::Euler { euler } => cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(euler.x), cgmath::Deg(euler.y), cgmath::Deg(euler.z))),
+    })
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: cgmath::Vector3::zero(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            rotation: cgmath::Quaternion::one(),
+            previous: None,
+            cached_matrix: Cell::new(None),
+        }
+    }
 }
 
 impl Transform {
+    pub fn at(position: cgmath::Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_scale(mut self, scale: cgmath::Vector3<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: cgmath::Quaternion<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    // Rotation around the x, y and z axes, applied in that order
+    pub fn euler_angles(&self) -> (cgmath::Deg<f32>, cgmath::Deg<f32>, cgmath::Deg<f32>) {
+        let euler = cgmath::Euler::from(self.rotation);
+        (euler.x.into(), euler.y.into(), euler.z.into())
+    }
+
+    // Must be called before the transform is moved by a fixed step
+    pub fn store_previous(&mut self) {
+        self.previous = Some((self.position, self.rotation));
+    }
+
+    // Renders the current state without interpolation until the next fixed step, e.g. after teleporting the entity
+    pub fn reset_interpolation(&mut self) {
+        self.previous = None;
+    }
+
+    // Model matrix between the state before the last fixed step (alpha 0) and the current one (alpha 1)
+    pub fn interpolated_matrix(&self, alpha: f32) -> cgmath::Matrix4<f32> {
+        let (previous_position, previous_rotation) = match self.previous {
+            Some(previous) => previous,
+            None => return self.build_model_matrix(),
+        };
+
+        let position = previous_position + (self.position - previous_position)*alpha;
+        let rotation = previous_rotation.slerp(self.rotation, alpha);
+        model_matrix(position, rotation, self.scale)
+    }
+
+    // Only recomputed when the transform changed since the last call, static entities reuse their matrix every frame
     pub fn build_model_matrix(&self) -> cgmath::Matrix4<f32> {
-        cgmath::Matrix4::from_translation(self.position)*cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)*cgmath::Matrix4::from(self.rotation)
+        if let Some(cached) = self.cached_matrix.get() {
+            if cached.position == self.position && cached.scale == self.scale && cached.rotation == self.rotation {
+                return cached.matrix;
+            }
+        }
+
+        let matrix = model_matrix(self.position, self.rotation, self.scale);
+        self.cached_matrix.set(Some(CachedMatrix { position: self.position, scale: self.scale, rotation: self.rotation, matrix }));
+        matrix
+    }
+}
+
+fn model_matrix(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>, scale: cgmath::Vector3<f32>) -> cgmath::Matrix4<f32> {
+    cgmath::Matrix4::from_translation(position)*cgmath::Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)*cgmath::Matrix4::from(rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_take_identity_values() {
+        let transform : Transform = serde_json::from_value(serde_json::json!({ "position": { "x": 1.0, "y": 2.0, "z": 3.0 } })).unwrap();
+
+        assert_eq!(transform.position, cgmath::Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(transform.scale, cgmath::Vector3::new(1.0, 1.0, 1.0));
+        assert_eq!(transform.rotation, cgmath::Quaternion::one());
+    }
+
+    #[test]
+    fn rotation_can_be_written_in_euler_degrees() {
+        let transform : Transform = serde_json::from_value(serde_json::json!({ "rotation": { "euler": { "x": 0.0, "y": 0.0, "z": 90.0 } } })).unwrap();
+
+        let (x, y, z) = transform.euler_angles();
+        assert!(x.0.abs() < 1e-4 && y.0.abs() < 1e-4);
+        assert!((z.0 - 90.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn interpolated_matrix_lerps_position() {
+        let mut transform = Transform::at(cgmath::Vector3::new(0.0, 0.0, 0.0));
+        transform.store_previous();
+        transform.position = cgmath::Vector3::new(2.0, 0.0, 0.0);
+
+        let matrix = transform.interpolated_matrix(0.25);
+        assert_eq!(matrix.w.truncate(), cgmath::Vector3::new(0.5, 0.0, 0.0));
+
+        transform.reset_interpolation();
+        assert_eq!(transform.interpolated_matrix(0.25), transform.build_model_matrix());
+    }
+
+    #[test]
+    fn cached_matrix_follows_changes() {
+        let mut transform = Transform::at(cgmath::Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(transform.build_model_matrix().w.truncate(), cgmath::Vector3::new(1.0, 0.0, 0.0));
+
+        transform.position.y = 3.0;
+        transform.scale = cgmath::Vector3::new(2.0, 2.0, 2.0);
+        assert_eq!(transform.build_model_matrix().w.truncate(), cgmath::Vector3::new(1.0, 3.0, 0.0));
+        assert_eq!(transform.build_model_matrix().x.x, 2.0);
     }
 }
@@ -0,0 +1,66 @@
+use cgmath::{Angle, Zero};
+
+// Lighter alternative to Transform for sprites that stay in the xy plane. Entities use one or the other.
+// Fields missing from a prefab take their identity value.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Transform2D {
+    pub position: cgmath::Vector2<f32>,
+    // Counterclockwise around the z axis
+    pub rotation: cgmath::Rad<f32>,
+    pub scale: cgmath::Vector2<f32>,
+}
+
+impl Default for Transform2D {
+    fn default() -> Self {
+        Self {
+            position: cgmath::Vector2::zero(),
+            rotation: cgmath::Rad(0.0),
+            scale: cgmath::Vector2::new(1.0, 1.0),
+        }
+    }
+}
+
+impl Transform2D {
+    pub fn at(position: cgmath::Vector2<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    // Same as the matrix of a Transform at z 0 rotated around the z axis, written out instead of multiplying three matrices
+    pub fn build_model_matrix(&self) -> cgmath::Matrix4<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        cgmath::Matrix4::new(
+            self.scale.x*cos, self.scale.y*sin, 0.0, 0.0,
+            -self.scale.x*sin, self.scale.y*cos, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            self.position.x, self.position.y, 0.0, 1.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Transform;
+
+    #[test]
+    fn model_matrix_matches_transform() {
+        let transform_2d = Transform2D {
+            position: cgmath::Vector2::new(3.0, -2.0),
+            rotation: cgmath::Rad(0.7),
+            scale: cgmath::Vector2::new(2.0, 0.5),
+        };
+        let transform = Transform::at(cgmath::Vector3::new(3.0, -2.0, 0.0))
+            .with_scale(cgmath::Vector3::new(2.0, 0.5, 1.0))
+            .with_rotation(cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Rad(0.0), cgmath::Rad(0.0), cgmath::Rad(0.7))));
+
+        let expected : [[f32; 4]; 4] = transform.build_model_matrix().into();
+        let actual : [[f32; 4]; 4] = transform_2d.build_model_matrix().into();
+        for (expected, actual) in expected.iter().flatten().zip(actual.iter().flatten()) {
+            assert!((expected - actual).abs() < 1e-5, "{:?} != {:?}", expected, actual);
+        }
+    }
+}
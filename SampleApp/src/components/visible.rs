@@ -0,0 +1,4 @@
+// Hides the entity from the render system while false, without destroying it or its other components. Entities without it
+// are visible.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Visible(pub bool);
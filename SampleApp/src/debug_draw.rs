@@ -0,0 +1,131 @@
+use crate::graphics::{ModelProperties, FULL_UV_RECT};
+use crate::transform::Transform;
+use cgmath::{Vector3, Vector4};
+use std::time::{Duration, Instant};
+
+// Name under which the line pipeline, the unit line mesh and the white texture used by debug drawing are registered in Graphics
+pub const DEBUG_LINES: &str = "__debug_lines";
+// How long a message stays on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+// Text placed in pixels from the top left corner of the window instead of in the world
+pub struct ScreenText {
+    pub text: String,
+    pub position: [f32; 2],
+    // Width and height of each glyph in pixels
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+// Accumulates debug geometry during a frame. The RenderSystem draws it on top of every camera and clears it at the end of the frame.
+pub struct DebugDraw {
+    // Every line is an instance of the unit line mesh going from (0, 0, 0) to (1, 0, 0)
+    lines: Vec<ModelProperties>,
+    screen_texts: Vec<ScreenText>,
+    // Feedback of debug keys, such as the present mode, kept across frames until it expires
+    message: Option<(String, Instant)>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self {
+            lines: Vec::new(),
+            screen_texts: Vec::new(),
+            message: None,
+        }
+    }
+
+    pub fn draw_line(&mut self, start: Vector3<f32>, end: Vector3<f32>, color: [f32; 4]) {
+        // Maps the x axis of the unit line onto the segment. The other axes don't matter since the line has no width.
+        let direction = end - start;
+        let model_matrix = cgmath::Matrix4::from_cols(
+            direction.extend(0.0),
+            Vector4::unit_y(),
+            Vector4::unit_z(),
+            start.extend(1.0),
+        );
+
+        self.lines.push(ModelProperties {
+            model_matrix: model_matrix.into(),
+            color,
+            uv_rect: FULL_UV_RECT,
+        });
+    }
+
+    // Outline of the quad mesh placed with the transform, which matches the sprite drawn with the same transform
+    pub fn draw_box(&mut self, transform: &Transform, color: [f32; 4]) {
+        let model_matrix = transform.build_model_matrix();
+        let corners : Vec<Vector3<f32>> = [(-0.5, 0.5), (0.5, 0.5), (0.5, -0.5), (-0.5, -0.5)].iter()
+            .map(|(x, y)| (model_matrix*Vector4::new(*x, *y, 0.0, 1.0)).truncate())
+            .collect();
+
+        for (i, corner) in corners.iter().enumerate() {
+            self.draw_line(*corner, corners[(i + 1)%corners.len()], color);
+        }
+    }
+
+    // Drawn over every camera, e.g. for diagnostics
+    pub fn draw_screen_text(&mut self, text: &str, position: [f32; 2], size: f32, color: [f32; 4]) {
+        self.screen_texts.push(ScreenText {
+            text: text.to_owned(),
+            position,
+            size,
+            color,
+        });
+    }
+
+    // Replaces the current message. The DebugOverlaySystem draws it for MESSAGE_DURATION, even while the overlay is hidden.
+    pub fn show_message(&mut self, text: String) {
+        self.message = Some((text, Instant::now()));
+    }
+
+    // None once the message expired
+    pub fn message(&self) -> Option<&str> {
+        match &self.message {
+            Some((text, shown_at)) if shown_at.elapsed() < MESSAGE_DURATION => Some(text.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn lines(&self) -> &[ModelProperties] {
+        &self.lines
+    }
+
+    pub fn screen_texts(&self) -> &[ScreenText] {
+        &self.screen_texts
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.screen_texts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, Zero};
+
+    #[test]
+    fn line_maps_unit_line_onto_segment() {
+        let mut debug_draw = DebugDraw::new();
+        let start = Vector3::new(1.0, 2.0, 0.0);
+        let end = Vector3::new(4.0, -2.0, 0.0);
+        debug_draw.draw_line(start, end, [1.0, 0.0, 0.0, 1.0]);
+
+        let model_matrix : Matrix4<f32> = debug_draw.lines()[0].model_matrix.into();
+        assert_eq!((model_matrix*Vector4::new(0.0, 0.0, 0.0, 1.0)).truncate(), start);
+        assert_eq!((model_matrix*Vector4::new(1.0, 0.0, 0.0, 1.0)).truncate(), end);
+    }
+
+    #[test]
+    fn box_is_four_lines() {
+        let mut debug_draw = DebugDraw::new();
+        let transform = Transform::at(Vector3::zero()).with_scale(Vector3::new(2.0, 2.0, 1.0));
+        debug_draw.draw_box(&transform, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(debug_draw.lines().len(), 4);
+
+        debug_draw.clear();
+        assert!(debug_draw.lines().is_empty());
+    }
+}
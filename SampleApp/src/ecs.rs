@@ -4,39 +4,95 @@ use crate::transform::Transform;
 use crate::camera::Camera;
 use crate::controller::Controller;
 use crate::mesh::Mesh;
-use crate::resources::Resources;
+use crate::collider::Collider;
+use crate::rigid_body::RigidBody;
+use crate::text::Text;
+use crate::light::Light;
+use crate::parent::Parent;
+use crate::lifetime::Lifetime;
+use crate::spawner::Spawner;
+use crate::sprite_animation::SpriteAnimation;
+use crate::sound_emitter::SoundEmitter;
+use crate::camera_shake::CameraShake;
+use crate::skeleton::Skeleton;
+use crate::transform_2d::Transform2D;
+use crate::tilemap::Tilemap;
+use crate::visible::Visible;
+use crate::resources::{merge_json, Resources, Scene};
 
+use anyhow::{bail, Context, Result};
 use anymap::AnyMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::BTreeSet;
 use std::collections::HashSet;
 use std::collections::VecDeque;
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
 
 pub struct EntityComponentSystem {
     entity_allocator: EntityAllocator,
 
     components: AnyMap,
-    cameras: HashSet<EntityId>,
-    entities_to_create: VecDeque<String>,
+    cameras: BTreeSet<EntityId>,
+    // Prefab instance each entity was created from, so reloading the prefab creates the entity again where it was
+    entity_prefabs: HashMap<EntityId, PrefabInstance>,
+    // Components of the prefabs already spawned, so spawning them again doesn't parse their json
+    prefab_templates: HashMap<String, Rc<PrefabTemplate>>,
+    // Entities with a Parent component, by parent
+    children: HashMap<EntityId, Vec<EntityId>>,
+    events: AnyMap,
+    event_clearers: Vec<fn(&mut AnyMap)>,
+    entities_to_create: VecDeque<PrefabInstance>,
+    entities_to_load: VecDeque<(EntityId, PrefabTemplate)>,
     entities_to_destroy: VecDeque<EntityId>,
+    // Entities that had components attached or detached since the system manager last updated the systems
+    changed_entities: RefCell<HashSet<EntityId>>,
+    // Game state that belongs to no entity, like the score, one value per type
+    shared_resources: AnyMap,
     resources: Resources,
 }
 
 impl EntityComponentSystem {
-    pub fn new(max_entities: usize, resources: Resources) -> Self {
-        let entity_allocator = EntityAllocator::new(max_entities);
+    // Storage for initial_capacity entities is allocated up front. It grows if more entities are created.
+    pub fn new(initial_capacity: usize, resources: Resources) -> Self {
+        let entity_allocator = EntityAllocator::new(initial_capacity);
 
         let mut components = AnyMap::new();
-        components.insert(RefCell::new(ComponentSet::<Transform>::new(max_entities)));
-        components.insert(RefCell::new(ComponentSet::<Controller>::new(max_entities)));
-        components.insert(RefCell::new(ComponentSet::<Camera>::new(max_entities)));
-        components.insert(RefCell::new(ComponentSet::<Mesh>::new(max_entities)));
+        // Components most entities have use dense sets. The ones only a few entities have use sparse sets so they don't allocate a slot per entity.
+        components.insert(RefCell::new(ComponentSet::<Transform>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Controller>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Camera>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Mesh>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Collider>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<RigidBody>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Text>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Light>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Parent>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Lifetime>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Spawner>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<SpriteAnimation>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<SoundEmitter>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<CameraShake>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Skeleton>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Transform2D>::new(initial_capacity)));
+        components.insert(RefCell::new(ComponentSet::<Tilemap>::new_sparse()));
+        components.insert(RefCell::new(ComponentSet::<Visible>::new_sparse()));
 
         Self {
             entity_allocator,
             components,
-            cameras: HashSet::new(),
+            cameras: BTreeSet::new(),
+            entity_prefabs: HashMap::new(),
+            prefab_templates: HashMap::new(),
+            children: HashMap::new(),
+            events: AnyMap::new(),
+            event_clearers: Vec::new(),
             entities_to_create: VecDeque::new(),
+            entities_to_load: VecDeque::new(),
             entities_to_destroy: VecDeque::new(),
+            changed_entities: RefCell::new(HashSet::new()),
+            shared_resources: AnyMap::new(),
             resources,
         }
     }
@@ -46,19 +102,99 @@ impl EntityComponentSystem {
     }
 
     pub fn add_entity(&mut self, prefab: String) {
-        self.entities_to_create.push_back(prefab)
+        self.entities_to_create.push_back(PrefabInstance { prefab, transform: None, parent: None })
     }
 
-    pub fn remove_entity(&mut self, entity: EntityId) {
-        self.entities_to_destroy.push_back(entity)
+    // Like add_entity, but the created entity is moved to the position
+    pub fn add_entity_at(&mut self, prefab: String, position: cgmath::Vector3<f32>) {
+        let transform = Some(serde_json::json!({ "position": position }));
+        self.entities_to_create.push_back(PrefabInstance { prefab, transform, parent: None })
+    }
+
+    // Queues the creation of every entity of the scene. The transform of each instance is merged over the one of its prefab.
+    pub fn load_scene(&mut self, scene: &Scene) {
+        for instance in &scene.entities {
+            self.entities_to_create.push_back(PrefabInstance { prefab: instance.prefab.clone(), transform: instance.transform.clone(), parent: None });
+        }
+    }
+
+    // Queues the entity for destruction. Returns false and does nothing if the entity is dead or already queued.
+    pub fn remove_entity(&mut self, entity: EntityId) -> bool {
+        if !self.is_alive(&entity) || self.entities_to_destroy.contains(&entity) {
+            return false;
+        }
+
+        self.entities_to_destroy.push_back(entity);
+        true
+    }
+
+    // Queues the destruction of the entities created from the prefab and the creation of as many new ones, so a reloaded prefab
+    // takes effect. The new entities keep the transform override and the parent of the ones they replace. Returns the number
+    // of entities recreated.
+    pub fn recreate_prefab_entities(&mut self, prefab: &str) -> usize {
+        self.prefab_templates.remove(prefab);
+        let instances : Vec<(EntityId, PrefabInstance)> = self.entity_prefabs.iter()
+            .filter(|(_, instance)| instance.prefab == prefab)
+            .map(|(entity, instance)| (*entity, instance.clone()))
+            .collect();
+
+        let mut recreated = 0;
+        for (entity, instance) in instances {
+            if self.remove_entity(entity) {
+                self.entities_to_create.push_back(instance);
+                recreated += 1;
+            }
+        }
+
+        recreated
+    }
+
+    pub fn is_alive(&self, entity: &EntityId) -> bool {
+        self.entity_allocator.is_alive(entity)
+    }
+
+    // Entities queued by add_entity are counted once created
+    pub fn entity_count(&self) -> usize {
+        self.entity_allocator.active_entities.len()
+    }
+
+    // Zero for component types that aren't registered
+    pub fn component_count<T: 'static>(&self) -> usize {
+        self.get_component_set::<T>().map_or(0, |components| components.borrow().count())
+    }
+
+    // Pools count entities of the prefab ahead of a burst of spawns, like bullets. The prefab is loaded and parsed now and the
+    // entity slots are allocated, so spawning reuses them instead of growing the allocator. Destroyed entities return their
+    // slot to the pool.
+    pub fn reserve_prefab(&mut self, prefab: &str, count: usize) {
+        self.prefab_template(prefab);
+        self.entity_allocator.reserve(count);
+    }
+
+    pub fn allocator_stats(&self) -> AllocatorStats {
+        self.entity_allocator.stats()
     }
 
     // Must be called by system manager only so it can add the new entities to their corresponding systems. TODO: Figure out a better way
     pub fn create_entities(&mut self) -> Vec<EntityId> {
         let mut new_entities = Vec::new();
         while !self.entities_to_create.is_empty() {
-            let prefab = self.entities_to_create.pop_front().unwrap();
-            new_entities.push(self.create_entity(&prefab));
+            let instance = self.entities_to_create.pop_front().unwrap();
+            // A recreated child whose parent is destroyed too comes back with the new parent instead
+            if let Some(parent) = instance.parent {
+                if !self.is_alive(&parent) || self.entities_to_destroy.contains(&parent) {
+                    continue;
+                }
+            }
+
+            self.create_entity(instance, &mut Vec::new(), &mut new_entities);
+        }
+
+        while !self.entities_to_load.is_empty() {
+            let (entity, template) = self.entities_to_load.pop_front().unwrap();
+            self.entity_allocator.allocate_at(entity);
+            self.add_components(&entity, &template);
+            new_entities.push(entity);
         }
 
         new_entities
@@ -69,6 +205,14 @@ impl EntityComponentSystem {
         let mut destroyed_entities = Vec::new();
         while !self.entities_to_destroy.is_empty() {
             let entity = self.entities_to_destroy.pop_front().unwrap();
+
+            // Children are queued behind the parent so they are destroyed in this same call
+            if let Some(children) = self.children.remove(&entity) {
+                for child in children {
+                    self.remove_entity(child);
+                }
+            }
+
             self.destroy_entity(&entity);
             destroyed_entities.push(entity);
         } 
@@ -76,6 +220,44 @@ impl EntityComponentSystem {
         destroyed_entities
     }
 
+    // Adds or replaces a component of a live entity. The systems start processing the entity from the next frame.
+    // Panics if the component set of T is borrowed, like when a system attaches a component of a type it is iterating.
+    pub fn attach_component<T: 'static>(&self, entity: &EntityId, component: T) {
+        if !self.is_alive(entity) {
+            return;
+        }
+
+        self.add_component(entity, component);
+        self.changed_entities.borrow_mut().insert(*entity);
+    }
+
+    // Removes a component of a live entity. The systems stop processing the entity from the next frame.
+    pub fn detach_component<T: 'static>(&self, entity: &EntityId) {
+        if !self.has_component::<T>(entity) {
+            return;
+        }
+
+        self.clear_component::<T>(entity);
+        self.changed_entities.borrow_mut().insert(*entity);
+    }
+
+    // Must be called by system manager only so it can update the systems of the entities. TODO: Figure out a better way
+    pub fn take_changed_entities(&mut self) -> Vec<EntityId> {
+        let entity_allocator = &self.entity_allocator;
+        let changed_entities : Vec<EntityId> = self.changed_entities.get_mut().drain()
+            .filter(|entity| entity_allocator.is_alive(entity))
+            .collect();
+
+        for entity in &changed_entities {
+            match self.has_component::<Camera>(entity) {
+                true => { self.cameras.insert(*entity); },
+                false => { self.cameras.remove(entity); },
+            }
+        }
+
+        changed_entities
+    }
+
     pub fn has_component<T: 'static>(&self, entity: &EntityId) -> bool {
         match self.get_component_set::<T>() {
             Some(set) => !set.borrow().get(&entity).is_none(),
@@ -83,56 +265,270 @@ impl EntityComponentSystem {
         }
     }
 
-    pub fn cameras(&self) -> &HashSet<EntityId> {
+    // Prefabs are loaded through the resources so they can be unloaded with the level that used them
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    // Replaces the resource of the same type if there is one
+    pub fn insert_resource<R: 'static>(&mut self, resource: R) {
+        self.shared_resources.insert(RefCell::new(resource));
+    }
+
+    pub fn remove_resource<R: 'static>(&mut self) -> Option<R> {
+        self.shared_resources.remove::<RefCell<R>>().map(RefCell::into_inner)
+    }
+
+    // Resources are borrowed like component sets, so a system can hold a resource while it iterates components.
+    // Panics if the resource is already mutably borrowed.
+    pub fn resource<R: 'static>(&self) -> Option<Ref<R>> {
+        self.shared_resources.get::<RefCell<R>>().map(RefCell::borrow)
+    }
+
+    // Panics if the resource is already borrowed
+    pub fn resource_mut<R: 'static>(&self) -> Option<RefMut<R>> {
+        self.shared_resources.get::<RefCell<R>>().map(RefCell::borrow_mut)
+    }
+
+    // Forgets which components changed, so ComponentSet::changed reports the changes made since the start of the frame
+    pub fn clear_changed_components(&self) {
+        self.clear_changed::<Transform>();
+        self.clear_changed::<Camera>();
+        self.clear_changed::<Controller>();
+        self.clear_changed::<Mesh>();
+        self.clear_changed::<Collider>();
+        self.clear_changed::<RigidBody>();
+        self.clear_changed::<Text>();
+        self.clear_changed::<Light>();
+        self.clear_changed::<Parent>();
+        self.clear_changed::<Lifetime>();
+        self.clear_changed::<Spawner>();
+        self.clear_changed::<SpriteAnimation>();
+        self.clear_changed::<SoundEmitter>();
+        self.clear_changed::<CameraShake>();
+        self.clear_changed::<Skeleton>();
+        self.clear_changed::<Transform2D>();
+        self.clear_changed::<Tilemap>();
+        self.clear_changed::<Visible>();
+    }
+
+    pub fn cameras(&self) -> &BTreeSet<EntityId> {
         &self.cameras
     }
 
-    fn create_entity(&mut self, prefab: &String) -> EntityId {
-        let json = &self.resources.prefabs[prefab];
-        match json {
-            serde_json::Value::Object(object) => {
-                let entity = self.entity_allocator.allocate();
-
-                for key in object.keys() {
-                    match key.as_ref() {
-                        "Transform" => {
-                            let component : Transform = serde_json::from_str(&object["Transform"].to_string()).unwrap();
-                            self.add_component(&entity, component);
-                        }
-                        "Camera" => {
-                            let component : Camera = serde_json::from_str(&object["Camera"].to_string()).unwrap();
-                            self.add_component(&entity, component);
-                        }
-                        "Controller" => {
-                            let component : Controller = serde_json::from_str(&object["Controller"].to_string()).unwrap();
-                            self.add_component(&entity, component);
-                        }
-                        "Mesh" => {
-                            let component : Mesh = serde_json::from_str(&object["Mesh"].to_string()).unwrap();
-                            self.add_component(&entity, component);
-                        }
-                        _ => {}
-                    };
-                }
+    // Events live for one frame. Events sent during a frame can be read by any system until the system manager
+    // clears them at the beginning of the next frame, so a system running earlier in the frame than the sender sees them on the next one.
+    pub fn send_event<E: 'static>(&mut self, event: E) {
+        if !self.events.contains::<Vec<E>>() {
+            self.events.insert(Vec::<E>::new());
+            self.event_clearers.push(clear_events::<E>);
+        }
 
-                if self.has_component::<Camera>(&entity) {
-                    self.cameras.insert(entity);
-                }
+        self.events.get_mut::<Vec<E>>().unwrap().push(event);
+    }
+
+    pub fn events<E: 'static>(&self) -> &[E] {
+        match self.events.get::<Vec<E>>() {
+            Some(events) => events,
+            None => &[]
+        }
+    }
+
+    // Removes the events so no other system will see them this frame
+    pub fn drain_events<E: 'static>(&mut self) -> Vec<E> {
+        match self.events.get_mut::<Vec<E>>() {
+            Some(events) => std::mem::take(events),
+            None => Vec::new()
+        }
+    }
+
+    // Must be called by system manager only at the beginning of each frame.
+    pub fn clear_events(&mut self) {
+        for clear in &self.event_clearers {
+            clear(&mut self.events);
+        }
+    }
+
+    // Serializes every active entity. Each entity is stored with the same shape as a prefab plus its "EntityId".
+    pub fn save_to_json(&self) -> serde_json::Value {
+        let mut entities : Vec<&EntityId> = self.entity_allocator.active_entities.iter().collect();
+        entities.sort_by_key(|entity| entity.index);
+
+        let mut saved_entities = Vec::new();
+        for entity in entities {
+            let mut object = serde_json::Map::new();
+            object.insert("EntityId".to_owned(), serde_json::to_value(entity).unwrap());
+            self.save_component::<Transform>(entity, "Transform", &mut object);
+            self.save_component::<Camera>(entity, "Camera", &mut object);
+            self.save_component::<Controller>(entity, "Controller", &mut object);
+            self.save_component::<Mesh>(entity, "Mesh", &mut object);
+            self.save_component::<Collider>(entity, "Collider", &mut object);
+            self.save_component::<RigidBody>(entity, "RigidBody", &mut object);
+            self.save_component::<Text>(entity, "Text", &mut object);
+            self.save_component::<Light>(entity, "Light", &mut object);
+            self.save_component::<Parent>(entity, "Parent", &mut object);
+            self.save_component::<Lifetime>(entity, "Lifetime", &mut object);
+            self.save_component::<Spawner>(entity, "Spawner", &mut object);
+            self.save_component::<SpriteAnimation>(entity, "SpriteAnimation", &mut object);
+            self.save_component::<SoundEmitter>(entity, "SoundEmitter", &mut object);
+            self.save_component::<CameraShake>(entity, "CameraShake", &mut object);
+            self.save_component::<Skeleton>(entity, "Skeleton", &mut object);
+            self.save_component::<Transform2D>(entity, "Transform2D", &mut object);
+            self.save_component::<Tilemap>(entity, "Tilemap", &mut object);
+            self.save_component::<Visible>(entity, "Visible", &mut object);
+            saved_entities.push(serde_json::Value::Object(object));
+        }
+
+        serde_json::json!({ "entities": saved_entities })
+    }
 
-                entity
+    // Replaces the current world with a saved one. Like add_entity and remove_entity, the change is applied by the system manager on the next frame.
+    // The whole save is parsed first, so the current world is kept if the save is invalid.
+    pub fn load_from_json(&mut self, json: &serde_json::Value) -> Result<()> {
+        let saved_entities = match json["entities"].as_array() {
+            Some(saved_entities) => saved_entities,
+            None => bail!("Failed to load the world. Expected an array of entities."),
+        };
+
+        let mut entities_to_load = VecDeque::new();
+        let mut indices = HashSet::new();
+        for saved_entity in saved_entities {
+            let entity = EntityId::deserialize(&saved_entity["EntityId"]).context("Failed to load the world. Invalid EntityId.")?;
+            if !indices.insert(entity.index) {
+                bail!("Failed to load the world. The entity index {} is saved more than once.", entity.index);
             }
-            _ => { 
-                panic!("Failed to create an entity from json file. Expected a json object."); 
+
+            let template = PrefabTemplate::from_json(saved_entity).with_context(|| format!("Failed to load the entity {}.", entity.index))?;
+            entities_to_load.push_back((entity, template));
+        }
+
+        let active_entities : Vec<EntityId> = self.entity_allocator.active_entities.iter().copied().collect();
+        for entity in active_entities {
+            self.remove_entity(entity);
+        }
+
+        self.entities_to_load = entities_to_load;
+        Ok(())
+    }
+
+    // Creates the entity of the prefab and, recursively, the entities of the prefabs listed in its "children" array.
+    // prefab_stack holds the prefabs being created to detect prefabs that contain themselves.
+    // The transform override is merged over the Transform of the prefab, which is added if the prefab has none. An override
+    // that doesn't make a valid Transform is reported and the one of the prefab is kept.
+    fn create_entity(&mut self, instance: PrefabInstance, prefab_stack: &mut Vec<String>, new_entities: &mut Vec<EntityId>) -> EntityId {
+        let entity = self.entity_allocator.allocate();
+        let prefab = instance.prefab.as_str();
+        let template = self.prefab_template(prefab);
+        self.add_components(&entity, &template);
+
+        // Only the Transform is parsed again, the other components are cloned from the template
+        if let Some(transform_override) = &instance.transform {
+            let mut transform = self.resources.prefab(prefab).unwrap()["Transform"].clone();
+            merge_json(&mut transform, transform_override.clone());
+            match serde_json::from_value(transform) {
+                Ok(transform) => self.add_component::<Transform>(&entity, transform),
+                Err(error) => eprintln!("The transform override {} of the prefab {} is invalid. Keeping the prefab transform. {}", transform_override, prefab, error),
+            }
+        }
+        if let Some(parent) = instance.parent {
+            self.set_parent(&entity, parent);
+        }
+        new_entities.push(entity);
+
+        prefab_stack.push(prefab.to_owned());
+        for child_prefab in &template.children {
+            if prefab_stack.contains(child_prefab) {
+                eprintln!("The prefab {} contains itself through {}. Skipping the child.", child_prefab, prefab);
+                continue;
+            }
+
+            let child = PrefabInstance { prefab: child_prefab.clone(), transform: None, parent: Some(entity) };
+            self.create_entity(child, prefab_stack, new_entities);
+        }
+        prefab_stack.pop();
+
+        self.entity_prefabs.insert(entity, instance);
+        entity
+    }
+
+    // Loads the prefab if needed and parses it the first time it is used
+    fn prefab_template(&mut self, prefab: &str) -> Rc<PrefabTemplate> {
+        if self.resources.assets.find::<serde_json::Value>(prefab).is_none() {
+            self.resources.load_prefab(prefab);
+            // The file may have changed since the prefab was unloaded
+            self.prefab_templates.remove(prefab);
+        }
+
+        if let Some(template) = self.prefab_templates.get(prefab) {
+            return template.clone();
+        }
+
+        let json = self.resources.prefab(prefab).unwrap_or_else(|| panic!("The prefab {} couldn't be loaded", prefab));
+        let mut template = PrefabTemplate::from_json(json).unwrap_or_else(|error| panic!("The prefab {} is invalid. {:?}", prefab, error));
+        for child_prefab in json["children"].as_array().into_iter().flatten() {
+            match child_prefab.as_str() {
+                Some(child_prefab) => template.children.push(child_prefab.to_owned()),
+                None => eprintln!("The children of the prefab {} must be prefab names. Skipping {}", prefab, child_prefab),
             }
         }
+
+        let template = Rc::new(template);
+        self.prefab_templates.insert(prefab.to_owned(), template.clone());
+        template
+    }
+
+    fn set_parent(&mut self, child: &EntityId, parent: EntityId) {
+        self.add_component(child, Parent { entity: parent });
+        self.children.entry(parent).or_default().push(*child);
+    }
+
+    fn add_components(&mut self, entity: &EntityId, template: &PrefabTemplate) {
+        for add_component in &template.components {
+            add_component(self, entity);
+        }
+
+        if let Some(parent) = template.parent {
+            self.set_parent(entity, parent);
+        }
+
+        if self.has_component::<Camera>(&entity) {
+            self.cameras.insert(*entity);
+        }
+    }
+
+    fn save_component<T: 'static + serde::Serialize>(&self, entity: &EntityId, name: &str, object: &mut serde_json::Map<String, serde_json::Value>) {
+        if let Some(component) = self.get_component_set::<T>().unwrap().borrow().get(entity) {
+            object.insert(name.to_owned(), serde_json::to_value(component).unwrap());
+        }
     }
 
     fn destroy_entity(&mut self, entity: &EntityId) {
         self.entity_allocator.deallocate(entity);
+        self.cameras.remove(entity);
+        self.entity_prefabs.remove(entity);
+
+        let parent = self.get_component_set::<Parent>().unwrap().borrow().get(entity).map(|parent| parent.entity);
+        if let Some(siblings) = parent.and_then(|parent| self.children.get_mut(&parent)) {
+            siblings.retain(|sibling| sibling != entity);
+        }
         self.clear_component::<Transform>(entity);
         self.clear_component::<Camera>(entity);
         self.clear_component::<Controller>(entity);
         self.clear_component::<Mesh>(entity);
+        self.clear_component::<Collider>(entity);
+        self.clear_component::<RigidBody>(entity);
+        self.clear_component::<Text>(entity);
+        self.clear_component::<Light>(entity);
+        self.clear_component::<Parent>(entity);
+        self.clear_component::<Lifetime>(entity);
+        self.clear_component::<Spawner>(entity);
+        self.clear_component::<SpriteAnimation>(entity);
+        self.clear_component::<SoundEmitter>(entity);
+        self.clear_component::<CameraShake>(entity);
+        self.clear_component::<Skeleton>(entity);
+        self.clear_component::<Transform2D>(entity);
+        self.clear_component::<Tilemap>(entity);
+        self.clear_component::<Visible>(entity);
     }
 
     fn add_component<T: 'static>(&self, entityId: &EntityId, component: T) {
@@ -142,4 +538,280 @@ impl EntityComponentSystem {
     fn clear_component<T: 'static>(&self, entityId: &EntityId) {
         self.get_component_set::<T>().unwrap().borrow_mut().set(&entityId, None)
     }
-}
\ No newline at end of file
+
+    fn clear_changed<T: 'static>(&self) {
+        self.get_component_set::<T>().unwrap().borrow_mut().clear_changed()
+    }
+}
+
+// Entity to create from a prefab
+#[derive(Clone)]
+struct PrefabInstance {
+    prefab: String,
+    // Transform fields that override the ones of the prefab
+    transform: Option<serde_json::Value>,
+    parent: Option<EntityId>,
+}
+
+// Components of a prefab or saved entity, deserialized once and cloned into every entity created from it
+struct PrefabTemplate {
+    components: Vec<Box<dyn Fn(&EntityComponentSystem, &EntityId)>>,
+    parent: Option<EntityId>,
+    // Prefabs of the child entities
+    children: Vec<String>,
+}
+
+impl PrefabTemplate {
+    fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let object = match json {
+            serde_json::Value::Object(object) => object,
+            _ => bail!("Failed to create an entity from json file. Expected a json object."),
+        };
+
+        let mut template = PrefabTemplate {
+            components: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+        };
+
+        for (key, value) in object {
+            match key.as_ref() {
+                "Transform" => template.add::<Transform>(value),
+                "Camera" => template.add::<Camera>(value),
+                "Controller" => template.add::<Controller>(value),
+                "Mesh" => template.add::<Mesh>(value),
+                "Collider" => template.add::<Collider>(value),
+                "RigidBody" => template.add::<RigidBody>(value),
+                "Text" => template.add::<Text>(value),
+                "Light" => template.add::<Light>(value),
+                "Parent" => Parent::deserialize(value).map(|component| template.parent = Some(component.entity)).map_err(Into::into),
+                "Lifetime" => template.add::<Lifetime>(value),
+                "Spawner" => template.add::<Spawner>(value),
+                "SpriteAnimation" => template.add::<SpriteAnimation>(value),
+                "SoundEmitter" => template.add::<SoundEmitter>(value),
+                "CameraShake" => template.add::<CameraShake>(value),
+                "Skeleton" => template.add::<Skeleton>(value),
+                "Transform2D" => template.add::<Transform2D>(value),
+                "Tilemap" => template.add::<Tilemap>(value),
+                "Visible" => template.add::<Visible>(value),
+                _ => Ok(())
+            }.with_context(|| format!("Invalid {}", key))?;
+        }
+
+        Ok(template)
+    }
+
+    // Deserializes straight from the json value, without copying it or going through a string
+    fn add<T: 'static + Clone + serde::de::DeserializeOwned>(&mut self, json: &serde_json::Value) -> Result<()> {
+        let component = T::deserialize(json)?;
+        self.components.push(Box::new(move |ecs: &EntityComponentSystem, entity: &EntityId| ecs.add_component(entity, component.clone())));
+        Ok(())
+    }
+}
+
+fn clear_events<E: 'static>(events: &mut AnyMap) {
+    if let Some(events) = events.get_mut::<Vec<E>>() {
+        events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn read_shipped_prefabs() -> Vec<(String, serde_json::Value)> {
+        let directory : PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "resources", "prefabs"].iter().collect();
+        std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |extension| extension == "json"))
+            .map(|path| {
+                let contents = std::fs::read(&path).unwrap();
+                let prefab : serde_json::Value = serde_json::from_slice(&contents).unwrap_or_else(|error| panic!("Failed to parse {:?}: {}", path, error));
+                (path.file_name().unwrap().to_string_lossy().into_owned(), prefab)
+            })
+            .collect()
+    }
+
+    fn load_world(json: &serde_json::Value) -> EntityComponentSystem {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.load_from_json(json).unwrap();
+        ecs.create_entities();
+        ecs
+    }
+
+    // Components are read with PrefabTemplate::from_json like create_entity does, so a prefab that doesn't match its component panics here
+    #[test]
+    fn shipped_prefabs_survive_a_save_and_load() {
+        for (prefab_name, prefab) in read_shipped_prefabs() {
+            let mut entity = prefab.clone();
+            entity["EntityId"] = serde_json::json!({ "index": 0, "generation": 0 });
+
+            let saved = load_world(&serde_json::json!({ "entities": [entity] })).save_to_json();
+            for key in prefab.as_object().unwrap().keys().filter(|key| key.as_str() != "children") {
+                assert!(saved["entities"][0].get(key).is_some(), "{} of {} isn't a known component", key, prefab_name);
+            }
+
+            assert_eq!(load_world(&saved).save_to_json(), saved, "{} changed after a save and load", prefab_name);
+        }
+    }
+
+    #[test]
+    fn invalid_save_keeps_the_current_world() {
+        let saved = serde_json::json!({ "entities": [{ "EntityId": { "index": 0, "generation": 0 }, "Lifetime": { "remaining": 1.0 } }] });
+        let mut ecs = load_world(&saved);
+
+        assert!(ecs.load_from_json(&serde_json::json!({ "entities": 3 })).is_err());
+        assert!(ecs.load_from_json(&serde_json::json!({ "entities": [{ "EntityId": "zero" }] })).is_err());
+        assert!(ecs.load_from_json(&serde_json::json!({ "entities": [{ "EntityId": { "index": 1, "generation": 0 }, "Lifetime": { "remaining": "soon" } }] })).is_err());
+
+        let duplicate = serde_json::json!({ "EntityId": { "index": 1, "generation": 0 } });
+        assert!(ecs.load_from_json(&serde_json::json!({ "entities": [duplicate, duplicate] })).is_err());
+
+        assert!(ecs.destroy_entities().is_empty());
+        assert_eq!(ecs.save_to_json(), saved);
+    }
+
+    #[test]
+    fn resources_are_stored_by_type() {
+        struct Score(u32);
+
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        assert!(ecs.resource::<Score>().is_none());
+
+        ecs.insert_resource(Score(1));
+        ecs.resource_mut::<Score>().unwrap().0 += 2;
+        assert_eq!(ecs.resource::<Score>().unwrap().0, 3);
+
+        assert_eq!(ecs.remove_resource::<Score>().map(|score| score.0), Some(3));
+        assert!(ecs.resource::<Score>().is_none());
+    }
+
+    #[test]
+    fn spawned_entities_get_fresh_copies_of_the_prefab_components() {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.resources_mut().assets.insert("bullet.json", serde_json::json!({
+            "Transform": { "position": { "x": 1.0, "y": 2.0, "z": 0.0 } },
+            "Lifetime": { "remaining": 2.0 }
+        }));
+        ecs.reserve_prefab("bullet.json", 2);
+
+        ecs.add_entity("bullet.json".to_owned());
+        let first = ecs.create_entities()[0];
+        ecs.get_component_set::<Lifetime>().unwrap().borrow_mut().get_mut(&first).unwrap().remaining = 0.5;
+
+        ecs.add_entity_at("bullet.json".to_owned(), cgmath::Vector3::new(5.0, 0.0, 0.0));
+        let second = ecs.create_entities()[0];
+        assert_eq!(ecs.allocator_stats().capacity, 2);
+
+        let lifetimes = ecs.get_component_set::<Lifetime>().unwrap().borrow();
+        assert_eq!(lifetimes.get(&first).unwrap().remaining, 0.5);
+        assert_eq!(lifetimes.get(&second).unwrap().remaining, 2.0);
+        let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+        assert_eq!(transforms.get(&second).unwrap().position, cgmath::Vector3::new(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn invalid_transform_override_keeps_the_prefab_transform() {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.resources_mut().assets.insert("ship.json", serde_json::json!({ "Transform": { "position": { "x": 1.0, "y": 2.0, "z": 0.0 } } }));
+        let scene = Scene { entities: vec![serde_json::from_value(serde_json::json!({ "prefab": "ship.json", "transform": { "position": "far" } })).unwrap()] };
+
+        ecs.load_scene(&scene);
+        let ship = ecs.create_entities()[0];
+
+        let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+        assert_eq!(transforms.get(&ship).unwrap().position, cgmath::Vector3::new(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn recreated_entities_keep_their_position_and_parent() {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.resources_mut().assets.insert("ship.json", serde_json::json!({ "Transform": {}, "children": ["gun.json"] }));
+        ecs.resources_mut().assets.insert("gun.json", serde_json::json!({ "Lifetime": { "remaining": 1.0 } }));
+        ecs.add_entity_at("ship.json".to_owned(), cgmath::Vector3::new(5.0, 0.0, 0.0));
+        ecs.create_entities();
+
+        let entity_of = |ecs: &EntityComponentSystem, prefab: &str| {
+            ecs.entity_prefabs.iter().find(|(_, instance)| instance.prefab == prefab).map(|(entity, _)| *entity).unwrap()
+        };
+        let parent_of = |ecs: &EntityComponentSystem, entity: EntityId| {
+            ecs.get_component_set::<Parent>().unwrap().borrow().get(&entity).map(|parent| parent.entity)
+        };
+
+        assert_eq!(ecs.recreate_prefab_entities("gun.json"), 1);
+        ecs.destroy_entities();
+        ecs.create_entities();
+        assert_eq!(parent_of(&ecs, entity_of(&ecs, "gun.json")), Some(entity_of(&ecs, "ship.json")));
+
+        // The gun is destroyed with the ship and comes back once, as the child of the new ship
+        ecs.recreate_prefab_entities("ship.json");
+        ecs.recreate_prefab_entities("gun.json");
+        ecs.destroy_entities();
+        ecs.create_entities();
+        assert_eq!(ecs.entity_count(), 2);
+        let ship = entity_of(&ecs, "ship.json");
+        assert_eq!(parent_of(&ecs, entity_of(&ecs, "gun.json")), Some(ship));
+        let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+        assert_eq!(transforms.get(&ship).unwrap().position, cgmath::Vector3::new(5.0, 0.0, 0.0));
+    }
+
+    const BULLETS: usize = 100;
+    const FRAMES: u32 = 3;
+
+    fn bullet_prefab() -> serde_json::Value {
+        serde_json::json!({
+            "Transform": { "position": { "x": 1.0, "y": 2.0, "z": 0.0 }, "scale": { "x": 0.1, "y": 0.1, "z": 0.1 } },
+            "RigidBody": { "velocity": { "x": 400.0, "y": 0.0, "z": 0.0 } },
+            "Collider": { "half_extents": { "x": 0.1, "y": 0.1, "z": 0.1 } },
+            "Mesh": { "mesh_name": "quad", "shader_name": "sprite", "diffuse_texture": "bullet.png" },
+            "Lifetime": { "remaining": 2.0 }
+        })
+    }
+
+    // Spawns BULLETS bullets one by one like a burst of shots, then destroys them all
+    fn spawn_bullets(ecs: &mut EntityComponentSystem) {
+        for _ in 0..BULLETS {
+            ecs.add_entity("bullet.json".to_owned());
+            ecs.create_entities();
+        }
+        assert_eq!(ecs.entity_count(), BULLETS);
+
+        for entity in ecs.entity_prefabs.keys().copied().collect::<Vec<_>>() {
+            ecs.remove_entity(entity);
+        }
+        ecs.destroy_entities();
+    }
+
+    #[test]
+    fn spawned_bullets_reuse_the_pooled_slots() {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.resources_mut().assets.insert("bullet.json", bullet_prefab());
+        ecs.reserve_prefab("bullet.json", BULLETS);
+        assert_eq!(ecs.allocator_stats().capacity, BULLETS);
+
+        // The destroyed bullets give their slots back, so the pool never grows
+        for _ in 0..FRAMES {
+            spawn_bullets(&mut ecs);
+            let stats = ecs.allocator_stats();
+            assert_eq!((stats.active, stats.free, stats.capacity), (0, BULLETS, BULLETS));
+        }
+    }
+
+    #[test]
+    fn spawns_clone_the_components_parsed_from_the_prefab_once() {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.resources_mut().assets.insert("bullet.json", bullet_prefab());
+        ecs.add_entity("bullet.json".to_owned());
+        ecs.create_entities();
+
+        // The spawn would panic if it parsed the prefab again
+        assert!(ecs.resources_mut().assets.reload("bullet.json", |_| Ok(serde_json::json!({ "Lifetime": { "remaining": "soon" } }))));
+        ecs.add_entity("bullet.json".to_owned());
+        let bullet = ecs.create_entities()[0];
+
+        assert_eq!(ecs.get_component_set::<Lifetime>().unwrap().borrow().get(&bullet).unwrap().remaining, 2.0);
+        assert_eq!(ecs.get_component_set::<Mesh>().unwrap().borrow().get(&bullet).unwrap().diffuse_texture, "bullet.png");
+    }
+}
+
@@ -1,11 +1,20 @@
 use std::collections::HashSet;
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+// Ordered by index, then generation, so the systems visit their entities in a deterministic order
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub struct EntityId {
     pub index: usize,
     pub generation: u64,
 }
 
+// Counts of the entity indices. Capacity is every index ever allocated, active or free.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AllocatorStats {
+    pub active: usize,
+    pub free: usize,
+    pub capacity: usize,
+}
+
 struct AllocatorEntry {
     generation: u64
 }
@@ -13,16 +22,15 @@ struct AllocatorEntry {
 pub struct EntityAllocator {
     entries: Vec<AllocatorEntry>,
     free: Vec<usize>,
-    pub max_size: usize,
     pub active_entities: HashSet<EntityId>,
 }
 
 impl EntityAllocator {
-    pub fn new(max_size: usize) -> Self {
+    // There is no limit to the number of entities. The initial capacity only avoids reallocations.
+    pub fn new(initial_capacity: usize) -> Self {
         EntityAllocator {
-            entries: Vec::new(),
+            entries: Vec::with_capacity(initial_capacity),
             free: Vec::new(),
-            max_size: max_size,
             active_entities: HashSet::new(),
         }
     }
@@ -40,6 +48,27 @@ impl EntityAllocator {
         gen_index
     }
 
+    // Allocates a specific generational index, e.g. when restoring a saved world. The index must not be in use.
+    pub fn allocate_at(&mut self, gen_index: EntityId) {
+        while self.entries.len() <= gen_index.index {
+            self.free.push(self.entries.len());
+            self.entries.push(AllocatorEntry {
+                generation: 0,
+            });
+        }
+
+        match self.free.iter().position(|index| *index == gen_index.index) {
+            Some(position) => { self.free.remove(position); },
+            None => panic!("System error. Attempt to allocate an index already in use: {}", gen_index.index),
+        }
+
+        self.entries[gen_index.index].generation = gen_index.generation;
+
+        if self.active_entities.insert(gen_index) == false {
+            panic!("System error. Allocated an already existing generational index.");
+        }
+    }
+
     pub fn deallocate(&mut self, gen_index: &EntityId) {
         if self.active_entities.remove(gen_index) == false {
             panic!("System error. Attempt to deallocate a non existing generational index.")
@@ -48,11 +77,32 @@ impl EntityAllocator {
         self.free.push(gen_index.index);
     }
 
-    fn add_new_entry(&mut self) -> EntityId {
-        if self.entries.len() >= self.max_size {
-            panic!("Out of memory. Exceeded the maximum allowed of indices {}", self.max_size);
+    // Adds free entries until at least count entities can be allocated without growing the allocator
+    pub fn reserve(&mut self, count: usize) {
+        while self.free.len() < count {
+            self.free.push(self.entries.len());
+            self.entries.push(AllocatorEntry {
+                generation: 0,
+            });
         }
-        
+    }
+
+    pub fn is_alive(&self, gen_index: &EntityId) -> bool {
+        match self.entries.get(gen_index.index) {
+            Some(entry) => entry.generation == gen_index.generation && self.active_entities.contains(gen_index),
+            None => false
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            active: self.active_entities.len(),
+            free: self.free.len(),
+            capacity: self.entries.len(),
+        }
+    }
+
+    fn add_new_entry(&mut self) -> EntityId {
         let gen_index = EntityId {
             index: self.entries.len(),
             generation: 0,
@@ -81,6 +131,7 @@ impl EntityAllocator {
 #[cfg(test)]
 mod tests {
     use super::EntityAllocator;
+    use super::AllocatorStats;
     use super::EntityId;
 
     #[test]
@@ -126,11 +177,69 @@ mod tests {
         assert_eq!(gen_index.generation, 1);
     }
 
+    #[test]
+    fn is_alive_is_false_after_dealloc() {
+        let mut allocator = EntityAllocator::new(5);
+        let gen_index = allocator.allocate();
+        assert!(allocator.is_alive(&gen_index));
+        allocator.deallocate(&gen_index);
+        assert!(!allocator.is_alive(&gen_index));
+        let reused_index = allocator.allocate();
+        assert!(!allocator.is_alive(&gen_index));
+        assert!(allocator.is_alive(&reused_index));
+    }
+
+    #[test]
+    fn allocate_at_restores_index_and_generation() {
+        let mut allocator = EntityAllocator::new(5);
+        allocator.allocate_at(EntityId {
+            index: 2,
+            generation: 3,
+        });
+
+        let gen_index = allocator.allocate();
+        assert_ne!(gen_index.index, 2);
+        assert!(allocator.active_entities.contains(&EntityId { index: 2, generation: 3 }));
+    }
+
     #[test]
     #[should_panic]
-    fn alloc_more_than_max_size_panics() {
+    fn allocate_at_active_index_panics() {
+        let mut allocator = EntityAllocator::new(5);
+        let gen_index = allocator.allocate();
+        allocator.allocate_at(gen_index);
+    }
+
+    #[test]
+    fn stats_count_active_and_free_indices() {
+        let mut allocator = EntityAllocator::new(5);
+        let gen_index = allocator.allocate();
+        allocator.allocate();
+        allocator.deallocate(&gen_index);
+
+        assert_eq!(allocator.stats(), AllocatorStats { active: 1, free: 1, capacity: 2 });
+    }
+
+    #[test]
+    fn alloc_more_than_initial_capacity_grows() {
         let mut allocator = EntityAllocator::new(1);
         allocator.allocate();
+        let gen_index = allocator.allocate();
+
+        assert_eq!(gen_index.index, 1);
+        assert!(allocator.is_alive(&gen_index));
+    }
+
+    #[test]
+    fn reserved_indices_are_allocated_without_growing() {
+        let mut allocator = EntityAllocator::new(1);
         allocator.allocate();
+        allocator.reserve(3);
+        assert_eq!(allocator.stats(), AllocatorStats { active: 1, free: 3, capacity: 4 });
+
+        for _ in 0..3 {
+            allocator.allocate();
+        }
+        assert_eq!(allocator.stats(), AllocatorStats { active: 4, free: 0, capacity: 4 });
     }
-}
\ No newline at end of file
+}
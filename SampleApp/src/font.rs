@@ -0,0 +1,100 @@
+use crate::graphics::Vertex;
+use font8x8::{UnicodeFonts, BASIC_FONTS};
+
+// Name under which the glyph atlas is registered in the assets of Graphics
+pub const FONT_TEXTURE: &str = "__font";
+
+// Glyphs are square, GLYPH_SIZE pixels wide and high
+const GLYPH_SIZE: u32 = 8;
+const ATLAS_COLUMNS: u32 = 16;
+// Only printable ASCII is in the atlas. Other characters are drawn as '?'.
+const FIRST_CHAR: u32 = ' ' as u32;
+const LAST_CHAR: u32 = '~' as u32;
+
+fn atlas_rows() -> u32 {
+    (LAST_CHAR - FIRST_CHAR + 1 + ATLAS_COLUMNS - 1)/ATLAS_COLUMNS
+}
+
+// Rasterizes the bitmap font into white RGBA glyphs on a transparent background so the text can be colored by the instance color.
+pub fn create_font_atlas() -> (Vec<u8>, (u32, u32)) {
+    let dimensions = (ATLAS_COLUMNS*GLYPH_SIZE, atlas_rows()*GLYPH_SIZE);
+    let mut rgba = vec![0u8; (dimensions.0*dimensions.1*4) as usize];
+
+    for code in FIRST_CHAR..=LAST_CHAR {
+        let glyph = BASIC_FONTS.get(std::char::from_u32(code).unwrap()).unwrap_or([0; 8]);
+        let index = code - FIRST_CHAR;
+        let origin = ((index%ATLAS_COLUMNS)*GLYPH_SIZE, (index/ATLAS_COLUMNS)*GLYPH_SIZE);
+
+        for (y, row) in glyph.iter().enumerate() {
+            // The lowest bit is the leftmost pixel
+            for x in 0..GLYPH_SIZE {
+                if row & (1 << x) != 0 {
+                    let pixel = (((origin.1 + y as u32)*dimensions.0 + origin.0 + x)*4) as usize;
+                    rgba[pixel..pixel + 4].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+        }
+    }
+
+    (rgba, dimensions)
+}
+
+// Top left and bottom right texture coordinates of the glyph in the atlas
+fn glyph_tex_coords(character: char) -> ([f32; 2], [f32; 2]) {
+    let code = match character as u32 {
+        code if (FIRST_CHAR..=LAST_CHAR).contains(&code) => code,
+        _ => '?' as u32,
+    };
+
+    let index = code - FIRST_CHAR;
+    let (column, row) = ((index%ATLAS_COLUMNS) as f32, (index/ATLAS_COLUMNS) as f32);
+    let (columns, rows) = (ATLAS_COLUMNS as f32, atlas_rows() as f32);
+
+    ([column/columns, row/rows], [(column + 1.0)/columns, (row + 1.0)/rows])
+}
+
+// Builds one quad per character, starting at the origin and going right and down. Each line is size units high.
+// Spaces advance the cursor without emitting a quad.
+pub fn append_text_quads(text: &str, size: f32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    for (line_index, line) in text.lines().enumerate() {
+        let top = -(line_index as f32)*size;
+
+        for (column, character) in line.chars().enumerate() {
+            if character == ' ' {
+                continue;
+            }
+
+            let left = column as f32*size;
+            let (uv_min, uv_max) = glyph_tex_coords(character);
+            let base_index = vertices.len() as u32;
+
+            vertices.push(Vertex::unlit([left, top, 0.0], [uv_min[0], uv_min[1]]));
+            vertices.push(Vertex::unlit([left + size, top, 0.0], [uv_max[0], uv_min[1]]));
+            vertices.push(Vertex::unlit([left, top - size, 0.0], [uv_min[0], uv_max[1]]));
+            vertices.push(Vertex::unlit([left + size, top - size, 0.0], [uv_max[0], uv_max[1]]));
+
+            // Same winding as the quad mesh
+            indices.extend([2, 1, 0, 1, 2, 3].iter().map(|index| base_index + index));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaces_and_newlines_emit_no_quads() {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        append_text_quads("a b\nc", 2.0, &mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 3*4);
+        assert_eq!(indices.len(), 3*6);
+    }
+
+    #[test]
+    fn unknown_characters_use_question_mark() {
+        assert_eq!(glyph_tex_coords('\u{e9}'), glyph_tex_coords('?'));
+    }
+}
@@ -1,22 +1,188 @@
 use std::collections::HashMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 use wgpu::util::DeviceExt;
+use anyhow::{bail, Context, Result};
+use std::sync::{Arc, Mutex};
 use crate::texture::Texture;
+use crate::asset_manager::{AssetManager, Handle};
+use crate::async_loader::AsyncLoader;
+use crate::light::Light;
+use crate::font::{create_font_atlas, FONT_TEXTURE};
+use crate::debug_draw::{DebugDraw, DEBUG_LINES};
+use crate::skeleton::{Bone, BoneTransform, SkeletalClip, Skeleton, Track, TrackValues, MAX_BONES};
+
+// Name under which the fallback pipeline, mesh and texture are registered. Used in place of assets that don't exist.
+pub const MISSING_ASSET: &str = "__missing";
+
+// Format of the depth buffer. Custom pipelines must use it in their DepthStencilState to be compatible with the render pass.
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Format of the texture headless graphics render to. Matches the layout of image::RgbaImage so frames are captured without conversion.
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Sample counts every adapter supports for render attachments. wgpu can't query an adapter for the other counts.
+pub const SUPPORTED_SAMPLE_COUNTS: [u32; 2] = [1, 4];
+
+pub enum RenderTarget {
+    Window {
+        surface: wgpu::Surface,
+        swap_chain: wgpu::SwapChain,
+        // The modes set_present_mode accepts, Fifo first
+        present_modes: Vec<wgpu::PresentMode>,
+    },
+    // Headless graphics render to a texture that can be copied back with capture_frame
+    Offscreen(wgpu::Texture),
+}
+
+// The texture a frame is rendered to. A window frame is presented when it is dropped.
+pub enum Frame {
+    Window(wgpu::SwapChainFrame),
+    Offscreen(wgpu::TextureView),
+}
+
+impl Frame {
+    pub fn view(&self) -> &wgpu::TextureView {
+        match self {
+            Frame::Window(frame) => &frame.output.view,
+            Frame::Offscreen(view) => view,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphicsInitError {
+    // No adapter of the selected backends can render, to the window when there is one
+    NoAdapter(wgpu::BackendBit),
+    DeviceRequestFailed(wgpu::RequestDeviceError),
+    // The adapter has no format to present to the window with
+    UnsupportedSurface(String),
+}
+
+impl std::fmt::Display for GraphicsInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphicsInitError::NoAdapter(backends) => write!(f, "No compatible GPU was found for the backends {:?}. Set {} to try other backends.", backends, BACKEND_VARIABLE),
+            GraphicsInitError::DeviceRequestFailed(error) => write!(f, "The GPU device couldn't be created: {}", error),
+            GraphicsInitError::UnsupportedSurface(adapter) => write!(f, "The adapter {} can't present to the window", adapter),
+        }
+    }
+}
+
+impl std::error::Error for GraphicsInitError {}
+
+// Environment variable selecting the backends adapters are requested from, as a comma separated list like "vulkan,gl"
+pub const BACKEND_VARIABLE: &str = "RUSTYGEAR_BACKEND";
+
+// The backends named by BACKEND_VARIABLE, or the primary ones (Vulkan, Metal, DX12 and browser WebGPU) when it isn't set or is invalid
+fn selected_backends() -> wgpu::BackendBit {
+    let value = match std::env::var(BACKEND_VARIABLE) {
+        Ok(value) => value,
+        Err(_) => return wgpu::BackendBit::PRIMARY,
+    };
+
+    let mut backends = wgpu::BackendBit::empty();
+    for name in value.split(',') {
+        backends |= match name.trim().to_lowercase().as_str() {
+            "vulkan" => wgpu::BackendBit::VULKAN,
+            "metal" => wgpu::BackendBit::METAL,
+            "dx12" => wgpu::BackendBit::DX12,
+            "dx11" => wgpu::BackendBit::DX11,
+            "gl" => wgpu::BackendBit::GL,
+            "webgpu" => wgpu::BackendBit::BROWSER_WEBGPU,
+            "primary" => wgpu::BackendBit::PRIMARY,
+            "secondary" => wgpu::BackendBit::SECONDARY,
+            _ => {
+                eprintln!("Unknown backend {} in {}. Using the primary backends.", name, BACKEND_VARIABLE);
+                return wgpu::BackendBit::PRIMARY;
+            }
+        };
+    }
+
+    backends
+}
+
+// Present modes the window surfaces of the backend support. wgpu 0.9 can't query a surface for its modes and silently
+// replaces an unsupported one with Fifo, so only the modes every surface of the backend supports are listed. Fifo is
+// supported by every surface.
+fn supported_present_modes(backend: wgpu::Backend) -> Vec<wgpu::PresentMode> {
+    match backend {
+        wgpu::Backend::Vulkan | wgpu::Backend::Metal | wgpu::Backend::Dx12 | wgpu::Backend::Dx11 => vec![wgpu::PresentMode::Fifo, wgpu::PresentMode::Immediate],
+        _ => vec![wgpu::PresentMode::Fifo],
+    }
+}
+
+fn create_offscreen_texture(device: &wgpu::Device, descriptor: &wgpu::SwapChainDescriptor) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("offscreen_texture"),
+        size: wgpu::Extent3d {
+            width: descriptor.width,
+            height: descriptor.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: descriptor.format,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::SAMPLED,
+    })
+}
+
+// Color and depth textures of a camera render target
+pub struct TextureTarget {
+    pub color: Texture,
+    // Rendered into and resolved to the color texture when multisampling
+    pub multisampled: Option<Texture>,
+    pub depth: Texture,
+    size: (u32, u32),
+}
 
 pub struct Graphics {
-    pub surface: wgpu::Surface,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    // Format and size of the target. Headless graphics use it to describe their texture.
     pub swap_chain_descriptor: wgpu::SwapChainDescriptor,
-    pub swap_chain: wgpu::SwapChain,
+    pub target: RenderTarget,
+    pub depth_texture: Texture,
+    // Rendered into and resolved to the frame when multisampling
+    pub multisampled_texture: Option<Texture>,
+    // Samples per pixel of the color and depth attachments and of the pipelines
+    sample_count: u32,
     pub size: (u32, u32),
-    pub models: HashMap<String, Mesh>,
-    pub textures: HashMap<String, wgpu::BindGroup>,
-    pub pipelines: HashMap<String, wgpu::RenderPipeline>,
+    // Meshes and texture bind groups. Models are loaded by load_mesh and textures by load_texture.
+    pub assets: AssetManager,
+    // Textures requested with load_texture_async are decoded here before being uploaded by finish_loads
+    texture_loader: AsyncLoader<image::RgbaImage>,
+    async_color_spaces: HashMap<String, ColorSpace>,
+    // Textures the cameras with a render target draw into, by name
+    texture_targets: HashMap<String, TextureTarget>,
+    // Every blend mode of a shader is a distinct pipeline
+    pub pipelines: HashMap<(String, BlendMode), wgpu::RenderPipeline>,
+    pipeline_descriptors: HashMap<(String, BlendMode), PipelineDescriptor>,
+    // Line polygon mode copies of the pipelines. Empty if the adapter doesn't support NON_FILL_POLYGON_MODE.
+    wireframe_pipelines: HashMap<(String, BlendMode), wgpu::RenderPipeline>,
+    wireframe: bool,
     pub uniforms: Uniforms,
     pub uniform_buffer: wgpu::Buffer,
     pub uniform_bind_group: wgpu::BindGroup,
+    // How far the frame is between the last two fixed steps, in the range [0, 1). Set by the game loop before rendering.
+    pub interpolation_alpha: f32,
+    // Lines and boxes drawn by the RenderSystem for the current frame only
+    pub debug_draw: DebugDraw,
+    // Draw calls the RenderSystem issued during the last frame
+    pub draw_calls: usize,
     texture_layout: wgpu::BindGroupLayout,
+    samplers: Samplers,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    bone_bind_group_layout: wgpu::BindGroupLayout,
+    // Validation errors are collected here instead of panicking while this is Some
+    captured_errors: Arc<Mutex<Option<Vec<String>>>>,
+    // Where to save the next frame rendered to the window
+    capture_request: Option<PathBuf>,
+    // Background of the frames no camera renders to
+    clear_color: wgpu::Color,
+    // Acquired by begin_frame and presented by end_frame. Systems that render take it and put it back.
+    pub current_frame: Option<Frame>,
 }
 
 #[repr(C)]
@@ -28,6 +194,23 @@ pub struct Vertex {
 }
 
 impl Vertex {
+    pub fn new(position: [f32; 3], normal: [f32; 3], tex_coords: [f32; 2]) -> Self {
+        Self {
+            position,
+            normal,
+            tex_coords,
+        }
+    }
+
+    // Vertex without a normal. The sprite shader doesn't light them, which is used by text and debug drawing.
+    pub fn unlit(position: [f32; 3], tex_coords: [f32; 2]) -> Self {
+        Self {
+            position,
+            normal: [0.0, 0.0, 0.0],
+            tex_coords,
+        }
+    }
+
     fn Desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -53,17 +236,226 @@ impl Vertex {
     }
 }
 
-pub struct Mesh {
-    pub vertices: Vec<Vertex>,
-    pub indices: Vec<u16>,
+// Vertex of a skinned mesh, moved by up to four bones of the skeleton of the entity
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    tex_coords: [f32; 2],
+    // Indices of the bones in the palette, and how much each one moves the vertex. The weights add up to one.
+    joints: [u32; 4],
+    weights: [f32; 4],
+}
+
+impl SkinnedVertex {
+    // The instance data takes the locations in between, so the joints and weights come after it
+    fn Desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x4,
+                }
+            ]
+        }
+    }
+}
+
+// Size of the palette of one skinned mesh in the bone buffer. A multiple of the 256 bytes dynamic offsets must be aligned to.
+pub const BONE_PALETTE_SIZE: wgpu::BufferAddress = (MAX_BONES*std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress;
+
+// Name of the pipeline drawing the entities with a Skeleton, whatever the shader of their Mesh component
+pub const SKINNED_PIPELINE: &str = "skinned";
+
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    // Picks the narrowest index type able to address every vertex.
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        match vertex_count <= u16::MAX as usize + 1 {
+            true => Indices::U16(indices.into_iter().map(|index| index as u16).collect()),
+            false => Indices::U32(indices),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Indices::U16(_) => wgpu::IndexFormat::Uint16,
+            Indices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(indices) => bytemuck::cast_slice(indices),
+            Indices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+}
+
+pub struct Mesh<V = Vertex> {
+    pub vertices: Vec<V>,
+    pub indices: Indices,
     pub vertex_buffer: Option<wgpu::Buffer>,
     pub index_buffer: Option<wgpu::Buffer>,
 }
 
+// Smallest size of a DynamicBuffer, so small scenes don't recreate it every time they grow by a few instances
+const MIN_DYNAMIC_BUFFER_SIZE: wgpu::BufferAddress = 1024;
+
+// Buffer rewritten every frame. It is kept across frames and only recreated, twice as large, when the data doesn't fit.
+pub struct DynamicBuffer {
+    label: &'static str,
+    usage: wgpu::BufferUsage,
+    buffer: Option<wgpu::Buffer>,
+    capacity: wgpu::BufferAddress,
+}
+
+impl DynamicBuffer {
+    pub fn new(label: &'static str, usage: wgpu::BufferUsage) -> Self {
+        Self {
+            label,
+            usage: usage | wgpu::BufferUsage::COPY_DST,
+            buffer: None,
+            capacity: 0,
+        }
+    }
+
+    // Returns true if the buffer was recreated, in which case the bind groups using it must be recreated too
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, contents: &[u8]) -> bool {
+        let size = contents.len() as wgpu::BufferAddress;
+        let recreated = self.buffer.is_none() || size > self.capacity;
+        if recreated {
+            self.capacity = size.next_power_of_two().max(MIN_DYNAMIC_BUFFER_SIZE);
+            self.buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(self.label),
+                size: self.capacity,
+                usage: self.usage,
+                mapped_at_creation: false,
+            }));
+        }
+
+        if !contents.is_empty() {
+            queue.write_buffer(self.buffer(), 0, contents);
+        }
+
+        recreated
+    }
+
+    // Must be called after write
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+// Puts the palettes one after the other, for the buffer of create_bone_bind_group. Palettes with fewer than MAX_BONES bones
+// are padded with identity matrices.
+pub fn pack_bone_palettes(palettes: &[&[[[f32; 4]; 4]]]) -> Vec<[[f32; 4]; 4]> {
+    use cgmath::SquareMatrix;
+
+    let identity : [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+    let mut contents = Vec::with_capacity(palettes.len()*MAX_BONES);
+    for palette in palettes {
+        let bones = palette.len().min(MAX_BONES);
+        contents.extend_from_slice(&palette[..bones]);
+        contents.resize(contents.len() + MAX_BONES - bones, identity);
+    }
+
+    contents
+}
+
+// Per instance data. Every entity drawn by an instanced draw call has its own entry in the instance buffer.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelProperties {
     pub model_matrix: [[f32; 4]; 4],
+    // Multiplied with the texture color
+    pub color: [f32; 4],
+    // Texture coordinates offset in xy and scale in zw
+    pub uv_rect: [f32; 4],
+}
+
+// uv_rect of the instances using the whole texture
+pub const FULL_UV_RECT: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+impl ModelProperties {
+    fn Desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ModelProperties>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            // A mat4 takes 4 vertex slots, one for each column
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                }
+            ]
+        }
+    }
 }
 
 fn create_quad() -> Mesh {
@@ -98,17 +490,298 @@ fn create_quad() -> Mesh {
     vertices.push(vertexC);
     vertices.push(vertexD);
 
-    let indices = vec!(2, 1, 0, 1, 2, 3);
+    let indices = Indices::U16(vec!(2, 1, 0, 1, 2, 3));
+
+    Mesh {
+        vertices,
+        indices,
+        vertex_buffer: None,
+        index_buffer: None,
+    }
+}
+
+// Line from (0, 0, 0) to (1, 0, 0) instanced by debug drawing
+fn create_unit_line() -> Mesh {
+    let vertices = vec!(Vertex::unlit([0.0, 0.0, 0.0], [0.0, 0.0]), Vertex::unlit([1.0, 0.0, 0.0], [0.0, 0.0]));
 
     Mesh {
+        vertices,
+        indices: Indices::U16(vec!(0, 1)),
+        vertex_buffer: None,
+        index_buffer: None,
+    }
+}
+
+// Loads every model of an obj file into a single mesh. Faces are triangulated and
+// positions, normals and texture coordinates are unified under one index buffer.
+pub fn load_obj(path: &Path) -> Result<Mesh> {
+    let (models, _materials) = tobj::load_obj(path, &tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    })?;
+
+    let mut vertices = Vec::new();
+    let mut indices : Vec<u32> = Vec::new();
+
+    for model in models {
+        let mesh = &model.mesh;
+        let base_index = vertices.len() as u32;
+
+        for i in 0..mesh.positions.len()/3 {
+            let normal = match mesh.normals.is_empty() {
+                true => [0.0, 0.0, 1.0],
+                false => [mesh.normals[i*3], mesh.normals[i*3 + 1], mesh.normals[i*3 + 2]],
+            };
+
+            // Obj texture coordinates have the origin at the bottom left while wgpu's is at the top left
+            let tex_coords = match mesh.texcoords.is_empty() {
+                true => [0.0, 0.0],
+                false => [mesh.texcoords[i*2], 1.0 - mesh.texcoords[i*2 + 1]],
+            };
+
+            vertices.push(Vertex {
+                position: [mesh.positions[i*3], mesh.positions[i*3 + 1], mesh.positions[i*3 + 2]],
+                normal,
+                tex_coords,
+            });
+        }
+
+        indices.extend(mesh.indices.iter().map(|index| base_index + index));
+    }
+
+    let indices = Indices::new(indices, vertices.len());
+
+    Ok(Mesh {
         vertices,
         indices,
         vertex_buffer: None,
         index_buffer: None,
+    })
+}
+
+// Loads an obj file of the models directory
+fn load_model(mesh_name: &str) -> Result<Mesh> {
+    let mut path = std::env::current_dir().unwrap();
+    path.push("src\\resources\\models");
+    path.push(mesh_name);
+
+    load_obj(&path).with_context(|| format!("Failed to load the model: {:?}", path))
+}
+
+pub enum GltfMesh {
+    Static(Mesh),
+    // Moved by the bones of the skeleton of the model
+    Skinned(Mesh<SkinnedVertex>),
+}
+
+// The meshes, embedded textures and skeleton of a gltf or glb file, before they are uploaded
+pub struct GltfModel {
+    // One mesh per primitive, with the index in textures of its base color texture
+    pub primitives: Vec<(GltfMesh, Option<usize>)>,
+    pub textures: Vec<image::RgbaImage>,
+    // The first skin of the file with every animation moving its joints
+    pub skeleton: Option<Skeleton>,
+}
+
+// Names under which Graphics::load_gltf registered a primitive and its base color texture, to use in a Mesh component
+pub struct GltfPrimitive {
+    pub mesh_name: String,
+    pub diffuse_texture: Option<String>,
+    // Skinned primitives are drawn for entities with a Skeleton component
+    pub skinned: bool,
+}
+
+// Name of the gltf or glb model of a primitive registered by Graphics::load_gltf
+fn gltf_model_name(mesh_name: &str) -> Option<&str> {
+    let (model_name, primitive) = mesh_name.rsplit_once('#')?;
+    primitive.parse::<usize>().ok()?;
+
+    match Path::new(model_name).extension().and_then(|extension| extension.to_str()) {
+        Some("gltf") | Some("glb") => Some(model_name),
+        _ => None,
+    }
+}
+
+// Loads the triangle primitives of every mesh of a gltf or glb file. Meshes are kept in their own space, the transforms of the
+// nodes using them are ignored. Primitives with joints and weights are skinned by the first skin of the file.
+pub fn load_gltf(path: &Path) -> Result<GltfModel> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    let mut primitives = Vec::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                bail!("The primitive {} of the mesh {} isn't made of triangles", primitive.index(), mesh.index());
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions : Vec<[f32; 3]> = reader.read_positions()
+                .with_context(|| format!("The primitive {} of the mesh {} has no positions", primitive.index(), mesh.index()))?
+                .collect();
+            let normals : Vec<[f32; 3]> = reader.read_normals().map(|normals| normals.collect()).unwrap_or_default();
+            let tex_coords : Vec<[f32; 2]> = reader.read_tex_coords(0).map(|tex_coords| tex_coords.into_f32().collect()).unwrap_or_default();
+
+            // Gltf texture coordinates already have the origin at the top left like wgpu's
+            let vertices : Vec<Vertex> = positions.iter().enumerate()
+                .map(|(i, position)| Vertex {
+                    position: *position,
+                    normal: normals.get(i).copied().unwrap_or([0.0, 0.0, 1.0]),
+                    tex_coords: tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+                })
+                .collect();
+
+            let indices : Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            let indices = Indices::new(indices, vertices.len());
+
+            let diffuse_texture = primitive.material().pbr_metallic_roughness().base_color_texture()
+                .map(|info| info.texture().source().index());
+
+            let mesh = match (reader.read_joints(0), reader.read_weights(0)) {
+                (Some(joints), Some(weights)) => GltfMesh::Skinned(Mesh {
+                    vertices: vertices.iter().zip(joints.into_u16()).zip(weights.into_f32())
+                        .map(|((vertex, joints), weights)| SkinnedVertex {
+                            position: vertex.position,
+                            normal: vertex.normal,
+                            tex_coords: vertex.tex_coords,
+                            joints: [joints[0] as u32, joints[1] as u32, joints[2] as u32, joints[3] as u32],
+                            weights,
+                        })
+                        .collect(),
+                    indices,
+                    vertex_buffer: None,
+                    index_buffer: None,
+                }),
+                _ => GltfMesh::Static(Mesh {
+                    vertices,
+                    indices,
+                    vertex_buffer: None,
+                    index_buffer: None,
+                }),
+            };
+
+            primitives.push((mesh, diffuse_texture));
+        }
+    }
+
+    let textures = images.into_iter().enumerate()
+        .map(|(index, image)| gltf_image_to_rgba(index, image))
+        .collect::<Result<Vec<image::RgbaImage>>>()?;
+
+    let skeleton = match document.skins().next() {
+        Some(skin) => Some(load_gltf_skeleton(&document, &skin, &buffers)?),
+        None => None,
+    };
+
+    Ok(GltfModel { primitives, textures, skeleton })
+}
+
+// The joints of the skin become the bones, in the same order so the joint indices of the vertices index the palette
+fn load_gltf_skeleton(document: &gltf::Document, skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Result<Skeleton> {
+    let joints : Vec<gltf::Node> = skin.joints().collect();
+    if joints.len() > MAX_BONES {
+        bail!("The skin {} has {} joints but at most {} are supported", skin.index(), joints.len(), MAX_BONES);
+    }
+
+    let bone_index = |node: &gltf::Node| joints.iter().position(|joint| joint.index() == node.index());
+
+    // Nodes only know their children
+    let mut parents = HashMap::new();
+    for node in document.nodes() {
+        for child in node.children() {
+            parents.insert(child.index(), node.index());
+        }
+    }
+
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    let inverse_bind_matrices : Vec<[[f32; 4]; 4]> = reader.read_inverse_bind_matrices().map(|matrices| matrices.collect()).unwrap_or_default();
+
+    let bones = joints.iter().enumerate()
+        .map(|(index, joint)| {
+            let (translation, rotation, scale) = joint.transform().decomposed();
+            Bone {
+                name: joint.name().unwrap_or_default().to_owned(),
+                parent: parents.get(&joint.index()).and_then(|parent| joints.iter().position(|candidate| candidate.index() == *parent)),
+                rest: BoneTransform {
+                    translation: translation.into(),
+                    // Gltf quaternions are stored as x, y, z, w
+                    rotation: cgmath::Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+                    scale: scale.into(),
+                },
+                inverse_bind_matrix: inverse_bind_matrices.get(index).copied().map(cgmath::Matrix4::from).unwrap_or_else(|| {
+                    use cgmath::SquareMatrix;
+                    cgmath::Matrix4::identity()
+                }),
+            }
+        })
+        .collect();
+
+    let mut clips = Vec::new();
+    for animation in document.animations() {
+        let mut tracks = Vec::new();
+        for channel in animation.channels() {
+            let bone = match bone_index(&channel.target().node()) {
+                Some(bone) => bone,
+                None => continue,
+            };
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times : Vec<f32> = match reader.read_inputs() {
+                Some(times) => times.collect(),
+                None => continue,
+            };
+
+            let interpolation = channel.sampler().interpolation();
+            let values = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(values)) => TrackValues::Translation(keyframe_values(values.map(cgmath::Vector3::from), interpolation)),
+                // Gltf quaternions are stored as x, y, z, w
+                Some(gltf::animation::util::ReadOutputs::Rotations(values)) => TrackValues::Rotation(keyframe_values(values.into_f32().map(|v| cgmath::Quaternion::new(v[3], v[0], v[1], v[2])), interpolation)),
+                Some(gltf::animation::util::ReadOutputs::Scales(values)) => TrackValues::Scale(keyframe_values(values.map(cgmath::Vector3::from), interpolation)),
+                _ => continue,
+            };
+
+            tracks.push(Track { bone, times, values });
+        }
+
+        clips.push(SkeletalClip {
+            name: animation.name().map(str::to_owned).unwrap_or_else(|| format!("animation{}", animation.index())),
+            duration: tracks.iter().filter_map(|track| track.times.last().copied()).fold(0.0, f32::max),
+            tracks,
+        });
     }
+
+    Ok(Skeleton {
+        bones,
+        clips,
+        clip: None,
+        looping: false,
+        time: 0.0,
+        palette: Vec::new(),
+    })
+}
+
+// Step and cubic spline channels are sampled linearly. Cubic spline outputs store an in tangent, the value and an out tangent per keyframe.
+fn keyframe_values<T>(values: impl Iterator<Item = T>, interpolation: gltf::animation::Interpolation) -> Vec<T> {
+    match interpolation {
+        gltf::animation::Interpolation::CubicSpline => values.skip(1).step_by(3).collect(),
+        _ => values.collect(),
+    }
+}
+
+fn gltf_image_to_rgba(index: usize, image: gltf::image::Data) -> Result<image::RgbaImage> {
+    let pixels = match image.format {
+        gltf::image::Format::R8G8B8A8 => image.pixels,
+        gltf::image::Format::R8G8B8 => image.pixels.chunks(3).flat_map(|rgb| vec!(rgb[0], rgb[1], rgb[2], 255)).collect(),
+        format => bail!("The image {} has the unsupported format {:?}", index, format),
+    };
+
+    image::RgbaImage::from_raw(image.width, image.height, pixels).with_context(|| format!("The image {} is smaller than its size", index))
 }
 
-impl Mesh {
+impl<V: bytemuck::Pod> Mesh<V> {
     fn upload_to_gpu(&mut self, device: &wgpu::Device) {
         self.vertex_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -118,7 +791,7 @@ impl Mesh {
 
         self.index_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&self.indices),
+            contents: self.indices.as_bytes(),
             usage: wgpu::BufferUsage::INDEX,
         }));
     }
@@ -127,7 +800,13 @@ impl Mesh {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Uniforms {
-    view_proj: [[f32; 4]; 4],   
+    view_proj: [[f32; 4]; 4],
+    // Direction the light travels in world space. The w component is unused.
+    light_direction: [f32; 4],
+    // Color times intensity of the directional light. The w component is unused.
+    light_color: [f32; 4],
+    // Light added regardless of the normal. The w component is unused.
+    ambient_color: [f32; 4],
 }
 
 impl Uniforms {
@@ -135,16 +814,54 @@ impl Uniforms {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            light_direction: [0.0, 0.0, -1.0, 0.0],
+            light_color: [0.0, 0.0, 0.0, 0.0],
+            ambient_color: [1.0, 1.0, 1.0, 0.0],
         }
     }
     pub fn update_view_proj(&mut self, matrix4: cgmath::Matrix4<f32>) {
         self.view_proj = matrix4.into();
     }
+
+    pub fn update_light(&mut self, light: &Light) {
+        let direction = light.unit_direction();
+        self.light_direction = [direction.x, direction.y, direction.z, 0.0];
+        self.light_color = [light.color[0]*light.intensity, light.color[1]*light.intensity, light.color[2]*light.intensity, 0.0];
+        self.ambient_color = [light.color[0]*light.ambient, light.color[1]*light.ambient, light.color[2]*light.ambient, 0.0];
+    }
+
+    // Without a light everything is drawn with the texture color only
+    pub fn clear_light(&mut self) {
+        self.light_color = [0.0, 0.0, 0.0, 0.0];
+        self.ambient_color = [1.0, 1.0, 1.0, 0.0];
+    }
+}
+
+pub fn upload_texture_to_gpu(texture_name: &str, color_space: ColorSpace, device: &wgpu::Device, queue: &wgpu::Queue, texture_bind_group_layout: &wgpu::BindGroupLayout, samplers: &Samplers) -> Result<TextureBindGroups> {
+    let texture = Texture::load_texture(texture_name, color_space, &device, &queue)?;
+
+    Ok(create_texture_bind_groups(&texture, texture_name, device, texture_bind_group_layout, samplers))
 }
 
-pub fn upload_texture_to_gpu(texture_name: &str, device: &wgpu::Device, queue: &wgpu::Queue, texture_bind_group_layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
-    let texture = Texture::load_texture(texture_name, &device, &queue).unwrap();
+// Magenta and black checkerboard used in place of textures that failed to load so they are easy to spot
+fn create_missing_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture_bind_group_layout: &wgpu::BindGroupLayout, samplers: &Samplers) -> TextureBindGroups {
+    let magenta : [u8; 4] = [255, 0, 255, 255];
+    let black : [u8; 4] = [0, 0, 0, 255];
+    let rgba = [magenta, black, black, magenta].concat();
+    let texture = Texture::from_rgba(&rgba, (2, 2), "missing_texture", ColorSpace::Srgb, device, queue);
 
+    create_texture_bind_groups(&texture, "missing_texture", device, texture_bind_group_layout, samplers)
+}
+
+// The texture is bound once per filter so meshes sharing it can sample it differently
+fn create_texture_bind_groups(texture: &Texture, label: &str, device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, samplers: &Samplers) -> TextureBindGroups {
+    TextureBindGroups {
+        linear: create_texture_bind_group(texture, samplers.get(TextureFilter::Linear), label, device, texture_bind_group_layout),
+        nearest: create_texture_bind_group(texture, samplers.get(TextureFilter::Nearest), label, device, texture_bind_group_layout),
+    }
+}
+
+fn create_texture_bind_group(texture: &Texture, sampler: &wgpu::Sampler, label: &str, device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
     device.create_bind_group(&wgpu::BindGroupDescriptor {
         layout: &texture_bind_group_layout,
         entries: &[
@@ -154,66 +871,275 @@ pub fn upload_texture_to_gpu(texture_name: &str, device: &wgpu::Device, queue: &
             },
             wgpu::BindGroupEntry {
                 binding: 1,
-                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                resource: wgpu::BindingResource::Sampler(sampler),
             }
         ],
-        label: Some(texture_name),
+        label: Some(label),
     })
 }
 
-pub fn load_shader(shader_name: &str) -> Vec<u8> {
-    let mut shader_dir = std::env::current_dir().unwrap();
+pub fn load_shader(shader_name: &str) -> Result<Vec<u8>> {
+    let mut shader_dir = std::env::current_dir()?;
     shader_dir.push("src\\resources\\shaders");
     shader_dir.push(shader_name);
 
-    match fs::read(&shader_dir) {
-        Ok(v) => v,
-        Err(error) => panic!("Failed to read the file: {:?}. Error: {}", shader_dir.as_path(), error)
+    fs::read(&shader_dir).with_context(|| format!("Failed to read the file: {:?}", shader_dir.as_path()))
+}
+
+// Picks the language from the extension of the file, .spv for SPIR-V binaries and .wgsl for WGSL sources. Compile errors are
+// reported by the device error handler, with the file name as the label of the module.
+pub fn load_shader_module(device: &wgpu::Device, shader_name: &str) -> Result<wgpu::ShaderModule> {
+    let contents = load_shader(shader_name)?;
+
+    match Path::new(shader_name).extension().and_then(|extension| extension.to_str()) {
+        Some("spv") => create_spirv_shader_module(device, shader_name, &contents),
+        Some("wgsl") => {
+            let source = String::from_utf8(contents).with_context(|| format!("{} is not valid UTF-8", shader_name))?;
+            Ok(device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some(shader_name),
+                flags: wgpu::ShaderFlags::all(),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            }))
+        },
+        _ => bail!("Unknown format of the shader {}. Expected a .spv or .wgsl file", shader_name),
     }
 }
 
-pub fn new_pipeline(device: &wgpu::Device, texture_format: wgpu::TextureFormat, vert_shader_name: &str, frag_shader_name: &str, texture_bind_group_layout: &wgpu::BindGroupLayout, uniform_bind_group_layout: &wgpu::BindGroupLayout, topology: wgpu::PrimitiveTopology, polygon_mode: wgpu::PolygonMode) -> wgpu::RenderPipeline {
-    let vert_shader_contents = load_shader(vert_shader_name);
-    let frag_shader_contents = load_shader(frag_shader_name);
-    
-    let vertex_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: Some(vert_shader_name),
-        flags: wgpu::ShaderFlags::all(),
-        source: wgpu::util::make_spirv(&vert_shader_contents),
-    });
+fn create_spirv_shader_module(device: &wgpu::Device, shader_name: &str, contents: &[u8]) -> Result<wgpu::ShaderModule> {
+    // make_spirv panics on malformed input so it is validated beforehand
+    const SPIRV_MAGIC_NUMBER: [u8; 4] = [0x03, 0x02, 0x23, 0x07];
+    if contents.len() % 4 != 0 || contents.len() < 4 || contents[0..4] != SPIRV_MAGIC_NUMBER {
+        bail!("{} is not a valid SPIR-V binary", shader_name);
+    }
 
-    let frag_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: Some(frag_shader_name),
+    Ok(device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some(shader_name),
         flags: wgpu::ShaderFlags::all(),
-        source: wgpu::util::make_spirv(&frag_shader_contents),
-    });
+        source: wgpu::util::make_spirv(contents),
+    }))
+}
 
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Render Pipeline Layout"),
-        bind_group_layouts: &[
-                &texture_bind_group_layout,
-                &uniform_bind_group_layout,
-            ],
-        push_constant_ranges: &[wgpu::PushConstantRange {
-            stages: wgpu_types::ShaderStage::VERTEX,
-            range: 0..128,
-        }],
-    });
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlendMode {
+    // Replaces the color behind
+    Opaque,
+    // Blends with the color behind using the alpha
+    AlphaBlend,
+    // Adds to the color behind. Doesn't write depth so overlapping additive meshes all show.
+    Additive,
+}
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &vertex_shader,
+// How the texels of a texture are stored. Color textures, made to be looked at, are sRGB. Textures holding data, like normal
+// maps or masks, are linear. The shaders get linear values from both.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
+    }
+}
+
+impl ColorSpace {
+    pub fn texture_format(&self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+// The shaders output linear colors, so the swap chain must encode them to sRGB like the offscreen format does. Some adapters
+// prefer a format without the conversion, which would make the frames too dark.
+fn srgb_format(format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    match format {
+        wgpu::TextureFormat::Bgra8Unorm => wgpu::TextureFormat::Bgra8UnormSrgb,
+        wgpu::TextureFormat::Rgba8Unorm => wgpu::TextureFormat::Rgba8UnormSrgb,
+        format => format,
+    }
+}
+
+// How a texture is sampled when it is magnified
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TextureFilter {
+    // Blends the nearest texels. Suits photographic textures.
+    Linear,
+    // Takes the nearest texel. Keeps pixel art crisp.
+    Nearest,
+}
+
+impl Default for TextureFilter {
+    fn default() -> Self {
+        TextureFilter::Linear
+    }
+}
+
+impl TextureFilter {
+    fn filter_mode(&self) -> wgpu::FilterMode {
+        match self {
+            TextureFilter::Linear => wgpu::FilterMode::Linear,
+            TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+        }
+    }
+}
+
+// Samplers shared by all textures, one per filter
+pub struct Samplers {
+    linear: wgpu::Sampler,
+    nearest: wgpu::Sampler,
+}
+
+impl Samplers {
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            linear: Self::create_sampler(TextureFilter::Linear, device),
+            nearest: Self::create_sampler(TextureFilter::Nearest, device),
+        }
+    }
+
+    fn create_sampler(filter: TextureFilter, device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter.filter_mode(),
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        })
+    }
+
+    pub fn get(&self, filter: TextureFilter) -> &wgpu::Sampler {
+        match filter {
+            TextureFilter::Linear => &self.linear,
+            TextureFilter::Nearest => &self.nearest,
+        }
+    }
+}
+
+pub struct TextureBindGroups {
+    linear: wgpu::BindGroup,
+    nearest: wgpu::BindGroup,
+}
+
+impl TextureBindGroups {
+    pub fn get(&self, filter: TextureFilter) -> &wgpu::BindGroup {
+        match filter {
+            TextureFilter::Linear => &self.linear,
+            TextureFilter::Nearest => &self.nearest,
+        }
+    }
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::AlphaBlend
+    }
+}
+
+impl BlendMode {
+    fn blend_state(&self) -> Option<wgpu::BlendState> {
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::AlphaBlend => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+        }
+    }
+}
+
+// Everything needed to build, and rebuild when reloading shaders, a pipeline
+#[derive(Clone)]
+pub struct PipelineDescriptor {
+    pub vert_shader_name: String,
+    pub frag_shader_name: String,
+    pub topology: wgpu::PrimitiveTopology,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub blend_mode: BlendMode,
+    // Skinned pipelines read SkinnedVertex vertices and the bone palette bind group
+    pub skinned: bool,
+    // Pipelines without it draw over everything drawn before them and don't write depth
+    pub depth_test: bool,
+}
+
+impl PipelineDescriptor {
+    pub fn sprite() -> Self {
+        Self {
+            vert_shader_name: "sprite.vert.spv".to_owned(),
+            frag_shader_name: "sprite.frag.spv".to_owned(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            blend_mode: BlendMode::AlphaBlend,
+            skinned: false,
+            depth_test: true,
+        }
+    }
+
+    // Lit like sprites, after moving the vertices with the bones
+    pub fn skinned() -> Self {
+        Self {
+            vert_shader_name: "skinned.vert.spv".to_owned(),
+            skinned: true,
+            ..Self::sprite()
+        }
+    }
+
+    // Sprite shaders drawing lines. The color comes from the instance. The lines are drawn last and over the scene, so
+    // geometry doesn't hide them.
+    pub fn debug_lines() -> Self {
+        Self {
+            topology: wgpu::PrimitiveTopology::LineList,
+            depth_test: false,
+            ..Self::sprite()
+        }
+    }
+}
+
+// Pipelines given a bone bind group layout are skinned
+pub fn new_pipeline(device: &wgpu::Device, texture_format: wgpu::TextureFormat, sample_count: u32, vert_shader_name: &str, frag_shader_name: &str, texture_bind_group_layout: &wgpu::BindGroupLayout, uniform_bind_group_layout: &wgpu::BindGroupLayout, bone_bind_group_layout: Option<&wgpu::BindGroupLayout>, topology: wgpu::PrimitiveTopology, polygon_mode: wgpu::PolygonMode, blend_mode: BlendMode, depth_test: bool) -> Result<wgpu::RenderPipeline> {
+    let vertex_shader = load_shader_module(device, vert_shader_name)?;
+    let frag_shader = load_shader_module(device, frag_shader_name)?;
+
+    let mut bind_group_layouts = vec!(texture_bind_group_layout, uniform_bind_group_layout);
+    bind_group_layouts.extend(bone_bind_group_layout);
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render Pipeline Layout"),
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    let vertex_layout = match bone_bind_group_layout {
+        Some(_) => SkinnedVertex::Desc(),
+        None => Vertex::Desc(),
+    };
+
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &vertex_shader,
             entry_point: "main",
-            buffers: &[Vertex::Desc()],
+            buffers: &[vertex_layout, ModelProperties::Desc()],
         },
         fragment: Some(wgpu::FragmentState {
             module: &frag_shader,
             entry_point: "main",
             targets: &[wgpu::ColorTargetState {
                 format: texture_format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING), // To select alpha
+                blend: blend_mode.blend_state(),
                 write_mask: wgpu::ColorWrite::ALL,
             }],
         }),
@@ -227,24 +1153,36 @@ pub fn new_pipeline(device: &wgpu::Device, texture_format: wgpu::TextureFormat,
             clamp_depth: false,
             conservative: false,
         },
-        depth_stencil: None,
+        // The depth format still has to match the depth attachment of the render pass when depth isn't tested
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: depth_test && blend_mode != BlendMode::Additive,
+            // Sprites at the same depth are drawn over each other in the order they are drawn in
+            depth_compare: match depth_test {
+                true => wgpu::CompareFunction::LessEqual,
+                false => wgpu::CompareFunction::Always,
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
     });
 
-    pipeline
+    Ok(pipeline)
 }
 
 impl Graphics {
-    pub async fn new(window: &sdl2::video::Window) -> Self {
-        let size = window.size();
+    // Renders to anything wgpu can create a surface for, of the given size in pixels. Only the SDL window is used for now since
+    // input, audio and the systems are still built on SDL, so the game can't be built for the web yet.
+    pub async fn new<W: raw_window_handle::HasRawWindowHandle>(window: &W, size: (u32, u32)) -> std::result::Result<Self, GraphicsInitError> {
 
         // The instance is a handle to our GPU
-        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let backends = selected_backends();
+        let instance = wgpu::Instance::new(backends);
 
         // Surface is used to create the swap chain and adapter
         let surface = unsafe { instance.create_surface(window) };
@@ -253,31 +1191,17 @@ impl Graphics {
         let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::default(),
             compatible_surface: Some(&surface),
-        }).await.unwrap();
-
-        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
-                // Specify any extra gpu feature. You can get a list of features supported by your device using adapter.features(), or device.features().
-                // https://docs.rs/wgpu/0.7.0/wgpu/struct.Features.html
-                features: wgpu::Features::PUSH_CONSTANTS,
-
-                // The limits field describes the limit of certain types of resource we can create.
-                // https://docs.rs/wgpu/0.7.0/wgpu/struct.Limits.html
-                limits: wgpu::Limits {
-                    max_push_constant_size: 128,
-                    ..wgpu::Limits::default()
-                },
+        }).await.ok_or(GraphicsInitError::NoAdapter(backends))?;
 
-                label: None,
-            },
-            None,
-        ).await.unwrap();
+        let (device, queue) = Graphics::request_device(&adapter).await?;
+        let format = adapter.get_swap_chain_preferred_format(&surface).map(srgb_format).ok_or_else(|| GraphicsInitError::UnsupportedSurface(adapter.get_info().name))?;
 
         // Define and creating the swap_chain.
         let swap_chain_descriptor = wgpu::SwapChainDescriptor {
             // The usage field describes how the swap_chain's underlying textures will be used.
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
             // Defines how the swap_chains textures will be stored on the gpu
-            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            format,
             width: size.0,
             height: size.1,
             // The present_mode uses the wgpu::PresentMode enum which determines how to sync the swap chain with the display. 
@@ -285,6 +1209,57 @@ impl Graphics {
         };
 
         let swap_chain = device.create_swap_chain(&surface, &swap_chain_descriptor);
+        let present_modes = supported_present_modes(adapter.get_info().backend);
+        Ok(Graphics::from_device(device, queue, swap_chain_descriptor, RenderTarget::Window { surface, swap_chain, present_modes }))
+    }
+
+    // Renders to a texture instead of a window, for tests and screenshots. The frames are read back with capture_frame.
+    pub async fn new_headless(width: u32, height: u32) -> std::result::Result<Self, GraphicsInitError> {
+        let backends = selected_backends();
+        let instance = wgpu::Instance::new(backends);
+        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+        }).await.ok_or(GraphicsInitError::NoAdapter(backends))?;
+
+        let (device, queue) = Graphics::request_device(&adapter).await?;
+
+        let swap_chain_descriptor = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            format: OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        let texture = create_offscreen_texture(&device, &swap_chain_descriptor);
+        Ok(Graphics::from_device(device, queue, swap_chain_descriptor, RenderTarget::Offscreen(texture)))
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> std::result::Result<(wgpu::Device, wgpu::Queue), GraphicsInitError> {
+        let info = adapter.get_info();
+        println!("Using the adapter {} ({:?}) with the {:?} backend", info.name, info.device_type, info.backend);
+
+        adapter.request_device(&wgpu::DeviceDescriptor {
+                // Specify any extra gpu feature. You can get a list of features supported by your device using adapter.features(), or device.features().
+                // https://docs.rs/wgpu/0.7.0/wgpu/struct.Features.html
+                // NON_FILL_POLYGON_MODE is only requested when available since the wireframe view is a debugging aid
+                features: adapter.features() & wgpu::Features::NON_FILL_POLYGON_MODE,
+
+                // The limits field describes the limit of certain types of resource we can create.
+                // https://docs.rs/wgpu/0.7.0/wgpu/struct.Limits.html
+                limits: wgpu::Limits::default(),
+
+                label: None,
+            },
+            None,
+        ).await.map_err(GraphicsInitError::DeviceRequestFailed)
+    }
+
+    // Creates everything that doesn't depend on the render target
+    fn from_device(device: wgpu::Device, queue: wgpu::Queue, swap_chain_descriptor: wgpu::SwapChainDescriptor, target: RenderTarget) -> Self {
+        let size = (swap_chain_descriptor.width, swap_chain_descriptor.height);
+        let depth_texture = Texture::create_depth_texture(&device, &swap_chain_descriptor, 1, "depth_texture");
 
         let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
@@ -324,7 +1299,8 @@ impl Graphics {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStage::VERTEX,
+                    // The fragment shader reads the light
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -347,39 +1323,852 @@ impl Graphics {
             label: Some("uniform_bind_group"),
         });
 
-        let mut pipelines : HashMap::<String, wgpu::RenderPipeline> = HashMap::new();
-        let pipeline = new_pipeline(&device, swap_chain_descriptor.format, "sprite.vert.spv", "sprite.frag.spv", &texture_layout, &uniform_bind_group_layout, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill);
-        pipelines.insert("sprite".to_owned(), pipeline);
+        // Every skinned mesh drawn in a frame has its palette at its own offset of the same buffer
+        let bone_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(BONE_PALETTE_SIZE),
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("bone_bind_group_layout"),
+        });
+
+        let captured_errors : Arc<Mutex<Option<Vec<String>>>> = Arc::new(Mutex::new(None));
+        let handler_errors = captured_errors.clone();
+        device.on_uncaptured_error(move |error| {
+            match handler_errors.lock().unwrap().as_mut() {
+                Some(errors) => errors.push(error.to_string()),
+                None => panic!("wgpu error: {}", error),
+            }
+        });
 
-        let mut models : HashMap::<String, Mesh> = HashMap::new();
+        // Obj models and textures are loaded on demand
+        let mut assets = AssetManager::new();
         let mut triangle_mesh = create_quad();
         triangle_mesh.upload_to_gpu(&device);
-        models.insert("quad".to_owned(), triangle_mesh);
-
-        let mut textures : HashMap<String, wgpu::BindGroup> = HashMap::new();
-        textures.insert("spaceship.png".to_owned(), upload_texture_to_gpu("spaceship.png", &device, &queue, &texture_layout));
+        assets.insert("quad", triangle_mesh);
 
-        Self {
-            surface,
+        let mut graphics = Self {
             device,
             queue,
             swap_chain_descriptor,
-            swap_chain,
+            target,
+            depth_texture,
+            multisampled_texture: None,
+            sample_count: 1,
             size,
-            models,
-            textures,
-            pipelines,
+            assets,
+            texture_loader: AsyncLoader::new(),
+            async_color_spaces: HashMap::new(),
+            texture_targets: HashMap::new(),
+            pipelines: HashMap::new(),
+            pipeline_descriptors: HashMap::new(),
+            wireframe_pipelines: HashMap::new(),
+            wireframe: false,
             texture_layout,
+            samplers: Samplers::new(&device),
+            uniform_bind_group_layout,
+            bone_bind_group_layout,
             uniforms,
             uniform_buffer,
             uniform_bind_group,
+            interpolation_alpha: 0.0,
+            debug_draw: DebugDraw::new(),
+            draw_calls: 0,
+            captured_errors,
+            capture_request: None,
+            clear_color: wgpu::Color::BLACK,
+            current_frame: None,
+        };
+
+        graphics.add_pipeline("sprite", PipelineDescriptor::sprite()).unwrap();
+        if let Err(error) = graphics.add_pipeline(SKINNED_PIPELINE, PipelineDescriptor::skinned()) {
+            eprintln!("Skinned meshes can't be drawn. Error: {:?}", error);
+        }
+        graphics.ensure_defaults();
+        graphics
+    }
+
+    // Registers the fallback assets under MISSING_ASSET: a sprite pipeline, a quad and a checkerboard texture.
+    // Also registers the built in assets used for text and debug drawing.
+    pub fn ensure_defaults(&mut self) {
+        if !self.has_pipeline(MISSING_ASSET, BlendMode::AlphaBlend) {
+            self.add_pipeline(MISSING_ASSET, PipelineDescriptor::sprite()).unwrap();
+        }
+
+        if self.mesh(MISSING_ASSET).is_none() {
+            let mut mesh = create_quad();
+            mesh.upload_to_gpu(&self.device);
+            self.assets.insert(MISSING_ASSET, mesh);
+        }
+
+        if !self.has_pipeline(DEBUG_LINES, BlendMode::AlphaBlend) {
+            self.add_pipeline(DEBUG_LINES, PipelineDescriptor::debug_lines()).unwrap();
+        }
+
+        if self.mesh(DEBUG_LINES).is_none() {
+            let mut mesh = create_unit_line();
+            mesh.upload_to_gpu(&self.device);
+            self.assets.insert(DEBUG_LINES, mesh);
+        }
+
+        if self.texture(DEBUG_LINES, TextureFilter::Nearest).is_none() {
+            let texture = Texture::from_rgba(&[255, 255, 255, 255], (1, 1), DEBUG_LINES, ColorSpace::Srgb, &self.device, &self.queue);
+            let bind_group = create_texture_bind_groups(&texture, DEBUG_LINES, &self.device, &self.texture_layout, &self.samplers);
+            self.assets.insert(DEBUG_LINES, bind_group);
+        }
+
+        if self.texture(FONT_TEXTURE, TextureFilter::Nearest).is_none() {
+            let (rgba, dimensions) = create_font_atlas();
+            let texture = Texture::from_rgba(&rgba, dimensions, FONT_TEXTURE, ColorSpace::Srgb, &self.device, &self.queue);
+            let bind_group = create_texture_bind_groups(&texture, FONT_TEXTURE, &self.device, &self.texture_layout, &self.samplers);
+            self.assets.insert(FONT_TEXTURE, bind_group);
+        }
+
+        if self.texture(MISSING_ASSET, TextureFilter::Linear).is_none() {
+            let bind_group = create_missing_texture(&self.device, &self.queue, &self.texture_layout, &self.samplers);
+            self.assets.insert(MISSING_ASSET, bind_group);
         }
     }
 
+    // Makes the shaders usable by the shader_name of Mesh components. The pipelines share the bind group layouts of the sprite
+    // pipeline and are rebuilt by reload_shaders. Registering a name again replaces its pipeline for that blend mode.
+    pub fn register_pipeline(&mut self, name: &str, vert_shader_name: &str, frag_shader_name: &str, topology: wgpu::PrimitiveTopology, polygon_mode: wgpu::PolygonMode, blend_mode: BlendMode) -> Result<()> {
+        let descriptor = PipelineDescriptor {
+            vert_shader_name: vert_shader_name.to_owned(),
+            frag_shader_name: frag_shader_name.to_owned(),
+            topology,
+            polygon_mode,
+            blend_mode,
+            skinned: false,
+            depth_test: true,
+        };
+
+        self.add_pipeline(name, descriptor).with_context(|| format!("Failed to register the pipeline {}", name))
+    }
+
+    fn add_pipeline(&mut self, name: &str, descriptor: PipelineDescriptor) -> Result<()> {
+        let pipeline = self.build_pipeline(&descriptor)?;
+        let key = (name.to_owned(), descriptor.blend_mode);
+        if let Some(wireframe_pipeline) = self.build_wireframe_pipeline(&key, &descriptor) {
+            self.wireframe_pipelines.insert(key.clone(), wireframe_pipeline);
+        }
+
+        self.pipelines.insert(key.clone(), pipeline);
+        self.pipeline_descriptors.insert(key, descriptor);
+        Ok(())
+    }
+
+    fn build_wireframe_pipeline(&self, key: &(String, BlendMode), descriptor: &PipelineDescriptor) -> Option<wgpu::RenderPipeline> {
+        if !self.supports_wireframe() {
+            return None;
+        }
+
+        let wireframe_descriptor = PipelineDescriptor {
+            polygon_mode: wgpu::PolygonMode::Line,
+            ..descriptor.clone()
+        };
+
+        match self.build_pipeline(&wireframe_descriptor) {
+            Ok(pipeline) => Some(pipeline),
+            Err(error) => {
+                eprintln!("Failed to build the wireframe pipeline {} ({:?}). Error: {:?}", key.0, key.1, error);
+                None
+            }
+        }
+    }
+
+    fn build_pipeline(&self, descriptor: &PipelineDescriptor) -> Result<wgpu::RenderPipeline> {
+        *self.captured_errors.lock().unwrap() = Some(Vec::new());
+        let bone_bind_group_layout = match descriptor.skinned {
+            true => Some(&self.bone_bind_group_layout),
+            false => None,
+        };
+        let pipeline = new_pipeline(&self.device, self.swap_chain_descriptor.format, self.sample_count, &descriptor.vert_shader_name, &descriptor.frag_shader_name, &self.texture_layout, &self.uniform_bind_group_layout, bone_bind_group_layout, descriptor.topology, descriptor.polygon_mode, descriptor.blend_mode, descriptor.depth_test);
+        let errors = self.captured_errors.lock().unwrap().take().unwrap();
+
+        let pipeline = pipeline?;
+        if !errors.is_empty() {
+            bail!("Errors in the pipeline of {} and {}:\n{}", descriptor.vert_shader_name, descriptor.frag_shader_name, errors.join("\n"));
+        }
+
+        Ok(pipeline)
+    }
+
+    // Rebuilds every pipeline from the shader files on disk. Pipelines that fail to build keep their previous version.
+    pub fn reload_shaders(&mut self) {
+        let descriptors : Vec<((String, BlendMode), PipelineDescriptor)> = self.pipeline_descriptors.iter().map(|(key, descriptor)| (key.clone(), descriptor.clone())).collect();
+        for (key, descriptor) in descriptors {
+            match self.build_pipeline(&descriptor) {
+                Ok(pipeline) => {
+                    if let Some(wireframe_pipeline) = self.build_wireframe_pipeline(&key, &descriptor) {
+                        self.wireframe_pipelines.insert(key.clone(), wireframe_pipeline);
+                    }
+                    self.pipelines.insert(key, pipeline);
+                },
+                Err(error) => eprintln!("Failed to reload the pipeline {} ({:?}). Keeping the previous one. Error: {:?}", key.0, key.1, error),
+            }
+        }
+    }
+
+    // Builds every registered pipeline and its wireframe version without replacing the current ones
+    fn build_pipelines(&self) -> Result<Vec<((String, BlendMode), wgpu::RenderPipeline, Option<wgpu::RenderPipeline>)>> {
+        self.pipeline_descriptors.iter().map(|(key, descriptor)| {
+            let pipeline = self.build_pipeline(descriptor).with_context(|| format!("Failed to build the pipeline {} ({:?})", key.0, key.1))?;
+            Ok((key.clone(), pipeline, self.build_wireframe_pipeline(key, descriptor)))
+        }).collect()
+    }
+
+    pub fn supports_wireframe(&self) -> bool {
+        self.device.features().contains(wgpu::Features::NON_FILL_POLYGON_MODE)
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    // While enabled pipeline returns the wireframe version of the pipelines that have one
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && !self.supports_wireframe() {
+            eprintln!("Wireframe rendering is not supported by the adapter");
+            return;
+        }
+
+        self.wireframe = wireframe;
+    }
+
+    pub fn has_pipeline(&self, name: &str, blend_mode: BlendMode) -> bool {
+        self.pipelines.contains_key(&(name.to_owned(), blend_mode))
+    }
+
+    pub fn pipeline(&self, name: &str, blend_mode: BlendMode) -> Option<&wgpu::RenderPipeline> {
+        let key = (name.to_owned(), blend_mode);
+        match self.wireframe {
+            true => self.wireframe_pipelines.get(&key).or_else(|| self.pipelines.get(&key)),
+            false => self.pipelines.get(&key),
+        }
+    }
+
+    // Builds the pipeline of a registered shader for another blend mode, if it wasn't built yet.
+    pub fn load_pipeline(&mut self, name: &str, blend_mode: BlendMode) -> Result<()> {
+        if self.has_pipeline(name, blend_mode) {
+            return Ok(());
+        }
+
+        let descriptor = self.pipeline_descriptors.iter()
+            .find(|((descriptor_name, _), _)| descriptor_name == name)
+            .map(|(_, descriptor)| PipelineDescriptor { blend_mode, ..descriptor.clone() })
+            .with_context(|| format!("No pipeline named {} is registered", name))?;
+
+        self.add_pipeline(name, descriptor)
+    }
+
+    // Adds a reference to the texture, uploading it if it isn't loaded. Release it with assets.unload. The color space is the
+    // one of the first load, a texture loaded again with another color space keeps it. A texture that can't be loaded isn't
+    // loaded again until it is unloaded, the renderer draws the placeholder instead.
+    pub fn load_texture(&mut self, texture_name: &str, color_space: ColorSpace) -> Handle<TextureBindGroups> {
+        let (device, queue, texture_layout, samplers) = (&self.device, &self.queue, &self.texture_layout, &self.samplers);
+        self.assets.load(texture_name, |name| upload_texture_to_gpu(name, color_space, device, queue, texture_layout, samplers))
+    }
+
+    // Decodes the texture on a worker thread. finish_loads uploads it and adds a reference like load_texture.
+    pub fn load_texture_async(&mut self, texture_name: &str, color_space: ColorSpace) {
+        self.async_color_spaces.insert(texture_name.to_owned(), color_space);
+        self.texture_loader.request(texture_name, Texture::read_rgba);
+    }
+
+    // Uploads the textures decoded since the last call. Called every frame by the game loop.
+    pub fn finish_loads(&mut self) {
+        for (texture_name, rgba) in self.texture_loader.poll() {
+            let color_space = self.async_color_spaces.remove(&texture_name).unwrap_or_default();
+            let (device, queue, texture_layout, samplers) = (&self.device, &self.queue, &self.texture_layout, &self.samplers);
+            self.assets.load(&texture_name, |name| {
+                let rgba = rgba?;
+                let texture = Texture::from_rgba(&rgba, rgba.dimensions(), name, color_space, device, queue);
+                Ok(create_texture_bind_groups(&texture, name, device, texture_layout, samplers))
+            });
+        }
+    }
+
+    // Fraction of the textures requested with load_texture_async that are loaded, in the range [0, 1]
+    pub fn load_progress(&self) -> f32 {
+        self.texture_loader.progress()
+    }
+
+    // Adds a reference to the obj model, uploading it if it isn't loaded. Release it with assets.unload. Names of the form
+    // "<model>#<primitive>" refer to a static primitive of a gltf or glb model, see load_gltf_primitive.
+    pub fn load_mesh(&mut self, mesh_name: &str) -> Handle<Mesh> {
+        if gltf_model_name(mesh_name).is_some() {
+            return self.load_gltf_primitive(mesh_name);
+        }
+
+        let device = &self.device;
+        self.assets.load(mesh_name, |name| {
+            let mut mesh = load_model(name)?;
+            mesh.upload_to_gpu(device);
+            Ok(mesh)
+        })
+    }
+
+    // Skinned meshes only come from gltf and glb models, so the name must be of the form "<model>#<primitive>"
+    pub fn load_skinned_mesh(&mut self, mesh_name: &str) -> Handle<Mesh<SkinnedVertex>> {
+        self.load_gltf_primitive(mesh_name)
+    }
+
+    // Loads the whole model with load_gltf the first time one of its primitives is used, which also registers its textures.
+    // The reference added by load_gltf is the one of this load, the other primitives and textures keep theirs until unloaded.
+    fn load_gltf_primitive<V: 'static>(&mut self, mesh_name: &str) -> Handle<Mesh<V>> {
+        if self.assets.find::<Mesh<V>>(mesh_name).is_none() {
+            if let Some(model_name) = gltf_model_name(mesh_name) {
+                if let Err(error) = self.load_gltf(model_name) {
+                    return self.assets.load(mesh_name, |_| Err(error));
+                }
+
+                if let Some(handle) = self.assets.find::<Mesh<V>>(mesh_name) {
+                    return handle;
+                }
+            }
+        }
+
+        // Skinned primitives aren't static meshes and the other way around
+        self.assets.load(mesh_name, |name| bail!("{} isn't a primitive of this kind of a gltf or glb model", name))
+    }
+
+    // Uploads the primitives and textures of a gltf or glb file of the models directory. Primitives are registered as
+    // "<model>#<primitive>" meshes and textures as "<model>#texture<image>", numbered in the order they appear in the file.
+    // The skeleton, if any, is for the Skeleton component of the entities drawing the skinned primitives.
+    pub fn load_gltf(&mut self, model_name: &str) -> Result<(Vec<GltfPrimitive>, Option<Skeleton>)> {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("src\\resources\\models");
+        path.push(model_name);
+
+        let model = load_gltf(&path).with_context(|| format!("Failed to load the model: {:?}", path))?;
+
+        let texture_names : Vec<String> = (0..model.textures.len()).map(|index| format!("{}#texture{}", model_name, index)).collect();
+        for (rgba, texture_name) in model.textures.iter().zip(&texture_names) {
+            // Only base color textures are loaded
+            let texture = Texture::from_rgba(rgba, rgba.dimensions(), texture_name, ColorSpace::Srgb, &self.device, &self.queue);
+            let bind_groups = create_texture_bind_groups(&texture, texture_name, &self.device, &self.texture_layout, &self.samplers);
+            self.assets.insert(texture_name, bind_groups);
+        }
+
+        let mut primitives = Vec::new();
+        for (index, (mesh, diffuse_texture)) in model.primitives.into_iter().enumerate() {
+            let mesh_name = format!("{}#{}", model_name, index);
+            let skinned = match mesh {
+                GltfMesh::Static(mut mesh) => {
+                    mesh.upload_to_gpu(&self.device);
+                    self.assets.insert(&mesh_name, mesh);
+                    false
+                },
+                GltfMesh::Skinned(mut mesh) => {
+                    mesh.upload_to_gpu(&self.device);
+                    self.assets.insert(&mesh_name, mesh);
+                    true
+                },
+            };
+
+            primitives.push(GltfPrimitive {
+                mesh_name,
+                diffuse_texture: diffuse_texture.map(|index| texture_names[index].clone()),
+                skinned,
+            });
+        }
+
+        Ok((primitives, model.skeleton))
+    }
+
+    // Creates the textures of a render target, or recreates them if its size changed or its texture was unloaded. The color
+    // texture is added to the textures under the name of the target so meshes can sample it.
+    pub fn prepare_texture_target(&mut self, name: &str, size: (u32, u32)) {
+        let size = (size.0.max(1), size.1.max(1));
+        if let Some(target) = self.texture_targets.get(name) {
+            if target.size == size && self.assets.get_by_name::<TextureBindGroups>(name).is_some() {
+                return;
+            }
+        }
+
+        let descriptor = wgpu::SwapChainDescriptor {
+            width: size.0,
+            height: size.1,
+            ..self.swap_chain_descriptor.clone()
+        };
+        let color = Texture::create_render_target(&self.device, &descriptor, 1, name);
+        let multisampled = self.create_multisampled_texture(&descriptor, name);
+        let depth = Texture::create_depth_texture(&self.device, &descriptor, self.sample_count, name);
+        self.assets.insert(name, create_texture_bind_groups(&color, name, &self.device, &self.texture_layout, &self.samplers));
+        self.texture_targets.insert(name.to_owned(), TextureTarget { color, multisampled, depth, size });
+    }
+
+    // Drops the render targets that aren't kept, with their texture, e.g. once the camera drawing into them is destroyed
+    pub fn retain_texture_targets<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        let removed : Vec<String> = self.texture_targets.keys().filter(|name| !keep(name)).cloned().collect();
+        for name in removed {
+            self.texture_targets.remove(&name);
+            // prepare_texture_target adds a reference each time it recreates the target
+            while let Some(handle) = self.assets.find::<TextureBindGroups>(&name) {
+                self.assets.unload(handle);
+            }
+        }
+    }
+
+    pub fn texture_target(&self, name: &str) -> Option<&TextureTarget> {
+        self.texture_targets.get(name)
+    }
+
+    // None if the texture isn't loaded or failed to load
+    pub fn texture(&self, texture_name: &str, filter: TextureFilter) -> Option<&wgpu::BindGroup> {
+        self.assets.get_by_name::<TextureBindGroups>(texture_name).map(|bind_groups| bind_groups.get(filter))
+    }
+
+    pub fn mesh(&self, mesh_name: &str) -> Option<&Mesh> {
+        self.assets.get_by_name(mesh_name)
+    }
+
+    // Skinned meshes are only created by load_gltf
+    pub fn skinned_mesh(&self, mesh_name: &str) -> Option<&Mesh<SkinnedVertex>> {
+        self.assets.get_by_name(mesh_name)
+    }
+
+    // Binds the palette at offset 0 of the buffer. The palette at index i of pack_bone_palettes is bound with the dynamic
+    // offset i*BONE_PALETTE_SIZE.
+    pub fn create_bone_bind_group(&self, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bone_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(BONE_PALETTE_SIZE),
+                    }),
+                }
+            ],
+            label: Some("bone_bind_group"),
+        })
+    }
+
+    // The mode in use, which is Fifo when the requested one isn't supported
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.swap_chain_descriptor.present_mode
+    }
+
+    // Modes the target can present with. Offscreen targets aren't presented and only list Fifo.
+    pub fn present_modes(&self) -> &[wgpu::PresentMode] {
+        match &self.target {
+            RenderTarget::Window { present_modes, .. } => present_modes,
+            RenderTarget::Offscreen(_) => &[wgpu::PresentMode::Fifo],
+        }
+    }
+
+    // Falls back to Fifo if the target doesn't support the mode. Returns the mode in use. Takes effect right away, unless the
+    // window has a zero size in which case the swap chain is created with it when the window is restored.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) -> wgpu::PresentMode {
+        let present_mode = match self.present_modes().contains(&present_mode) {
+            true => present_mode,
+            false => wgpu::PresentMode::Fifo,
+        };
+
+        if self.swap_chain_descriptor.present_mode != present_mode {
+            self.swap_chain_descriptor.present_mode = present_mode;
+            if !self.is_zero_sized() {
+                self.create_target();
+            }
+        }
+
+        present_mode
+    }
+
+    fn create_target(&mut self) {
+        match &mut self.target {
+            RenderTarget::Window { surface, swap_chain, .. } => *swap_chain = self.device.create_swap_chain(surface, &self.swap_chain_descriptor),
+            RenderTarget::Offscreen(texture) => *texture = create_offscreen_texture(&self.device, &self.swap_chain_descriptor),
+        }
+    }
+
+    fn get_current_frame(&self) -> Result<Frame, wgpu::SwapChainError> {
+        match &self.target {
+            RenderTarget::Window { swap_chain, .. } => swap_chain.get_current_frame().map(Frame::Window),
+            RenderTarget::Offscreen(texture) => Ok(Frame::Offscreen(texture.create_view(&wgpu::TextureViewDescriptor::default()))),
+        }
+    }
+
+    // Acquires the frame the systems render to. Called by the game loop once per frame, before running the systems.
+    // No frame is acquired while the window is minimized or if the swap chain fails, in which case nothing is rendered.
+    pub fn begin_frame(&mut self) {
+        if self.is_zero_sized() {
+            return;
+        }
+
+        match self.get_current_frame() {
+            Ok(frame) => self.current_frame = Some(frame),
+            // Recreate the swap_chain if lost
+            Err(wgpu::SwapChainError::Lost) => self.resize(self.size),
+            // All other errors (OOM, Outdated, Timeout) should be resolved by the next frame
+            Err(error) => eprintln!("{:?}", error),
+        }
+    }
+
+    // Presents the frame acquired by begin_frame
+    pub fn end_frame(&mut self) {
+        self.current_frame = None;
+    }
+
+    // Copies the last frame rendered by headless graphics back to the cpu. Fails for window graphics since swap chain textures can't be read.
+    pub fn capture_frame(&self) -> Result<image::RgbaImage> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => self.read_texture(texture),
+            RenderTarget::Window { .. } => bail!("Only headless graphics can capture frames"),
+        }
+    }
+
+    // Saves the frame as a PNG. Headless graphics save the last rendered frame. Window graphics can't read their swap chain,
+    // so the RenderSystem renders the next frame to a capture texture, copies it to the window and saves it.
+    pub fn capture_to_png(&mut self, path: &Path) {
+        match self.target {
+            RenderTarget::Offscreen(_) => {
+                let image = self.capture_frame();
+                self.save_png(image, path);
+            },
+            RenderTarget::Window { .. } => self.capture_request = Some(path.to_owned()),
+        }
+    }
+
+    // Returns the path of the capture requested for the frame being rendered, if any
+    pub fn take_capture_request(&mut self) -> Option<PathBuf> {
+        self.capture_request.take()
+    }
+
+    // A texture compatible with the pipelines that can be read back with save_texture_png
+    pub fn create_capture_texture(&self) -> wgpu::Texture {
+        create_offscreen_texture(&self.device, &self.swap_chain_descriptor)
+    }
+
+    // Draws a texture created by create_capture_texture over the whole frame. The pipeline is created on every call, which
+    // is fine as long as it is only used for captures.
+    pub fn copy_to_frame(&self, texture: &wgpu::Texture, frame: &Frame) -> Result<()> {
+        let vertex_shader = load_shader_module(&self.device, "blit.vert.spv")?;
+        let frag_shader = load_shader_module(&self.device, "blit.frag.spv")?;
+
+        let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&self.texture_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit Pipeline"),
+            layout: Some(&pipeline_layout),
+            // The vertices are generated by the shader
+            vertex: wgpu::VertexState {
+                module: &vertex_shader,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &frag_shader,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format: self.swap_chain_descriptor.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(self.samplers.get(TextureFilter::Nearest)),
+                }
+            ],
+            label: Some("Blit Bind Group"),
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Blit Encoder"),
+        });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Blit Pass"),
+                color_attachments: &[
+                    wgpu::RenderPassColorAttachment {
+                        view: frame.view(),
+                        resolve_target: None,
+                        // Every pixel is drawn over
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }
+                    }
+                ],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+
+    pub fn save_texture_png(&mut self, texture: &wgpu::Texture, path: &Path) {
+        let image = self.read_texture(texture);
+        self.save_png(image, path);
+    }
+
+    // The result is shown as a debug message
+    fn save_png(&mut self, image: Result<image::RgbaImage>, path: &Path) {
+        let message = match image.and_then(|image| image.save(path).with_context(|| format!("Failed to write the file: {:?}", path))) {
+            Ok(()) => format!("Saved the frame to {}", path.display()),
+            Err(error) => {
+                eprintln!("Failed to capture the frame. Error: {:?}", error);
+                "Failed to capture the frame".to_owned()
+            }
+        };
+        self.debug_draw.show_message(message);
+    }
+
+    // Reads a texture of the size of the target with COPY_SRC usage
+    fn read_texture(&self, texture: &wgpu::Texture) -> Result<image::RgbaImage> {
+        // Rows copied to a buffer must be aligned, so they are padded and the padding is removed after reading them
+        let (width, height) = self.size;
+        let unpadded_bytes_per_row = 4*width;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + alignment - 1)/alignment*alignment;
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row*height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).map_err(|error| anyhow::anyhow!("Failed to read the captured frame. Error: {:?}", error))?;
+
+        let padded = slice.get_mapped_range();
+        let pixels : Vec<u8> = padded.chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| row[..unpadded_bytes_per_row as usize].iter().copied())
+            .collect();
+        drop(padded);
+        buffer.unmap();
+
+        let mut image = image::RgbaImage::from_raw(width, height, pixels).context("The captured frame doesn't match its size")?;
+
+        // Swap chains usually prefer a BGRA format
+        if let wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb = self.swap_chain_descriptor.format {
+            for pixel in image.pixels_mut() {
+                pixel.0.swap(0, 2);
+            }
+        }
+
+        Ok(image)
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: wgpu::Color) {
+        self.clear_color = clear_color;
+    }
+
+    // True while the window is minimized. Nothing can be rendered until it is resized again.
+    pub fn is_zero_sized(&self) -> bool {
+        self.size.0 == 0 || self.size.1 == 0
+    }
+
+    // wgpu can't create a swap chain without area, so a zero size keeps the previous swap chain until the window is restored
     pub fn resize(&mut self, new_size: (u32, u32)) {
         self.size = new_size;
+        if self.is_zero_sized() {
+            return;
+        }
+
         self.swap_chain_descriptor.width = new_size.0;
         self.swap_chain_descriptor.height = new_size.1;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.swap_chain_descriptor);
+        self.create_target();
+        self.create_frame_textures();
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Multisampling smooths the edges of meshes and sprites. The pipelines, the frame textures and the render targets are
+    // recreated with the new count. Fails and keeps the current count and pipelines if the count isn't in
+    // SUPPORTED_SAMPLE_COUNTS or a pipeline fails to build with it.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<()> {
+        if !SUPPORTED_SAMPLE_COUNTS.contains(&sample_count) {
+            bail!("Unsupported sample count {}. The supported counts are {:?}", sample_count, SUPPORTED_SAMPLE_COUNTS);
+        }
+
+        if self.sample_count == sample_count {
+            return Ok(());
+        }
+
+        // Every pipeline is built before any is replaced, since pipelines of different counts can't draw in the same pass
+        let previous_sample_count = self.sample_count;
+        self.sample_count = sample_count;
+        let pipelines = match self.build_pipelines() {
+            Ok(pipelines) => pipelines,
+            Err(error) => {
+                self.sample_count = previous_sample_count;
+                return Err(error);
+            }
+        };
+
+        for (key, pipeline, wireframe_pipeline) in pipelines {
+            match wireframe_pipeline {
+                Some(wireframe_pipeline) => self.wireframe_pipelines.insert(key.clone(), wireframe_pipeline),
+                None => self.wireframe_pipelines.remove(&key),
+            };
+            self.pipelines.insert(key, pipeline);
+        }
+
+        // The render targets are recreated by the next prepare_texture_target
+        self.texture_targets.clear();
+        if !self.is_zero_sized() {
+            self.create_frame_textures();
+        }
+
+        Ok(())
+    }
+
+    // Depth and multisampled textures of the size of the frame
+    fn create_frame_textures(&mut self) {
+        self.depth_texture = Texture::create_depth_texture(&self.device, &self.swap_chain_descriptor, self.sample_count, "depth_texture");
+        self.multisampled_texture = self.create_multisampled_texture(&self.swap_chain_descriptor, "multisampled_texture");
+    }
+
+    fn create_multisampled_texture(&self, descriptor: &wgpu::SwapChainDescriptor, label: &str) -> Option<Texture> {
+        match self.sample_count {
+            1 => None,
+            sample_count => Some(Texture::create_render_target(&self.device, descriptor, sample_count, label)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A triangle with a 1x1 red base color texture. The buffer and the image are embedded as data uris.
+    const TRIANGLE_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 44, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA=" }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "images": [{ "uri": "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR4nGP4z8DwHwAFAAH/iZk9HQAAAABJRU5ErkJggg==" }],
+        "textures": [{ "source": 0 }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }]
+    }"#;
+
+    #[test]
+    fn gltf_primitives_keep_their_base_color_texture() {
+        let path = std::env::temp_dir().join("rusty_gear_triangle.gltf");
+        fs::write(&path, TRIANGLE_GLTF).unwrap();
+        let model = load_gltf(&path);
+        fs::remove_file(&path).ok();
+        let model = model.unwrap();
+
+        assert_eq!(model.primitives.len(), 1);
+        match &model.primitives[0] {
+            (GltfMesh::Static(mesh), Some(0)) => {
+                let positions : Vec<[f32; 3]> = mesh.vertices.iter().map(|vertex| vertex.position).collect();
+                assert_eq!(positions, vec!([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]));
+            },
+            _ => panic!("Expected a static primitive using the first texture"),
+        }
+        assert_eq!(model.textures[0].get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert!(model.skeleton.is_none());
+    }
+
+    #[test]
+    fn sample_count_switches_between_one_and_four() {
+        let mut graphics = match futures::executor::block_on(Graphics::new_headless(64, 32)) {
+            Ok(graphics) => graphics,
+            Err(error) => {
+                eprintln!("Skipping the test. {}", error);
+                return;
+            }
+        };
+        let pipeline_count = graphics.pipelines.len();
+
+        graphics.set_sample_count(4).unwrap();
+        assert_eq!(graphics.sample_count(), 4);
+        assert!(graphics.multisampled_texture.is_some());
+        assert_eq!(graphics.pipelines.len(), pipeline_count);
+
+        graphics.set_sample_count(1).unwrap();
+        assert_eq!(graphics.sample_count(), 1);
+        assert!(graphics.multisampled_texture.is_none());
+        assert_eq!(graphics.pipelines.len(), pipeline_count);
+
+        assert!(graphics.set_sample_count(2).is_err());
+        assert_eq!(graphics.sample_count(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn gltf_primitive_names_refer_to_their_model() {
+        assert_eq!(gltf_model_name("robot.glb#2"), Some("robot.glb"));
+        assert_eq!(gltf_model_name("robot.gltf#texture0"), None);
+        assert_eq!(gltf_model_name("cube.obj"), None);
+    }
+}
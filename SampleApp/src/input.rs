@@ -1,35 +1,509 @@
 use sdl2::EventPump;
-use sdl2::keyboard::Scancode;
-use sdl2::keyboard::KeyboardState;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{Scancode, TextInputUtil};
+use sdl2::mouse::MouseButton;
+use crate::input_map::{Binding, InputMap};
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::hash::Hash;
 
+// Axis values with a magnitude below the dead zone are reported as zero to ignore stick drift
+const AXIS_DEAD_ZONE: f32 = 0.1;
+
+// The SDL joystick instance id of a connected controller
+pub type ControllerId = u32;
+
+// Sent to the systems through the ECS events when the window is resized. The render system resizes the graphics.
+pub struct WindowResized {
+    pub width: u32,
+    pub height: u32,
+}
+
+// What the gameplay systems read from the input, so they can be tested with a MockInput instead of SDL
+pub trait InputState {
+    fn is_key_pressed(&self, key: Scancode) -> bool;
+    fn is_key_down(&self, key: Scancode) -> bool;
+    fn is_key_up(&self, key: Scancode) -> bool;
+    // Like is_key_pressed for any of the keys or buttons bound to the action
+    fn is_action_pressed(&self, action: &str) -> bool;
+    fn is_action_down(&self, action: &str) -> bool;
+    fn is_action_up(&self, action: &str) -> bool;
+    // The controller with the lowest id, used by single player code
+    fn first_controller(&self) -> Option<ControllerId>;
+    // Returns the axis position in the range [-1, 1], or 0 if the controller is not connected.
+    fn axis(&self, controller: ControllerId, axis: Axis) -> f32;
+}
+
+// Keys, mouse buttons and controller buttons all report the same states. Pressed means held this frame, down means pressed
+// this frame but not the previous one and up means released this frame. Actions are pressed, down or up like their bindings
+// as a whole, so pressing a second key of an action already held isn't a new down.
 pub struct Input {
-    current_pressed_keys: HashSet<Scancode>,
-    previous_pressed_keys: HashSet<Scancode>,
+    keys: PressedStates<Scancode>,
+    mouse_buttons: PressedStates<MouseButton>,
+    mouse_position: (i32, i32),
+    mouse_delta: (i32, i32),
+    wheel_delta: i32,
+    game_controller_subsystem: GameControllerSubsystem,
+    controllers: HashMap<ControllerId, GameController>,
+    buttons: PressedStates<(ControllerId, Button)>,
+    input_map: InputMap,
+    text_input_util: TextInputUtil,
+    text: TextBuffer,
+}
+
+// What is pressed in the current and the previous frame
+struct PressedStates<T> {
+    current: HashSet<T>,
+    previous: HashSet<T>,
+}
+
+impl<T: Eq + Hash + Clone> PressedStates<T> {
+    // Nothing is down in the first frame
+    fn new(pressed: HashSet<T>) -> Self {
+        Self {
+            previous: pressed.clone(),
+            current: pressed,
+        }
+    }
+
+    // Starts a new frame in which the given items are pressed
+    fn update(&mut self, pressed: HashSet<T>) {
+        self.previous = std::mem::replace(&mut self.current, pressed);
+    }
+
+    fn reset_edges(&mut self) {
+        self.previous = self.current.clone();
+    }
+
+    fn is_pressed(&self, item: &T) -> bool {
+        self.current.contains(item)
+    }
+
+    fn is_down(&self, item: &T) -> bool {
+        self.current.contains(item) && !self.previous.contains(item)
+    }
+
+    fn is_up(&self, item: &T) -> bool {
+        !self.current.contains(item) && self.previous.contains(item)
+    }
+}
+
+// True if any of the bindings is pressed in the given sets, the buttons being of any controller
+fn is_any_binding_pressed(bindings: &[Binding], keys: &HashSet<Scancode>, buttons: &HashSet<(ControllerId, Button)>) -> bool {
+    bindings.iter().any(|binding| match binding {
+        Binding::Key(key) => keys.contains(key),
+        Binding::Button(button) => buttons.iter().any(|(_, pressed)| pressed == button),
+    })
+}
+
+// Whether any binding of an action is pressed in the current and the previous frame, so actions are down and up like keys
+struct ActionState {
+    current: bool,
+    previous: bool,
+}
+
+impl ActionState {
+    fn new(bindings: &[Binding], keys: &PressedStates<Scancode>, buttons: &PressedStates<(ControllerId, Button)>) -> Self {
+        Self {
+            current: is_any_binding_pressed(bindings, &keys.current, &buttons.current),
+            previous: is_any_binding_pressed(bindings, &keys.previous, &buttons.previous),
+        }
+    }
+
+    fn is_pressed(&self) -> bool {
+        self.current
+    }
+
+    fn is_down(&self) -> bool {
+        self.current && !self.previous
+    }
+
+    fn is_up(&self) -> bool {
+        !self.current && self.previous
+    }
+}
+
+// Text typed while text input is active. The IME sends the text being composed, e.g. the syllables of a word before it is
+// converted to kanji, separately from the committed text.
+#[derive(Default)]
+struct TextBuffer {
+    committed: String,
+    composition: String,
+}
+
+impl TextBuffer {
+    fn commit(&mut self, text: &str) {
+        self.committed.push_str(text);
+        self.composition.clear();
+    }
+
+    fn compose(&mut self, text: &str) {
+        self.composition.clear();
+        self.composition.push_str(text);
+    }
+
+    // While composing, the IME handles backspace itself and sends the shortened composition
+    fn backspace(&mut self) {
+        if self.composition.is_empty() {
+            self.committed.pop();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.committed.clear();
+        self.composition.clear();
+    }
 }
 
 impl Input {
-    pub fn new(eventPump: &EventPump) -> Self {
+    // Controllers connected at startup are reported by SDL as device added events, so they are opened by handle_event too.
+    pub fn new(eventPump: &EventPump, gameControllerSubsystem: GameControllerSubsystem, textInputUtil: TextInputUtil) -> Self {
+        let mouse_state = eventPump.mouse_state();
+        // SDL starts with text input enabled, which opens the on-screen keyboard on some platforms
+        textInputUtil.stop();
+
         Self {
-            current_pressed_keys: eventPump.keyboard_state().pressed_scancodes().collect(),
-            previous_pressed_keys: eventPump.keyboard_state().pressed_scancodes().collect()
+            keys: PressedStates::new(eventPump.keyboard_state().pressed_scancodes().collect()),
+            mouse_buttons: PressedStates::new(mouse_state.pressed_mouse_buttons().collect()),
+            mouse_position: (mouse_state.x(), mouse_state.y()),
+            mouse_delta: (0, 0),
+            wheel_delta: 0,
+            game_controller_subsystem: gameControllerSubsystem,
+            controllers: HashMap::new(),
+            buttons: PressedStates::new(HashSet::new()),
+            input_map: InputMap::new(),
+            text_input_util: textInputUtil,
+            text: TextBuffer::default(),
+        }
+    }
+
+    // Starts a new frame. Must be called exactly once per frame, before the events of the frame are handled and before any
+    // query, otherwise the down and up states last for more or less than one frame.
+    pub fn update(&mut self, eventPump: &EventPump) {
+        self.keys.update(eventPump.keyboard_state().pressed_scancodes().collect());
+
+        let mouse_state = eventPump.mouse_state();
+        self.mouse_buttons.update(mouse_state.pressed_mouse_buttons().collect());
+
+        let new_position = (mouse_state.x(), mouse_state.y());
+        self.mouse_delta = (new_position.0 - self.mouse_position.0, new_position.1 - self.mouse_position.1);
+        self.mouse_position = new_position;
+        self.wheel_delta = 0;
+
+        // The controller buttons are changed by the events of the frame
+        self.buttons.update(self.buttons.current.clone());
+    }
+
+    // Makes the current state the previous one so no key or button is reported as down or up this frame
+    pub fn reset_edges(&mut self) {
+        self.keys.reset_edges();
+        self.mouse_buttons.reset_edges();
+        self.buttons.reset_edges();
+        self.mouse_delta = (0, 0);
+    }
+
+    // Must be called for every polled event after update so the events of the frame are accumulated.
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::TextInput { text, .. } => {
+                self.text.commit(text);
+            },
+            Event::TextEditing { text, .. } => {
+                self.text.compose(text);
+            },
+            // Repeated key downs erase one character each while backspace is held
+            Event::KeyDown { scancode: Some(Scancode::Backspace), .. } if self.is_text_input_active() => {
+                self.text.backspace();
+            },
+            Event::MouseWheel { y, .. } => {
+                self.wheel_delta += y;
+            },
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.game_controller_subsystem.open(*which) {
+                    Ok(controller) => { self.controllers.insert(controller.instance_id(), controller); },
+                    Err(error) => eprintln!("Failed to open the game controller {}. Error: {}", which, error),
+                }
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.remove(which);
+                self.buttons.current.retain(|(controller, _)| controller != which);
+            },
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.buttons.current.insert((*which, *button));
+            },
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.buttons.current.remove(&(*which, *button));
+            },
+            _ => {}
         }
     }
 
-    pub fn update(&mut self, newKeyboardState: &KeyboardState) {
-        std::mem::swap(&mut self.current_pressed_keys, &mut self.previous_pressed_keys);
-        self.current_pressed_keys = newKeyboardState.pressed_scancodes().collect();
+    // Clears the text typed so far. The typed text is received until stop_text_input is called.
+    pub fn start_text_input(&mut self) {
+        self.text.clear();
+        self.text_input_util.start();
+    }
+
+    // The typed text stays available until text input is started again. Text still being composed is discarded.
+    pub fn stop_text_input(&mut self) {
+        self.text_input_util.stop();
+        self.text.composition.clear();
+    }
+
+    pub fn is_text_input_active(&self) -> bool {
+        self.text_input_util.is_active()
+    }
+
+    pub fn text_input(&self) -> &str {
+        &self.text.committed
+    }
+
+    // Text being composed with the IME, shown after the typed text but not part of it yet
+    pub fn text_composition(&self) -> &str {
+        &self.text.composition
+    }
+
+    pub fn input_map(&self) -> &InputMap {
+        &self.input_map
+    }
+
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.input_map = input_map;
+    }
+
+    pub fn mouse_position(&self) -> (i32, i32) {
+        self.mouse_position
     }
 
-    pub fn is_key_pressed(&self, key: Scancode) -> bool {
-        self.current_pressed_keys.contains(&key)
+    pub fn mouse_delta(&self) -> (i32, i32) {
+        self.mouse_delta
     }
 
-    pub fn is_key_down(&self, key: Scancode) -> bool {
-        self.current_pressed_keys.contains(&key) && !self.previous_pressed_keys.contains(&key)
+    pub fn wheel_delta(&self) -> i32 {
+        self.wheel_delta
     }
 
-    pub fn is_key_up(&self, key: Scancode) -> bool {
-        !self.current_pressed_keys.contains(&key) && self.previous_pressed_keys.contains(&key)
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.is_pressed(&button)
     }
-}
\ No newline at end of file
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.is_down(&button)
+    }
+
+    pub fn is_mouse_button_up(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.is_up(&button)
+    }
+
+    pub fn controllers(&self) -> impl Iterator<Item = ControllerId> + '_ {
+        self.controllers.keys().copied()
+    }
+
+    pub fn is_button_pressed(&self, controller: ControllerId, button: Button) -> bool {
+        self.buttons.is_pressed(&(controller, button))
+    }
+
+    pub fn is_button_down(&self, controller: ControllerId, button: Button) -> bool {
+        self.buttons.is_down(&(controller, button))
+    }
+
+    pub fn is_button_up(&self, controller: ControllerId, button: Button) -> bool {
+        self.buttons.is_up(&(controller, button))
+    }
+
+    fn action_state(&self, action: &str) -> ActionState {
+        ActionState::new(self.input_map.bindings(action), &self.keys, &self.buttons)
+    }
+}
+
+impl InputState for Input {
+    fn is_key_pressed(&self, key: Scancode) -> bool {
+        self.keys.is_pressed(&key)
+    }
+
+    fn is_key_down(&self, key: Scancode) -> bool {
+        self.keys.is_down(&key)
+    }
+
+    fn is_key_up(&self, key: Scancode) -> bool {
+        self.keys.is_up(&key)
+    }
+
+    fn is_action_pressed(&self, action: &str) -> bool {
+        self.action_state(action).is_pressed()
+    }
+
+    fn is_action_down(&self, action: &str) -> bool {
+        self.action_state(action).is_down()
+    }
+
+    fn is_action_up(&self, action: &str) -> bool {
+        self.action_state(action).is_up()
+    }
+
+    fn first_controller(&self) -> Option<ControllerId> {
+        self.controllers.keys().min().copied()
+    }
+
+    fn axis(&self, controller: ControllerId, axis: Axis) -> f32 {
+        match self.controllers.get(&controller) {
+            Some(controller) => {
+                let value = (controller.axis(axis) as f32/i16::MAX as f32).max(-1.0);
+                if value.abs() < AXIS_DEAD_ZONE { 0.0 } else { value }
+            },
+            None => 0.0
+        }
+    }
+}
+
+// Input for tests. Actions are pressed by name, or like Input through the keys bound to them in the input map. The axes set
+// are those of controller 0.
+#[cfg(test)]
+pub struct MockInput {
+    keys: PressedStates<Scancode>,
+    actions: PressedStates<String>,
+    axes: HashMap<Axis, f32>,
+    input_map: InputMap,
+}
+
+#[cfg(test)]
+impl Default for MockInput {
+    fn default() -> Self {
+        Self {
+            keys: PressedStates::new(HashSet::new()),
+            actions: PressedStates::new(HashSet::new()),
+            axes: HashMap::new(),
+            input_map: InputMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl MockInput {
+    // Like Input::update, the keys and actions pressed stay pressed
+    pub fn next_frame(&mut self) {
+        self.keys.update(self.keys.current.clone());
+        self.actions.update(self.actions.current.clone());
+    }
+
+    pub fn set_key_pressed(&mut self, key: Scancode, pressed: bool) {
+        match pressed {
+            true => self.keys.current.insert(key),
+            false => self.keys.current.remove(&key),
+        };
+    }
+
+    pub fn set_action_pressed(&mut self, action: &str, pressed: bool) {
+        match pressed {
+            true => self.actions.current.insert(action.to_owned()),
+            false => self.actions.current.remove(action),
+        };
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: f32) {
+        self.axes.insert(axis, value);
+    }
+
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.input_map = input_map;
+    }
+
+    // The mock has no controller buttons
+    fn action_state(&self, action: &str) -> ActionState {
+        ActionState::new(self.input_map.bindings(action), &self.keys, &PressedStates::new(HashSet::new()))
+    }
+}
+
+#[cfg(test)]
+impl InputState for MockInput {
+    fn is_key_pressed(&self, key: Scancode) -> bool {
+        self.keys.is_pressed(&key)
+    }
+
+    fn is_key_down(&self, key: Scancode) -> bool {
+        self.keys.is_down(&key)
+    }
+
+    fn is_key_up(&self, key: Scancode) -> bool {
+        self.keys.is_up(&key)
+    }
+
+    fn is_action_pressed(&self, action: &str) -> bool {
+        self.actions.is_pressed(&action.to_owned()) || self.action_state(action).is_pressed()
+    }
+
+    fn is_action_down(&self, action: &str) -> bool {
+        self.actions.is_down(&action.to_owned()) || self.action_state(action).is_down()
+    }
+
+    fn is_action_up(&self, action: &str) -> bool {
+        self.actions.is_up(&action.to_owned()) || self.action_state(action).is_up()
+    }
+
+    fn first_controller(&self) -> Option<ControllerId> {
+        match self.axes.is_empty() {
+            true => None,
+            false => Some(0),
+        }
+    }
+
+    fn axis(&self, controller: ControllerId, axis: Axis) -> f32 {
+        match controller {
+            0 => self.axes.get(&axis).copied().unwrap_or(0.0),
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backspace_erases_committed_text_only() {
+        let mut text = TextBuffer::default();
+        text.commit("héllo");
+        text.backspace();
+        assert_eq!(text.committed, "héll");
+
+        text.compose("にほ");
+        text.backspace();
+        assert_eq!(text.committed, "héll");
+
+        text.commit("日本");
+        assert_eq!(text.committed, "héll日本");
+        assert!(text.composition.is_empty());
+    }
+
+    #[test]
+    fn down_and_up_last_one_frame() {
+        let mut input_map = InputMap::new();
+        input_map.bind("thrust", Binding::Key(Scancode::W));
+        input_map.bind("thrust", Binding::Key(Scancode::Up));
+        let mut input = MockInput::default();
+        input.set_input_map(input_map);
+
+        input.next_frame();
+        input.set_key_pressed(Scancode::W, true);
+        assert!(input.is_key_pressed(Scancode::W) && input.is_key_down(Scancode::W));
+        assert!(input.is_action_down("thrust"));
+
+        // Held for a second frame, with another key of the action pressed
+        input.next_frame();
+        input.set_key_pressed(Scancode::Up, true);
+        assert!(input.is_key_pressed(Scancode::W) && !input.is_key_down(Scancode::W));
+        assert!(input.is_key_down(Scancode::Up));
+        assert!(input.is_action_pressed("thrust") && !input.is_action_down("thrust"));
+
+        input.next_frame();
+        input.set_key_pressed(Scancode::W, false);
+        input.set_key_pressed(Scancode::Up, false);
+        assert!(input.is_key_up(Scancode::W) && input.is_key_up(Scancode::Up));
+        assert!(input.is_action_up("thrust"));
+
+        input.next_frame();
+        assert!(!input.is_key_up(Scancode::W) && !input.is_action_up("thrust"));
+    }
+}
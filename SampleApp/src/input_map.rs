@@ -0,0 +1,125 @@
+use anyhow::{anyhow, bail, Result};
+use sdl2::controller::Button;
+use sdl2::keyboard::Scancode;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(Scancode),
+    // A button of any connected controller
+    Button(Button),
+}
+
+// Maps named actions to the keys and controller buttons that trigger them, so gameplay code doesn't depend on physical keys
+pub struct InputMap {
+    actions: HashMap<String, Vec<Binding>>,
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        Self {
+            actions: HashMap::new(),
+        }
+    }
+
+    // The ship controls, used when config/controls.json can't be read so the game stays playable
+    pub fn default_controls() -> Self {
+        let mut input_map = InputMap::new();
+        let actions : [(&str, &[Scancode]); 6] = [
+            ("thrust", &[Scancode::W, Scancode::Up]),
+            ("reverse", &[Scancode::S, Scancode::Down]),
+            ("rotate_left", &[Scancode::A, Scancode::Left]),
+            ("rotate_right", &[Scancode::D, Scancode::Right]),
+            ("strafe_left", &[Scancode::Q]),
+            ("strafe_right", &[Scancode::E]),
+        ];
+
+        for (action, keys) in actions.iter() {
+            for key in keys.iter() {
+                input_map.bind(action, Binding::Key(*key));
+            }
+        }
+
+        input_map
+    }
+
+    // Expects an object of actions, each one with optional "keys" and "buttons" arrays of SDL key and button names:
+    // { "thrust": { "keys": ["W", "Up"], "buttons": ["dpup"] } }
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        let mut input_map = InputMap::new();
+        let actions = json.as_object().ok_or_else(|| anyhow!("Expected an object of actions"))?;
+
+        for (action, bindings) in actions {
+            for key in bindings["keys"].as_array().into_iter().flatten() {
+                let name = key.as_str().ok_or_else(|| anyhow!("The keys of the action {} must be key names, not {}", action, key))?;
+                match Scancode::from_name(name) {
+                    Some(key) => input_map.bind(action, Binding::Key(key)),
+                    None => bail!("Unknown key {} bound to the action {}", name, action),
+                }
+            }
+
+            for button in bindings["buttons"].as_array().into_iter().flatten() {
+                let name = button.as_str().ok_or_else(|| anyhow!("The buttons of the action {} must be button names, not {}", action, button))?;
+                match Button::from_string(name) {
+                    Some(button) => input_map.bind(action, Binding::Button(button)),
+                    None => bail!("Unknown button {} bound to the action {}", name, action),
+                }
+            }
+        }
+
+        Ok(input_map)
+    }
+
+    // Adds a binding to the action. An action can have any number of bindings.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind_all(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    // Empty if the action isn't bound
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        match self.actions.get(action) {
+            Some(bindings) => bindings,
+            None => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_can_have_several_bindings() {
+        let input_map = InputMap::from_json(&serde_json::json!({
+            "thrust": { "keys": ["W", "Up"], "buttons": ["dpup"] }
+        })).unwrap();
+
+        assert_eq!(input_map.bindings("thrust"), &[Binding::Key(Scancode::W), Binding::Key(Scancode::Up), Binding::Button(Button::DPadUp)]);
+        assert!(input_map.bindings("fire").is_empty());
+    }
+
+    #[test]
+    fn default_controls_match_the_shipped_config() {
+        let path : std::path::PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "resources", "config", "controls.json"].iter().collect();
+        let json : serde_json::Value = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        let shipped = InputMap::from_json(&json).unwrap();
+
+        let default_controls = InputMap::default_controls();
+        for action in json.as_object().unwrap().keys() {
+            assert_eq!(default_controls.bindings(action), shipped.bindings(action), "{} is bound differently", action);
+        }
+        assert_eq!(default_controls.actions.len(), shipped.actions.len());
+    }
+
+    #[test]
+    fn unknown_key_is_an_error() {
+        assert!(InputMap::from_json(&serde_json::json!({ "thrust": { "keys": ["NotAKey"] } })).is_err());
+    }
+}
@@ -1,16 +1,23 @@
 #![allow(non_snake_case)]
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use futures::executor::block_on;
 
 mod graphics;
 mod input;
+mod input_map;
 mod resources;
+mod asset_manager;
+mod async_loader;
 mod ecs;
 mod texture;
+mod font;
+mod debug_draw;
 mod entity;
+mod audio;
+mod spatial;
 
 #[path= "components\\component.rs"]
 mod component;
@@ -22,6 +29,34 @@ mod controller;
 mod camera;
 #[path= "components\\mesh.rs"]
 mod mesh;
+#[path= "components\\collider.rs"]
+mod collider;
+#[path= "components\\rigid_body.rs"]
+mod rigid_body;
+#[path= "components\\text.rs"]
+mod text;
+#[path= "components\\light.rs"]
+mod light;
+#[path= "components\\parent.rs"]
+mod parent;
+#[path= "components\\lifetime.rs"]
+mod lifetime;
+#[path= "components\\spawner.rs"]
+mod spawner;
+#[path= "components\\sprite_animation.rs"]
+mod sprite_animation;
+#[path= "components\\sound_emitter.rs"]
+mod sound_emitter;
+#[path= "components\\camera_shake.rs"]
+mod camera_shake;
+#[path= "components\\skeleton.rs"]
+mod skeleton;
+#[path= "components\\transform_2d.rs"]
+mod transform_2d;
+#[path= "components\\tilemap.rs"]
+mod tilemap;
+#[path= "components\\visible.rs"]
+mod visible;
 
 #[path= "systems\\system.rs"]
 mod system;
@@ -29,54 +64,185 @@ mod system;
 mod render;
 #[path= "systems\\control.rs"]
 mod control;
+#[path= "systems\\collision.rs"]
+mod collision;
+#[path= "systems\\camera_control.rs"]
+mod camera_control;
+#[path= "systems\\physics.rs"]
+mod physics;
+#[path= "systems\\expiry.rs"]
+mod expiry;
+#[path= "systems\\spawn.rs"]
+mod spawn;
+#[path= "systems\\animation.rs"]
+mod animation;
+#[path= "systems\\sound.rs"]
+mod sound;
+#[path= "systems\\camera_shake.rs"]
+mod camera_shake_system;
+#[path= "systems\\skeletal_animation.rs"]
+mod skeletal_animation;
+#[path= "systems\\debug_overlay.rs"]
+mod debug_overlay;
 
 use graphics::Graphics;
 use system::SystemManager;
-use input::Input;
-use resources::Resources;
+use input::{Input, WindowResized};
+use audio::Audio;
+use resources::{Resources, Scene};
+use input_map::InputMap;
 use ecs::*;
+use mesh::Mesh;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+const WINDOW_TITLE: &str = "Sample";
+const MAX_FRAME_TIME: f64 = 0.25;
+// Sleeping is not precise so the last part of the frame is spin-waited
+const SPIN_WAIT_TIME: Duration = Duration::from_micros(1000);
+// Frame rate while the game is paused so it doesn't burn cpu in the background
+const PAUSED_FPS: u16 = 10;
+// Samples per pixel while multisampling, the highest count every adapter supports
+const MULTISAMPLE_COUNT: u32 = 4;
 
 struct AppState {
     input: Input,
     graphics: Graphics,
+    audio: Audio,
     start_of_frame: Instant,
     time_elapsed: f64,
     delta_time: f64,
-    target_fps: u16,
+    fixed_delta: f64,
+    accumulator: f64,
+    target_fps: Option<u16>,
+    focused: bool,
+    minimized: bool,
     exit_app: bool,
+    // Window events received by enter_frame, sent to the systems once the events of the previous frame are cleared
+    window_resizes: Vec<WindowResized>,
 }
 
 impl AppState {
-    pub fn new(input: Input, graphics: Graphics, target_fps: Option<u16>) -> AppState {
+    pub fn new(input: Input, graphics: Graphics, audio: Audio, target_fps: Option<u16>) -> AppState {
         let fps = target_fps.unwrap_or(60);
         AppState {
             input: input,
             graphics: graphics,
+            audio: audio,
             start_of_frame: Instant::now(),
             time_elapsed: 0.0,
-            target_fps: fps,
+            target_fps: Some(fps),
             delta_time: 1.0/(fps as f64),
-            exit_app: false
+            fixed_delta: 1.0/120.0,
+            accumulator: 0.0,
+            focused: true,
+            minimized: false,
+            exit_app: false,
+            window_resizes: Vec::new(),
         }
     }
 
-    pub fn TargetRefreshRate(&self) -> f64 {
-        1.0/(self.target_fps as f64)
+    // None or zero uncaps the frame rate
+    pub fn set_target_fps(&mut self, target_fps: Option<u16>) {
+        self.target_fps = target_fps.filter(|fps| *fps > 0);
+    }
+
+    pub fn TargetRefreshRate(&self) -> Option<Duration> {
+        let target_fps = match self.paused() {
+            true => Some(self.target_fps.map_or(PAUSED_FPS, |fps| fps.min(PAUSED_FPS))),
+            false => self.target_fps,
+        };
+
+        target_fps.map(|fps| Duration::from_secs_f64(1.0/(fps as f64)))
+    }
+
+    // The game doesn't advance while the window is in the background or minimized
+    pub fn paused(&self) -> bool {
+        !self.focused || self.minimized
+    }
+
+    // Cycles through the present modes the window supports, starting with vsync (Fifo), to measure raw frame times
+    pub fn cycle_present_mode(&mut self) {
+        let present_modes = self.graphics.present_modes();
+        let index = present_modes.iter().position(|present_mode| *present_mode == self.graphics.present_mode()).unwrap_or(0);
+        let present_mode = present_modes[(index + 1)%present_modes.len()];
+
+        let present_mode = self.graphics.set_present_mode(present_mode);
+        self.graphics.debug_draw.show_message(format!("Present mode: {:?}", present_mode));
+    }
+
+    // Switches between no multisampling and MULTISAMPLE_COUNT samples to compare the edges and the frame times
+    pub fn toggle_multisampling(&mut self) {
+        let sample_count = match self.graphics.sample_count() {
+            1 => MULTISAMPLE_COUNT,
+            _ => 1,
+        };
+
+        let message = match self.graphics.set_sample_count(sample_count) {
+            Ok(()) => format!("Sample count: {}", sample_count),
+            Err(error) => {
+                eprintln!("{:?}", error);
+                format!("Sample count {} isn't supported", sample_count)
+            }
+        };
+        self.graphics.debug_draw.show_message(message);
+    }
+
+    pub fn fixed_delta(&self) -> f64 {
+        self.fixed_delta
+    }
+
+    pub fn interpolation_alpha(&self) -> f64 {
+        self.accumulator/self.fixed_delta
     }
 }
 
 fn enter_frame(event_pump: &mut sdl2::EventPump, app_state: &mut AppState) {
     app_state.start_of_frame = Instant::now();
-    app_state.input.update(&event_pump.keyboard_state());
+    app_state.input.update(&event_pump);
 
     for event in event_pump.poll_iter() {
+        app_state.input.handle_event(&event);
+
         match event {
             Event::Quit {..} |
             Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>  {
                 app_state.exit_app = true;
             },
+            Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                app_state.graphics.reload_shaders();
+            },
+            Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                app_state.cycle_present_mode();
+            },
+            Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                let wireframe = !app_state.graphics.wireframe();
+                app_state.graphics.set_wireframe(wireframe);
+            },
+            Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                app_state.toggle_multisampling();
+            },
+            Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+                app_state.graphics.capture_to_png(std::path::Path::new(&format!("screenshot_{}.png", timestamp)));
+            },
             Event::Window { win_event : sdl2::event::WindowEvent::Resized(width, height), .. }=> {
                 app_state.graphics.resize((width as u32, height as u32));
+                app_state.window_resizes.push(WindowResized { width: width as u32, height: height as u32 });
+            },
+            Event::Window { win_event : sdl2::event::WindowEvent::FocusLost, .. } => {
+                app_state.focused = false;
+            },
+            Event::Window { win_event : sdl2::event::WindowEvent::FocusGained, .. } => {
+                app_state.focused = true;
+                // Keys held when the focus was lost must not be seen as pressed this frame
+                app_state.input.reset_edges();
+            },
+            Event::Window { win_event : sdl2::event::WindowEvent::Minimized, .. } => {
+                app_state.minimized = true;
+            },
+            Event::Window { win_event : sdl2::event::WindowEvent::Restored, .. } => {
+                app_state.minimized = false;
             },
             _ => {}
         }
@@ -84,17 +250,73 @@ fn enter_frame(event_pump: &mut sdl2::EventPump, app_state: &mut AppState) {
 }
 
 fn exit_frame(app_state: &mut AppState) {
-    'lockFPS: loop {
-        if app_state.start_of_frame.elapsed().as_secs_f64() >= app_state.TargetRefreshRate() {
-            break 'lockFPS;
+    if let Some(target_refresh_rate) = app_state.TargetRefreshRate() {
+        let elapsed = app_state.start_of_frame.elapsed();
+        if elapsed + SPIN_WAIT_TIME < target_refresh_rate {
+            std::thread::sleep(target_refresh_rate - elapsed - SPIN_WAIT_TIME);
+        }
+
+        while app_state.start_of_frame.elapsed() < target_refresh_rate {
+            std::hint::spin_loop();
         }
     }
 
     app_state.delta_time = app_state.start_of_frame.elapsed().as_secs_f64();
     app_state.time_elapsed += app_state.delta_time;
+}
+
+// Reads the prefabs of the scene, their children and their textures on worker threads before the scene is created, so the
+// window stays responsive. The window title shows the progress. Returns false if the game was closed while loading.
+fn preload_scene(scene: &Scene, ecs: &mut EntityComponentSystem, app_state: &mut AppState, event_pump: &mut sdl2::EventPump, window: &mut sdl2::video::Window) -> bool {
+    let mut requested : HashSet<String> = HashSet::new();
+    let mut waiting : Vec<String> = Vec::new();
+    for instance in &scene.entities {
+        if requested.insert(instance.prefab.clone()) {
+            ecs.resources_mut().load_prefab_async(&instance.prefab);
+            waiting.push(instance.prefab.clone());
+        }
+    }
+
+    loop {
+        enter_frame(event_pump, app_state);
+        if app_state.exit_app {
+            return false;
+        }
+
+        app_state.graphics.finish_loads();
+        ecs.resources_mut().finish_loads();
+
+        // The textures and children of a prefab are known once it is read. The ones that failed are reported when created.
+        let (read, still_waiting) : (Vec<String>, Vec<String>) = waiting.into_iter()
+            .partition(|prefab| ecs.resources_mut().assets.find::<serde_json::Value>(prefab).is_some());
+        waiting = still_waiting;
+        for prefab in read {
+            let json = match ecs.resources_mut().prefab(&prefab) {
+                Some(json) => json.clone(),
+                None => continue,
+            };
+
+            if let Ok(mesh) = Mesh::deserialize(&json["Mesh"]) {
+                app_state.graphics.load_texture_async(&mesh.diffuse_texture, mesh.diffuse_color_space);
+            }
+
+            for child in json["children"].as_array().into_iter().flatten().filter_map(|child| child.as_str()) {
+                if requested.insert(child.to_owned()) {
+                    ecs.resources_mut().load_prefab_async(child);
+                    waiting.push(child.to_owned());
+                }
+            }
+        }
+
+        let progress = (ecs.resources_mut().load_progress() + app_state.graphics.load_progress())/2.0;
+        if waiting.is_empty() && progress >= 1.0 {
+            let _ = window.set_title(WINDOW_TITLE);
+            return true;
+        }
 
-    // println!("Application has been running for: {:?} seconds", appState.timeElapsed);
-    // println!("Application FPS: {:?} ", 1.0/appState.deltaTime);
+        let _ = window.set_title(&format!("{} - Loading {:.0}%", WINDOW_TITLE, progress*100.0));
+        exit_frame(app_state);
+    }
 }
 
 fn main() {
@@ -102,8 +324,9 @@ fn main() {
     let resources = Resources::new();
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Sample", 1280, 720)
+    let game_controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut window = video_subsystem
+        .window(WINDOW_TITLE, 1280, 720)
         .position_centered()
         .resizable()
         .build()
@@ -111,14 +334,35 @@ fn main() {
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    // let graphics = block_on(Graphics::new(&window));
-    let graphics = block_on(Graphics::new(&window));
-    let mut app_state = AppState::new(Input::new(&event_pump), graphics, None);
+    let mut graphics = match block_on(Graphics::new(&window, window.size())) {
+        Ok(graphics) => graphics,
+        Err(error) => {
+            eprintln!("The graphics couldn't be initialized. {}", error);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = graphics.set_sample_count(MULTISAMPLE_COUNT) {
+        eprintln!("Rendering without multisampling. Error: {:?}", error);
+    }
+    let mut app_state = AppState::new(Input::new(&event_pump, game_controller_subsystem, video_subsystem.text_input()), graphics, Audio::new(&sdl_context), None);
+    let input_map = Resources::read_input_map("controls.json").unwrap_or_else(|error| {
+        eprintln!("Using the default controls. Error: {:?}", error);
+        InputMap::default_controls()
+    });
+    app_state.input.set_input_map(input_map);
     
     let mut ecs = EntityComponentSystem::new(10_000, resources);
     let mut systems = SystemManager::new();
-    ecs.add_entity("spaceship.json".to_owned());
-    ecs.add_entity("ortho_camera.json".to_owned());
+    systems.init(&mut app_state.graphics);
+    match Resources::read_scene("main.json") {
+        Ok(scene) => {
+            if !preload_scene(&scene, &mut ecs, &mut app_state, &mut event_pump, &mut window) {
+                return;
+            }
+            ecs.load_scene(&scene);
+        },
+        Err(error) => eprintln!("The scene couldn't be loaded. Error: {:?}", error),
+    }
 
     'game_loop: loop {
         enter_frame(&mut event_pump, &mut app_state);
@@ -127,7 +371,37 @@ fn main() {
             break 'game_loop;
         }
 
-        systems.run(&mut ecs, &mut app_state.graphics, &app_state.input, app_state.delta_time as f32);
+        // Recreate the entities of the prefabs modified on disk
+        #[cfg(feature = "hot-reload")]
+        for prefab in ecs.resources_mut().reload_changed_prefabs() {
+            ecs.recreate_prefab_entities(&prefab);
+        }
+
+        // Create the assets read by the worker threads before the new entities use them
+        app_state.graphics.finish_loads();
+        ecs.resources_mut().finish_loads();
+
+        systems.begin_frame(&mut ecs);
+        for window_resized in app_state.window_resizes.drain(..) {
+            ecs.send_event(window_resized);
+        }
+
+        // While paused no time passes for the game, so the fixed step systems don't run and the others get a zero delta time
+        if app_state.paused() {
+            app_state.delta_time = 0.0;
+        }
+
+        // Avoid a spiral of death where a slow frame requires more fixed steps, making the next frame even slower
+        app_state.accumulator += app_state.delta_time.min(MAX_FRAME_TIME);
+        while app_state.accumulator >= app_state.fixed_delta() {
+            systems.run_fixed_step(&mut ecs, &mut app_state.graphics, &app_state.input, &mut app_state.audio, app_state.fixed_delta() as f32);
+            app_state.accumulator -= app_state.fixed_delta();
+        }
+
+        app_state.graphics.interpolation_alpha = app_state.interpolation_alpha() as f32;
+        app_state.graphics.begin_frame();
+        systems.run(&mut ecs, &mut app_state.graphics, &app_state.input, &mut app_state.audio, app_state.delta_time as f32);
+        app_state.graphics.end_frame();
 
         exit_frame(&mut app_state);
     }
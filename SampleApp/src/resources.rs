@@ -1,32 +1,280 @@
-use std::collections::HashMap;
+use crate::asset_manager::{AssetManager, Handle};
+use crate::async_loader::AsyncLoader;
+use crate::input_map::InputMap;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(Debug)]
+pub enum ResourceError {
+    // The prefabs in the order they inherit from each other, ending with the first prefab repeated
+    CyclicInheritance(Vec<String>),
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceError::CyclicInheritance(prefabs) => write!(f, "Cyclic prefab inheritance: {}", prefabs.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+// A level made of prefab instances, read from the scenes directory
+#[derive(serde::Deserialize, Debug)]
+pub struct Scene {
+    pub entities: Vec<SceneEntity>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SceneEntity {
+    pub prefab: String,
+    // Transform fields overriding the ones of the prefab. Fields that aren't specified keep the value of the prefab.
+    #[serde(default)]
+    pub transform: Option<serde_json::Value>,
+}
 
 pub struct Resources {
-    pub prefabs: HashMap<String, serde_json::Value>,
+    // Prefabs are read from the prefabs directory on first use
+    pub assets: AssetManager,
+    prefab_loader: AsyncLoader<serde_json::Value>,
+    // Last modification time seen by reload_changed_prefabs, by prefab file
+    modified_times: HashMap<String, SystemTime>,
+    // Files each loaded prefab is read from, itself and the prefabs it inherits from, so editing a base prefab reloads the
+    // prefabs derived from it. Recorded when reloading a prefab or the first time reload_changed_prefabs sees it.
+    prefab_files: HashMap<String, Vec<String>>,
 }
 
 impl Resources {
     pub fn new() -> Self {
         Self {
-            prefabs : Resources::load_all_prefabs(),
+            assets: AssetManager::new(),
+            prefab_loader: AsyncLoader::new(),
+            modified_times: HashMap::new(),
+            prefab_files: HashMap::new(),
+        }
+    }
+
+    // Adds a reference to the prefab, reading it if it isn't loaded. Release it with assets.unload.
+    pub fn load_prefab(&mut self, prefab_name: &str) -> Handle<serde_json::Value> {
+        self.assets.load(prefab_name, Resources::read_prefab)
+    }
+
+    // Reads the prefab on a worker thread. finish_loads adds a reference to it like load_prefab.
+    pub fn load_prefab_async(&mut self, prefab_name: &str) {
+        self.prefab_loader.request(prefab_name, Resources::read_prefab);
+    }
+
+    // Adds the prefabs read since the last call. Called every frame by the game loop.
+    pub fn finish_loads(&mut self) {
+        for (prefab_name, prefab) in self.prefab_loader.poll() {
+            self.assets.load(&prefab_name, |_| prefab);
+        }
+    }
+
+    // Fraction of the prefabs requested with load_prefab_async that are loaded, in the range [0, 1]
+    pub fn load_progress(&self) -> f32 {
+        self.prefab_loader.progress()
+    }
+
+    // None if the prefab isn't loaded or failed to load
+    pub fn prefab(&self, prefab_name: &str) -> Option<&serde_json::Value> {
+        self.assets.get_by_name(prefab_name)
+    }
+
+    // Reads the prefab from disk again. Returns false and keeps the previous version if it isn't loaded or can't be read.
+    pub fn reload_prefab(&mut self, prefab_name: &str) -> bool {
+        let mut files = Vec::new();
+        let reloaded = self.assets.reload(prefab_name, |prefab_name| resolve_prefab(prefab_name, &Resources::read_prefab_file, &mut Vec::new(), &mut files));
+        if reloaded {
+            self.prefab_files.insert(prefab_name.to_owned(), files);
+        }
+
+        reloaded
+    }
+
+    // Returns the names of the prefabs that were reloaded
+    pub fn reload_all(&mut self) -> Vec<String> {
+        self.assets.names::<serde_json::Value>().into_iter()
+            .filter(|prefab_name| self.reload_prefab(prefab_name))
+            .collect()
+    }
+
+    // Reloads the loaded prefabs whose file, or the file of a prefab they inherit from, was modified since the last call.
+    // Returns the names of the prefabs that were reloaded.
+    pub fn reload_changed_prefabs(&mut self) -> Vec<String> {
+        let prefab_names = self.assets.names::<serde_json::Value>();
+        self.prefab_files.retain(|prefab_name, _| prefab_names.contains(prefab_name));
+        for prefab_name in &prefab_names {
+            if !self.prefab_files.contains_key(prefab_name) {
+                let mut files = Vec::new();
+                // A prefab that can't be read is still watched through the files read before the error
+                let _ = resolve_prefab(prefab_name, &Resources::read_prefab_file, &mut Vec::new(), &mut files);
+                self.prefab_files.insert(prefab_name.clone(), files);
+            }
+        }
+
+        // Every file is checked once, even when several prefabs inherit from it
+        let files : HashSet<&String> = self.prefab_files.values().flatten().collect();
+        let mut changed_files = HashSet::new();
+        for file in files {
+            let modified = match fs::metadata(Resources::prefab_path(file)).and_then(|metadata| metadata.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+
+            // The first time a file is seen only its modification time is recorded
+            if let Some(previous) = self.modified_times.insert(file.clone(), modified) {
+                if previous != modified {
+                    changed_files.insert(file.clone());
+                }
+            }
         }
+
+        let changed_prefabs : Vec<String> = prefab_names.into_iter()
+            .filter(|prefab_name| self.prefab_files[prefab_name].iter().any(|file| changed_files.contains(file)))
+            .collect();
+        changed_prefabs.into_iter()
+            .filter(|prefab_name| self.reload_prefab(prefab_name))
+            .collect()
+    }
+
+    // Reads the actions bound to keys and buttons from the config directory
+    pub fn read_input_map(config_name: &str) -> Result<InputMap> {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("src\\resources\\config");
+        path.push(config_name);
+
+        let contents = fs::read(&path).with_context(|| format!("Failed to read the file: {:?}", path))?;
+        let json : serde_json::Value = serde_json::from_slice(&contents).with_context(|| format!("Failed to parse the input map: {:?}", path))?;
+        InputMap::from_json(&json).with_context(|| format!("Invalid input map: {:?}", path))
+    }
+
+    pub fn read_scene(scene_name: &str) -> Result<Scene> {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("src\\resources\\scenes");
+        path.push(scene_name);
+
+        let contents = fs::read(&path).with_context(|| format!("Failed to read the file: {:?}", path))?;
+        serde_json::from_slice(&contents).with_context(|| format!("Failed to parse the scene: {:?}", path))
+    }
+
+    fn prefab_path(prefab_name: &str) -> PathBuf {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("src\\resources\\prefabs");
+        path.push(prefab_name);
+        path
+    }
+
+    // Reads the prefab and the prefabs it inherits from through its "base" field
+    fn read_prefab(prefab_name: &str) -> Result<serde_json::Value> {
+        resolve_prefab(prefab_name, &Resources::read_prefab_file, &mut Vec::new(), &mut Vec::new())
+    }
+
+    fn read_prefab_file(prefab_name: &str) -> Result<serde_json::Value> {
+        let path = Resources::prefab_path(prefab_name);
+        let contents = fs::read(&path).with_context(|| format!("Failed to read the file: {:?}", path))?;
+        serde_json::from_slice(&contents).with_context(|| format!("Failed to parse the prefab: {:?}", path))
+    }
+}
+
+// Merges the prefab over its base prefab, recursively. inheritance_chain holds the prefabs being resolved to detect cycles.
+// The name of every prefab read, or attempted to, is added to files.
+fn resolve_prefab(prefab_name: &str, read_file: &dyn Fn(&str) -> Result<serde_json::Value>, inheritance_chain: &mut Vec<String>, files: &mut Vec<String>) -> Result<serde_json::Value> {
+    if inheritance_chain.iter().any(|name| name == prefab_name) {
+        let mut cycle = inheritance_chain.clone();
+        cycle.push(prefab_name.to_owned());
+        return Err(ResourceError::CyclicInheritance(cycle).into());
     }
 
-    fn load_all_prefabs() -> HashMap<String, serde_json::Value> {
-        let mut prefab_dir = std::env::current_dir().unwrap();
-        prefab_dir.push("src\\resources\\prefabs");
-        let paths = fs::read_dir(&prefab_dir).unwrap();
-        
-        let mut prefabs = HashMap::new();
+    files.push(prefab_name.to_owned());
+    let mut prefab = read_file(prefab_name)?;
+    let base_name = match prefab.as_object_mut().and_then(|object| object.remove("base")) {
+        Some(serde_json::Value::String(base_name)) => base_name,
+        Some(base_name) => anyhow::bail!("The base of the prefab {} must be a prefab name, not {}", prefab_name, base_name),
+        None => return Ok(prefab),
+    };
 
-        for p in paths {
-            let path = p.unwrap().path();
-            let contents = fs::read(&path).unwrap();
-            let value : serde_json::Value = serde_json::from_slice(&contents).unwrap();
-            let key = path.strip_prefix(&prefab_dir.as_path()).unwrap().to_owned().into_os_string().into_string().unwrap();
-            prefabs.insert(key, value);
+    inheritance_chain.push(prefab_name.to_owned());
+    let mut base = resolve_prefab(&base_name, read_file, inheritance_chain, files)?;
+    inheritance_chain.pop();
+
+    merge_json(&mut base, prefab);
+    Ok(base)
+}
+
+// Objects are merged key by key so overrides only replace the fields they specify. Any other value, arrays included, is replaced.
+pub fn merge_json(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_json(base_value, value),
+                    None => { base.insert(key, value); },
+                }
+            }
+        },
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read_test_prefab(prefab_name: &str) -> Result<serde_json::Value> {
+        match prefab_name {
+            "ship.json" => Ok(json!({
+                "Transform": { "position": { "x": 0.0, "y": 0.0 } },
+                "Mesh": { "mesh_name": "quad", "shader_name": "sprite" }
+            })),
+            "moved_ship.json" => Ok(json!({
+                "base": "ship.json",
+                "Transform": { "position": { "x": 5.0 } }
+            })),
+            "a.json" => Ok(json!({ "base": "b.json" })),
+            "b.json" => Ok(json!({ "base": "a.json" })),
+            _ => anyhow::bail!("No prefab named {}", prefab_name),
         }
+    }
 
-        prefabs
+    #[test]
+    fn base_fields_are_deep_merged() {
+        let mut files = Vec::new();
+        let prefab = resolve_prefab("moved_ship.json", &read_test_prefab, &mut Vec::new(), &mut files).unwrap();
+
+        assert_eq!(prefab, json!({
+            "Transform": { "position": { "x": 5.0, "y": 0.0 } },
+            "Mesh": { "mesh_name": "quad", "shader_name": "sprite" }
+        }));
+        // Editing either file must reload the prefab
+        assert_eq!(files, vec!("moved_ship.json", "ship.json"));
+    }
+
+    #[test]
+    fn scene_transform_is_optional() {
+        let scene : Scene = serde_json::from_value(json!({
+            "entities": [
+                { "prefab": "ship.json", "transform": { "position": { "x": 5.0 } } },
+                { "prefab": "ship.json" }
+            ]
+        })).unwrap();
+
+        assert_eq!(scene.entities[0].transform, Some(json!({ "position": { "x": 5.0 } })));
+        assert_eq!(scene.entities[1].transform, None);
+    }
+
+    #[test]
+    fn cyclic_inheritance_is_an_error() {
+        let error = resolve_prefab("a.json", &read_test_prefab, &mut Vec::new(), &mut Vec::new()).unwrap_err();
+
+        match error.downcast_ref::<ResourceError>() {
+            Some(ResourceError::CyclicInheritance(cycle)) => assert_eq!(cycle, &vec!("a.json", "b.json", "a.json")),
+            None => panic!("Unexpected error: {:?}", error),
+        }
     }
 }
@@ -0,0 +1,11 @@
+#version 440
+
+// Draws a triangle covering the whole target without any vertex buffer
+layout (location = 0) out vec2 texCoord;
+
+void main() {
+	vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+	// The first row of the texture is the top of the target
+	texCoord = vec2(position.x, 1.0 - position.y);
+	gl_Position = vec4(position*2.0 - 1.0, 0.0, 1.0);
+}
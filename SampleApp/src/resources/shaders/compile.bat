@@ -1,2 +1,5 @@
 glslangValidator.exe -V sprite.frag -o sprite.frag.spv
-glslangValidator.exe -V sprite.vert -o sprite.vert.spv
\ No newline at end of file
+glslangValidator.exe -V sprite.vert -o sprite.vert.spv
+glslangValidator.exe -V skinned.vert -o skinned.vert.spv
+glslangValidator.exe -V blit.vert -o blit.vert.spv
+glslangValidator.exe -V blit.frag -o blit.frag.spv
\ No newline at end of file
@@ -0,0 +1,44 @@
+#version 440
+
+layout (location = 0) in vec3 vPosition;
+layout (location = 1) in vec3 vNormal;
+layout (location = 2) in vec2 vTexCoord;
+
+// Per instance model matrix, one column per location
+layout (location = 3) in vec4 iModelMatrix0;
+layout (location = 4) in vec4 iModelMatrix1;
+layout (location = 5) in vec4 iModelMatrix2;
+layout (location = 6) in vec4 iModelMatrix3;
+layout (location = 7) in vec4 iColor;
+// Texture coordinates offset in xy and scale in zw
+layout (location = 8) in vec4 iUvRect;
+
+// Up to four bones move each vertex, weighted by their influence
+layout (location = 9) in uvec4 vJoints;
+layout (location = 10) in vec4 vWeights;
+
+layout(set = 1, binding = 0) uniform uniforms {
+	mat4 view_matrix;
+	vec4 light_direction;
+	vec4 light_color;
+	vec4 ambient_color;
+} Uniforms;
+
+// Moves the vertices from the bind pose to the current pose of each bone. Sized like MAX_BONES.
+layout(set = 2, binding = 0) uniform bones {
+	mat4 palette[64];
+} Bones;
+
+layout (location = 0) out vec2 texCoord;
+layout (location = 1) out vec4 color;
+layout (location = 2) out vec3 worldNormal;
+
+void main() {
+	mat4 skin_matrix = vWeights.x*Bones.palette[vJoints.x] + vWeights.y*Bones.palette[vJoints.y] + vWeights.z*Bones.palette[vJoints.z] + vWeights.w*Bones.palette[vJoints.w];
+	mat4 model_matrix = mat4(iModelMatrix0, iModelMatrix1, iModelMatrix2, iModelMatrix3)*skin_matrix;
+	gl_Position = Uniforms.view_matrix*model_matrix*vec4(vPosition, 1.0);
+	texCoord = iUvRect.xy + vTexCoord*iUvRect.zw;
+	color = iColor;
+	// The inverse transpose keeps the normal perpendicular to the surface under non uniform scaling
+	worldNormal = transpose(inverse(mat3(model_matrix)))*vNormal;
+}
\ No newline at end of file
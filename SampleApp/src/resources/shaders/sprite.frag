@@ -1,12 +1,29 @@
 #version 440
 
 layout (location = 0) in vec2 texCoord;
+layout (location = 1) in vec4 color;
+layout (location = 2) in vec3 worldNormal;
 
 layout(set = 0, binding = 0) uniform texture2D u_Texture;
 layout(set = 0, binding = 1) uniform sampler u_Sampler;
 
+layout(set = 1, binding = 0) uniform uniforms {
+	mat4 view_matrix;
+	vec4 light_direction;
+	vec4 light_color;
+	vec4 ambient_color;
+} Uniforms;
+
 layout (location = 0) out vec4 outColor;
 
 void main() {
-	outColor = texture(sampler2D(u_Texture, u_Sampler), texCoord);
+	// Vertices without a normal are not lit
+	vec3 lighting = vec3(1.0);
+	if (dot(worldNormal, worldNormal) > 0.0) {
+		float diffuse = max(dot(normalize(worldNormal), -Uniforms.light_direction.xyz), 0.0);
+		lighting = Uniforms.ambient_color.rgb + diffuse*Uniforms.light_color.rgb;
+	}
+
+	vec4 textureColor = texture(sampler2D(u_Texture, u_Sampler), texCoord)*color;
+	outColor = vec4(textureColor.rgb*lighting, textureColor.a);
 }
\ No newline at end of file
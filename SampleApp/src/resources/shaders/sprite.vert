@@ -4,17 +4,31 @@ layout (location = 0) in vec3 vPosition;
 layout (location = 1) in vec3 vNormal;
 layout (location = 2) in vec2 vTexCoord;
 
+// Per instance model matrix, one column per location
+layout (location = 3) in vec4 iModelMatrix0;
+layout (location = 4) in vec4 iModelMatrix1;
+layout (location = 5) in vec4 iModelMatrix2;
+layout (location = 6) in vec4 iModelMatrix3;
+layout (location = 7) in vec4 iColor;
+// Texture coordinates offset in xy and scale in zw
+layout (location = 8) in vec4 iUvRect;
+
 layout(set = 1, binding = 0) uniform uniforms {
 	mat4 view_matrix;
+	vec4 light_direction;
+	vec4 light_color;
+	vec4 ambient_color;
 } Uniforms;
 
 layout (location = 0) out vec2 texCoord;
-
-layout(push_constant) uniform model_properties {
-	mat4 model_matrix;
-} ModelProperties;
+layout (location = 1) out vec4 color;
+layout (location = 2) out vec3 worldNormal;
 
 void main() {
-	gl_Position = Uniforms.view_matrix*ModelProperties.model_matrix*vec4(vPosition, 1.0);
-	texCoord = vTexCoord;
+	mat4 model_matrix = mat4(iModelMatrix0, iModelMatrix1, iModelMatrix2, iModelMatrix3);
+	gl_Position = Uniforms.view_matrix*model_matrix*vec4(vPosition, 1.0);
+	texCoord = iUvRect.xy + vTexCoord*iUvRect.zw;
+	color = iColor;
+	// The inverse transpose keeps the normal perpendicular to the surface under non uniform scaling
+	worldNormal = transpose(inverse(mat3(model_matrix)))*vNormal;
 }
\ No newline at end of file
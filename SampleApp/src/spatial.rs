@@ -0,0 +1,153 @@
+use crate::entity::EntityId;
+
+// Nodes holding more items than this are split in four, unless they are at the maximum depth
+const MAX_NODE_ITEMS: usize = 8;
+const MAX_DEPTH: u32 = 8;
+
+// Axis aligned rectangle in the xy plane. Rectangles touching on an edge intersect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Vector2<f32>,
+    pub max: cgmath::Vector2<f32>,
+}
+
+impl Aabb {
+    pub fn from_center(center: cgmath::Vector2<f32>, half_extents: cgmath::Vector2<f32>) -> Self {
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x && self.min.y <= other.max.y && other.min.y <= self.max.y
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x && other.max.x <= self.max.x && self.min.y <= other.min.y && other.max.y <= self.max.y
+    }
+
+    fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: cgmath::Vector2::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: cgmath::Vector2::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    fn quadrants(&self) -> [Aabb; 4] {
+        let center = (self.min + self.max)/2.0;
+        [
+            Aabb { min: self.min, max: center },
+            Aabb { min: cgmath::Vector2::new(center.x, self.min.y), max: cgmath::Vector2::new(self.max.x, center.y) },
+            Aabb { min: cgmath::Vector2::new(self.min.x, center.y), max: cgmath::Vector2::new(center.x, self.max.y) },
+            Aabb { min: center, max: self.max },
+        ]
+    }
+}
+
+struct Node {
+    bounds: Aabb,
+    // Items that don't fit entirely inside one of the children stay in the node
+    items: Vec<(EntityId, Aabb)>,
+    children: Option<Box<[Node; 4]>>,
+}
+
+impl Node {
+    fn new(bounds: Aabb) -> Self {
+        Self {
+            bounds,
+            items: Vec::new(),
+            children: None,
+        }
+    }
+
+    fn insert(&mut self, entity: EntityId, bounds: Aabb, depth: u32) {
+        if let Some(children) = &mut self.children {
+            if let Some(child) = children.iter_mut().find(|child| child.bounds.contains(&bounds)) {
+                child.insert(entity, bounds, depth + 1);
+                return;
+            }
+        }
+
+        self.items.push((entity, bounds));
+        if self.children.is_none() && self.items.len() > MAX_NODE_ITEMS && depth < MAX_DEPTH {
+            self.split(depth);
+        }
+    }
+
+    fn split(&mut self, depth: u32) {
+        let [a, b, c, d] = self.bounds.quadrants();
+        self.children = Some(Box::new([Node::new(a), Node::new(b), Node::new(c), Node::new(d)]));
+
+        for (entity, bounds) in std::mem::take(&mut self.items) {
+            self.insert(entity, bounds, depth);
+        }
+    }
+
+    fn query(&self, region: &Aabb, found: &mut Vec<EntityId>) {
+        found.extend(self.items.iter().filter(|(_, bounds)| bounds.intersects(region)).map(|(entity, _)| *entity));
+
+        if let Some(children) = &self.children {
+            for child in children.iter().filter(|child| child.bounds.intersects(region)) {
+                child.query(region, found);
+            }
+        }
+    }
+}
+
+// Finds the entities whose bounds intersect a region without testing every entity. Built from scratch when the entities
+// move, e.g. every fixed step by the collision system, which stores it as a resource of the ECS for the other systems.
+pub struct Quadtree {
+    root: Option<Node>,
+}
+
+impl Quadtree {
+    // The tree covers the union of the bounds of the items
+    pub fn new(items: &[(EntityId, Aabb)]) -> Self {
+        let bounds = match items.split_first() {
+            Some(((_, first), rest)) => rest.iter().fold(*first, |bounds, (_, item)| bounds.union(item)),
+            None => return Self { root: None },
+        };
+
+        let mut root = Node::new(bounds);
+        for (entity, bounds) in items {
+            root.insert(*entity, *bounds, 0);
+        }
+
+        Self { root: Some(root) }
+    }
+
+    // Entities whose bounds intersect the region, in index order
+    pub fn query(&self, region: &Aabb) -> Vec<EntityId> {
+        let mut found = Vec::new();
+        if let Some(root) = &self.root {
+            root.query(region, &mut found);
+        }
+
+        found.sort();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f32, y: f32) -> Aabb {
+        Aabb::from_center(cgmath::Vector2::new(x, y), cgmath::Vector2::new(0.5, 0.5))
+    }
+
+    #[test]
+    fn query_finds_only_intersecting_entities() {
+        // A row of squares one unit apart, enough to split the root several times
+        let items : Vec<(EntityId, Aabb)> = (0..100)
+            .map(|index| (EntityId { index, generation: 0 }, square(index as f32*2.0, 0.0)))
+            .collect();
+        let quadtree = Quadtree::new(&items);
+
+        let found = quadtree.query(&square(41.0, 0.0));
+        assert_eq!(found, vec!(EntityId { index: 20, generation: 0 }, EntityId { index: 21, generation: 0 }));
+        assert!(quadtree.query(&square(0.0, 10.0)).is_empty());
+        assert!(Quadtree::new(&[]).query(&square(0.0, 0.0)).is_empty());
+    }
+}
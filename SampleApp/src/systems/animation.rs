@@ -0,0 +1,76 @@
+use crate::sprite_animation::SpriteAnimation;
+use crate::mesh::Mesh;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::{Graphics, TextureBindGroups};
+use crate::asset_manager::Handle;
+use std::collections::{BTreeSet, HashMap};
+
+pub struct AnimationSystem {
+    // Frame names and texture references held by every animation, so the textures are released with the animation
+    frame_textures: HashMap<EntityId, (Vec<String>, Vec<Handle<TextureBindGroups>>)>,
+    // References of removed animations, released on the next run since on_entity_removed has no access to the assets
+    released_textures: Vec<Handle<TextureBindGroups>>,
+}
+
+impl AnimationSystem {
+    pub fn new() -> Self {
+        Self {
+            frame_textures: HashMap::new(),
+            released_textures: Vec::new(),
+        }
+    }
+}
+
+impl System for AnimationSystem {
+    fn name(&self) -> &'static str {
+        "animation"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        for handle in self.released_textures.drain(..) {
+            graphics.assets.unload(handle);
+        }
+
+        let mut animations = ecs.get_component_set::<SpriteAnimation>().unwrap().borrow_mut();
+        let mut meshes = ecs.get_component_set::<Mesh>().unwrap().borrow_mut();
+
+        for entity in entities {
+            let animation = animations.get_mut(entity).unwrap();
+
+            // Every frame is loaded up front so switching frames doesn't stall on a texture load. The references are taken
+            // once per animation and retaken only when its frames are replaced.
+            let loaded = self.frame_textures.get(entity).map_or(false, |(frames, _)| *frames == animation.frames);
+            if !loaded {
+                let color_space = meshes.get(entity).unwrap().diffuse_color_space;
+                let handles = animation.frames.iter().map(|frame| graphics.load_texture(frame, color_space)).collect();
+                if let Some((_, old_handles)) = self.frame_textures.insert(*entity, (animation.frames.clone(), handles)) {
+                    for handle in old_handles {
+                        graphics.assets.unload(handle);
+                    }
+                }
+            }
+
+            animation.advance(delta_time);
+            if let Some(frame) = animation.current_frame() {
+                // The mesh is only borrowed mutably on a frame switch so it isn't flagged as changed every frame
+                if meshes.get(entity).unwrap().diffuse_texture != animation.frames[frame] {
+                    meshes.get_mut(entity).unwrap().diffuse_texture = animation.frames[frame].clone();
+                }
+            }
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<SpriteAnimation>(entity) && ecs.has_component::<Mesh>(entity)
+    }
+
+    fn on_entity_removed(&mut self, entity: &EntityId, _ecs: &EntityComponentSystem) {
+        if let Some((_, handles)) = self.frame_textures.remove(entity) {
+            self.released_textures.extend(handles);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::camera::Camera;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+use sdl2::mouse::MouseButton;
+
+// Zoom applied for each step of the mouse wheel
+const ZOOM_STEP: f32 = 1.1;
+
+// Zooms orthographic cameras with the mouse wheel and pans them while dragging with the right mouse button
+pub struct CameraControlSystem {}
+
+impl System for CameraControlSystem {
+    fn name(&self) -> &'static str {
+        "camera_control"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, graphics: &mut Graphics, input: &Input, _audio: &mut Audio, _delta_time: f32) {
+        let mut cameras = ecs.get_component_set::<Camera>().unwrap().borrow_mut();
+
+        for entity in entities {
+            if let Some(camera) = cameras.get_mut(entity) {
+                if input.wheel_delta() != 0 {
+                    camera.zoom(ZOOM_STEP.powi(input.wheel_delta()));
+                }
+
+                if input.is_mouse_button_pressed(MouseButton::Right) {
+                    let (_, _, width, height) = camera.viewport_rect(graphics.size);
+                    if let Some((units_x, units_y)) = camera.world_units_per_pixel((width as u32, height as u32)) {
+                        // Screen y grows downwards while world y grows upwards
+                        let (dx, dy) = input.mouse_delta();
+                        camera.pan(-dx as f32*units_x, dy as f32*units_y);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Camera>(entity)
+    }
+
+}
@@ -0,0 +1,51 @@
+use crate::camera::Camera;
+use crate::camera_shake::CameraShake;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+
+pub struct CameraShakeSystem {}
+
+impl System for CameraShakeSystem {
+    fn name(&self) -> &'static str {
+        "camera_shake"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        let mut finished = Vec::new();
+
+        {
+            let mut cameras = ecs.get_component_set::<Camera>().unwrap().borrow_mut();
+            let mut shakes = ecs.get_component_set::<CameraShake>().unwrap().borrow_mut();
+
+            for entity in entities {
+                let camera = cameras.get_mut(entity).unwrap();
+                match shakes.get_mut(entity).unwrap().advance(delta_time) {
+                    Some(offset) => camera.view_offset = offset,
+                    None => {
+                        camera.view_offset = cgmath::Vector3::new(0.0, 0.0, 0.0);
+                        finished.push(*entity);
+                    }
+                }
+            }
+        }
+
+        for entity in finished {
+            ecs.detach_component::<CameraShake>(&entity);
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Camera>(entity) && ecs.has_component::<CameraShake>(entity)
+    }
+
+
+    // The shake only depends on the seed and the number of steps, so it's the same whatever the frame rate
+    fn is_fixed_step(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,76 @@
+use crate::transform::Transform;
+use crate::collider::Collider;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use crate::spatial::{Aabb, Quadtree};
+use std::collections::BTreeSet;
+
+// Sent every fixed step for each pair of overlapping entities
+pub struct CollisionEvent {
+    pub a: EntityId,
+    pub b: EntityId,
+}
+
+pub struct CollisionSystem {}
+
+impl System for CollisionSystem {
+    fn name(&self) -> &'static str {
+        "collision"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, _delta_time: f32) {
+        let mut collisions = Vec::new();
+        let quadtree;
+
+        {
+            let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+            let colliders = ecs.get_component_set::<Collider>().unwrap().borrow();
+
+            let bodies : Vec<(EntityId, &Transform, &Collider)> = entities.iter()
+                .filter_map(|entity| match (transforms.get(entity), colliders.get(entity)) {
+                    (Some(transform), Some(collider)) => Some((*entity, transform, collider)),
+                    _ => None
+                })
+                .collect();
+
+            let bounds : Vec<(EntityId, Aabb)> = bodies.iter()
+                .map(|(entity, transform, collider)| (*entity, Aabb::from_center(transform.position.truncate(), collider.half_extents)))
+                .collect();
+            quadtree = Quadtree::new(&bounds);
+
+            // Only the pairs the quadtree finds close to each other are tested. Each pair is tested once, from its lowest entity.
+            for ((entity_a, transform_a, collider_a), (_, bounds_a)) in bodies.iter().zip(&bounds) {
+                for entity_b in quadtree.query(bounds_a).into_iter().filter(|entity_b| entity_b > entity_a) {
+                    let (transform_b, collider_b) = (transforms.get(&entity_b).unwrap(), colliders.get(&entity_b).unwrap());
+                    if collider_a.overlaps(transform_a.position, collider_b, transform_b.position) {
+                        collisions.push(CollisionEvent { a: *entity_a, b: entity_b });
+                    }
+                }
+            }
+        }
+
+        for collision in collisions {
+            ecs.send_event(collision);
+        }
+
+        // Other systems can look for entities near a position without testing all of them
+        ecs.insert_resource(quadtree);
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Transform>(entity) && ecs.has_component::<Collider>(entity)
+    }
+
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["physics"]
+    }
+
+    fn is_fixed_step(&self) -> bool {
+        true
+    }
+}
@@ -1,59 +1,199 @@
 use crate::transform::Transform;
 use crate::controller::Controller;
-use crate::input::Input;
+use crate::rigid_body::RigidBody;
+use crate::input::{Input, InputState};
+use crate::audio::Audio;
 use crate::ecs::EntityComponentSystem;
 use crate::system::System;
 use crate::entity::EntityId;
 use crate::graphics::Graphics;
-use std::collections::HashSet;
-use sdl2::keyboard::Scancode;
+use std::collections::BTreeSet;
+use sdl2::controller::Axis;
+use cgmath::InnerSpace;
 
 pub struct ControlSystem {}
 
-impl System for ControlSystem {
-    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &HashSet<EntityId>, _graphics: &mut Graphics, input: &Input, delta_time: f32) {
-        let mut transforms = ecs.get_component_set::<Transform>().unwrap().borrow_mut();
-        let mut controllers = ecs.get_component_set::<Controller>().unwrap().borrow_mut();
+// 1 while the positive action is pressed, -1 while the negative one is and 0 for both or neither
+fn action_axis(input: &dyn InputState, positive: &str, negative: &str) -> f32 {
+    input.is_action_pressed(positive) as i32 as f32 - input.is_action_pressed(negative) as i32 as f32
+}
+
+// Stick axis of the first controller, negated so up and left are positive like thrust, strafe_left and rotate_left.
+// Stick up and left are negative in SDL.
+fn gamepad_axis(input: &dyn InputState, axis: Axis) -> f32 {
+    match input.first_controller() {
+        Some(gamepad) => -input.axis(gamepad, axis),
+        None => 0.0,
+    }
+}
+
+impl ControlSystem {
+    // Adds the acceleration requested by the input to the rigid bodies and sets their angular velocity
+    fn apply_input(&self, ecs: &EntityComponentSystem, entities: &BTreeSet<EntityId>, input: &dyn InputState) {
+        let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+        let controllers = ecs.get_component_set::<Controller>().unwrap().borrow();
+        let mut rigid_bodies = ecs.get_component_set::<RigidBody>().unwrap().borrow_mut();
 
         for entity in entities {
-            match (transforms.get_mut(&entity), controllers.get_mut(&entity)) {
-                (Some(transform), Some(controller)) => {
-                    let mut acc_dir = transform.rotation*cgmath::Vector3{x: 1.0, y: 0.0, z: 0.0};
-                    if input.is_key_pressed(Scancode::W) {
-                        acc_dir *= 1.0;
-                    }
-                    else if input.is_key_pressed(Scancode::S) {
-                        acc_dir *= -1.0;
+            match (transforms.get(&entity), controllers.get(&entity), rigid_bodies.get_mut(&entity)) {
+                (Some(transform), Some(controller), Some(rigid_body)) => {
+                    // Forward along the local x axis and to the left along the local y axis. Keys and sticks add up, and the
+                    // direction is limited to unit length so thrusting and strafing together isn't faster.
+                    let mut direction = cgmath::Vector3::new(
+                        action_axis(input, "thrust", "reverse") + gamepad_axis(input, Axis::LeftY),
+                        action_axis(input, "strafe_left", "strafe_right") + gamepad_axis(input, Axis::RightX),
+                        0.0,
+                    );
+                    if direction.magnitude2() > 1.0 {
+                        direction = direction.normalize();
                     }
-                    else {
-                        acc_dir *= 0.0;
-                    }
-    
-                    cgmath::Deg(1.0);
-    
-                    let mut rotate_dir = 0.0;
-                    if input.is_key_pressed(Scancode::A) {
-                        rotate_dir = 1.0;
-                    }
-                    else if input.is_key_pressed(Scancode::D) {
-                        rotate_dir = -1.0;
-                    }
-    
-                    controller.velocity = controller.acceleration_speed*delta_time*acc_dir + controller.velocity*0.99;
-                    transform.position += controller.velocity*delta_time;
-                    transform.rotation = transform.rotation*cgmath::Quaternion::from(
-                        cgmath::Euler {
-                            x: cgmath::Deg(0.0), 
-                            y: cgmath::Deg(0.0), 
-                            z: cgmath::Deg(controller.rotation_speed*rotate_dir*delta_time),
-                        });
-                        }
+
+                    let rotate_dir = (action_axis(input, "rotate_left", "rotate_right") + gamepad_axis(input, Axis::LeftX)).clamp(-1.0, 1.0);
+
+                    // The physics system integrates the acceleration and angular velocity
+                    rigid_body.acceleration += controller.acceleration_speed*(transform.rotation*direction);
+                    rigid_body.angular_velocity = controller.rotation_speed*rotate_dir;
+                }
                 _ => {}
             }
         }
     }
+}
+
+impl System for ControlSystem {
+    fn name(&self) -> &'static str {
+        "control"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, input: &Input, _audio: &mut Audio, _delta_time: f32) {
+        self.apply_input(ecs, entities, input);
+    }
 
     fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
-        ecs.has_component::<Transform>(entity) && ecs.has_component::<Controller>(entity)
+        ecs.has_component::<Transform>(entity) && ecs.has_component::<Controller>(entity) && ecs.has_component::<RigidBody>(entity)
+    }
+
+
+    fn is_fixed_step(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::MockInput;
+    use crate::physics::PhysicsSystem;
+    use crate::resources::Resources;
+    use cgmath::InnerSpace;
+
+    const FIXED_DELTA: f32 = 1.0/120.0;
+
+    // A ship like the one of the sample, rotated around the z axis
+    fn create_ship(rotation: f32, damping: f32) -> (EntityComponentSystem, BTreeSet<EntityId>) {
+        create_ship_with_rigid_body(rotation, serde_json::json!({ "damping": damping }))
+    }
+
+    fn create_ship_with_rigid_body(rotation: f32, rigid_body: serde_json::Value) -> (EntityComponentSystem, BTreeSet<EntityId>) {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.load_from_json(&serde_json::json!({ "entities": [{
+            "EntityId": { "index": 0, "generation": 0 },
+            "Transform": { "rotation": { "euler": { "x": 0.0, "y": 0.0, "z": rotation } } },
+            "Controller": { "acceleration_speed": 200.0, "rotation_speed": 90.0 },
+            "RigidBody": rigid_body
+        }]})).unwrap();
+        let entities = ecs.create_entities().into_iter().collect();
+        (ecs, entities)
+    }
+
+    // Runs the control and physics systems like the fixed steps of a frame
+    fn step(ecs: &EntityComponentSystem, entities: &BTreeSet<EntityId>, input: &mut MockInput, steps: usize) {
+        for _ in 0..steps {
+            input.next_frame();
+            ControlSystem {}.apply_input(ecs, entities, input);
+            PhysicsSystem {}.integrate(ecs, entities, FIXED_DELTA);
+        }
+    }
+
+    fn position_and_velocity(ecs: &EntityComponentSystem) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let entity = EntityId { index: 0, generation: 0 };
+        let position = ecs.get_component_set::<Transform>().unwrap().borrow().get(&entity).unwrap().position;
+        let velocity = ecs.get_component_set::<RigidBody>().unwrap().borrow().get(&entity).unwrap().velocity;
+        (position, velocity)
+    }
+
+    #[test]
+    fn thrust_accelerates_forward() {
+        let (ecs, entities) = create_ship(0.0, 1.0);
+        let mut input = MockInput::default();
+        input.set_action_pressed("thrust", true);
+        for _ in 0..3 {
+            input.next_frame();
+            ControlSystem {}.apply_input(&ecs, &entities, &input);
+        }
+
+        let rigid_bodies = ecs.get_component_set::<RigidBody>().unwrap().borrow();
+        let rigid_body = rigid_bodies.get(&EntityId { index: 0, generation: 0 }).unwrap();
+        assert_eq!(rigid_body.acceleration, cgmath::Vector3::new(600.0, 0.0, 0.0));
+        assert_eq!(rigid_body.angular_velocity, 0.0);
+    }
+
+    #[test]
+    fn thrusting_while_strafing_is_not_faster() {
+        let (ecs, entities) = create_ship(0.0, 1.0);
+        let mut input = MockInput::default();
+        input.set_action_pressed("thrust", true);
+        input.set_action_pressed("strafe_right", true);
+        input.set_action_pressed("rotate_left", true);
+        input.next_frame();
+        ControlSystem {}.apply_input(&ecs, &entities, &input);
+
+        let rigid_bodies = ecs.get_component_set::<RigidBody>().unwrap().borrow();
+        let rigid_body = rigid_bodies.get(&EntityId { index: 0, generation: 0 }).unwrap();
+        let expected = cgmath::Vector3::new(1.0, -1.0, 0.0).normalize()*200.0;
+        assert!((rigid_body.acceleration - expected).magnitude() < 1e-3, "{:?}", rigid_body.acceleration);
+        assert_eq!(rigid_body.angular_velocity, 90.0);
+    }
+
+    #[test]
+    fn ship_moves_in_the_direction_it_faces() {
+        // Facing up the y axis
+        let (ecs, entities) = create_ship(90.0, 1.0);
+        let mut input = MockInput::default();
+        input.set_action_pressed("thrust", true);
+        step(&ecs, &entities, &mut input, 60);
+
+        let (position, velocity) = position_and_velocity(&ecs);
+        assert!(position.y > 0.0 && position.x.abs() < 1e-3*position.y, "{:?}", position);
+        // Half a second of constant acceleration
+        assert!((velocity.magnitude() - 100.0).abs() < 1e-2, "{:?}", velocity);
+    }
+
+    #[test]
+    fn speed_is_limited_to_the_maximum() {
+        let (ecs, entities) = create_ship_with_rigid_body(0.0, serde_json::json!({ "max_speed": 50.0 }));
+        let mut input = MockInput::default();
+        input.set_action_pressed("thrust", true);
+        step(&ecs, &entities, &mut input, 120);
+
+        let (position, velocity) = position_and_velocity(&ecs);
+        assert!(velocity.magnitude() <= 50.0, "{:?}", velocity);
+        assert!(velocity.magnitude() >= 50.0 - 1e-3, "{:?}", velocity);
+        assert!(position.magnitude() <= 50.0, "{:?}", position);
+    }
+
+    #[test]
+    fn velocity_decays_once_thrust_stops() {
+        let (ecs, entities) = create_ship(0.0, 0.3);
+        let mut input = MockInput::default();
+        input.set_action_pressed("thrust", true);
+        step(&ecs, &entities, &mut input, 10);
+        let (_, thrust_velocity) = position_and_velocity(&ecs);
+
+        input.set_action_pressed("thrust", false);
+        step(&ecs, &entities, &mut input, 20);
+        let (_, velocity) = position_and_velocity(&ecs);
+        let expected = thrust_velocity*0.3f32.powf(20.0*FIXED_DELTA);
+        assert!((velocity - expected).magnitude() < 1e-3, "{:?} != {:?}", velocity, expected);
     }
 }
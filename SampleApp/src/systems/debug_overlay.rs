@@ -0,0 +1,128 @@
+use crate::input::{Input, InputState};
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use sdl2::keyboard::Scancode;
+use std::collections::{BTreeSet, VecDeque};
+
+// Number of frames the frame time is averaged over
+const FRAME_TIME_SAMPLES: usize = 60;
+const TOGGLE_KEY: Scancode = Scancode::F3;
+const TEXT_POSITION: [f32; 2] = [8.0, 8.0];
+const TEXT_SIZE: f32 = 16.0;
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+// Distance of the messages from the bottom left corner of the window
+const MESSAGE_MARGIN: f32 = 8.0;
+
+// Delta times of the last frames, so the displayed values don't change every frame
+struct FrameTimes {
+    samples: VecDeque<f32>,
+    total: f32,
+}
+
+impl FrameTimes {
+    fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(FRAME_TIME_SAMPLES),
+            total: 0.0,
+        }
+    }
+
+    // The oldest sample is dropped once there are FRAME_TIME_SAMPLES of them
+    fn push(&mut self, delta_time: f32) {
+        if self.samples.len() == FRAME_TIME_SAMPLES {
+            self.total -= self.samples.pop_front().unwrap();
+        }
+
+        self.samples.push_back(delta_time);
+        self.total += delta_time;
+    }
+
+    // None until the first non zero sample
+    fn average(&self) -> Option<f32> {
+        match self.samples.is_empty() {
+            true => None,
+            false => Some(self.total/self.samples.len() as f32).filter(|average| *average > 0.0),
+        }
+    }
+}
+
+// Draws the frames per second, the frame time, the number of entities and the draw calls of the previous frame in the top
+// left corner of the window. Toggled with F3, hidden at startup. The messages of DebugDraw are drawn in the bottom left corner.
+pub struct DebugOverlaySystem {
+    frame_times: FrameTimes,
+    visible: bool,
+}
+
+impl DebugOverlaySystem {
+    pub fn new() -> Self {
+        Self {
+            frame_times: FrameTimes::new(),
+            visible: false,
+        }
+    }
+
+    fn text(&self, entity_count: usize, draw_calls: usize) -> String {
+        let (fps, frame_time) = match self.frame_times.average() {
+            Some(average) => (1.0/average, average*1000.0),
+            None => (0.0, 0.0),
+        };
+
+        format!("FPS: {:.0}\nFrame: {:.2} ms\nEntities: {}\nDraw calls: {}", fps, frame_time, entity_count, draw_calls)
+    }
+}
+
+impl System for DebugOverlaySystem {
+    fn name(&self) -> &'static str {
+        "debug_overlay"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, _entities: &BTreeSet<EntityId>, graphics: &mut Graphics, input: &Input, _audio: &mut Audio, delta_time: f32) {
+        // Measured even while hidden so the average is ready when the overlay is shown
+        self.frame_times.push(delta_time);
+
+        if input.is_key_down(TOGGLE_KEY) {
+            self.visible = !self.visible;
+        }
+
+        if self.visible {
+            let text = self.text(ecs.entity_count(), graphics.draw_calls);
+            graphics.debug_draw.draw_screen_text(&text, TEXT_POSITION, TEXT_SIZE, TEXT_COLOR);
+        }
+
+        if let Some(message) = graphics.debug_draw.message().map(str::to_owned) {
+            let position = [MESSAGE_MARGIN, graphics.size.1 as f32 - MESSAGE_MARGIN - TEXT_SIZE];
+            graphics.debug_draw.draw_screen_text(&message, position, TEXT_SIZE, TEXT_COLOR);
+        }
+    }
+
+    fn is_system_entity(&self, _entity: &EntityId, _ecs: &EntityComponentSystem) -> bool {
+        false
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_time_is_averaged_over_the_last_frames() {
+        let mut frame_times = FrameTimes::new();
+        assert_eq!(frame_times.average(), None);
+
+        frame_times.push(0.0);
+        assert_eq!(frame_times.average(), None);
+
+        for _ in 0..FRAME_TIME_SAMPLES {
+            frame_times.push(0.01);
+        }
+        assert!((frame_times.average().unwrap() - 0.01).abs() < 1e-6);
+
+        // A single slow frame only moves the average by its share of the samples
+        frame_times.push(0.61);
+        assert!((frame_times.average().unwrap() - 0.02).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::lifetime::Lifetime;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+
+pub struct LifetimeSystem {}
+
+impl LifetimeSystem {
+    // Expired entities are queued for destruction, so they are destroyed at the beginning of the next frame
+    fn tick(ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, delta_time: f32) {
+        let mut expired = Vec::new();
+
+        {
+            let mut lifetimes = ecs.get_component_set::<Lifetime>().unwrap().borrow_mut();
+            for entity in entities {
+                if let Some(lifetime) = lifetimes.get_mut(entity) {
+                    lifetime.remaining -= delta_time;
+                    if lifetime.remaining <= 0.0 {
+                        expired.push(*entity);
+                    }
+                }
+            }
+        }
+
+        for entity in expired {
+            ecs.remove_entity(entity);
+        }
+    }
+}
+
+impl System for LifetimeSystem {
+    fn name(&self) -> &'static str {
+        "lifetime"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        LifetimeSystem::tick(ecs, entities, delta_time);
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Lifetime>(entity)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+
+    #[test]
+    fn entity_expires_after_its_lifetime() {
+        let mut ecs = EntityComponentSystem::new(4, Resources::new());
+        ecs.load_from_json(&serde_json::json!({
+            "entities": [{ "EntityId": { "index": 0, "generation": 0 }, "Lifetime": { "remaining": 1.0 } }]
+        })).unwrap();
+        let entities : BTreeSet<EntityId> = ecs.create_entities().into_iter().collect();
+        let entity = *entities.iter().next().unwrap();
+
+        LifetimeSystem::tick(&mut ecs, &entities, 0.6);
+        assert!(ecs.destroy_entities().is_empty());
+
+        LifetimeSystem::tick(&mut ecs, &entities, 0.6);
+        assert_eq!(ecs.destroy_entities(), vec!(entity));
+        assert!(!ecs.is_alive(&entity));
+        assert!(!ecs.has_component::<Lifetime>(&entity));
+    }
+}
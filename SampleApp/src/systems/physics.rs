@@ -0,0 +1,144 @@
+use crate::transform::Transform;
+use crate::rigid_body::RigidBody;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+use cgmath::{InnerSpace, Zero};
+
+// Integrates the velocity and rotation of rigid bodies. Forces are applied by other systems through RigidBody::acceleration.
+pub struct PhysicsSystem {}
+
+// Velocity and displacement after delta_time of a body under a constant acceleration, with a damping that keeps that fraction
+// of the velocity per second. This is the exact solution of dv/dt = a - k*v with k = -ln(damping), so the motion doesn't depend
+// on how the time is split into steps. Computed in f64 since the terms nearly cancel out for a weak damping.
+fn integrate_damped(velocity: cgmath::Vector3<f32>, acceleration: cgmath::Vector3<f32>, damping: f32, delta_time: f32) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+    let dt = delta_time as f64;
+    let k = -(damping as f64).ln();
+
+    // Integrals over the step of the decay of the velocity and of the velocity gained from the acceleration
+    let (velocity_time, acceleration_time) = if (k*dt).abs() < 1e-6 {
+        (dt, dt*dt/2.0)
+    } else {
+        let velocity_time = -(-k*dt).exp_m1()/k;
+        (velocity_time, (dt - velocity_time)/k)
+    };
+
+    let decay = (damping as f64).powf(dt) as f32;
+    (velocity*decay + acceleration*velocity_time as f32, velocity*velocity_time as f32 + acceleration*acceleration_time as f32)
+}
+
+impl PhysicsSystem {
+    pub fn integrate(&self, ecs: &EntityComponentSystem, entities: &BTreeSet<EntityId>, delta_time: f32) {
+        let mut transforms = ecs.get_component_set::<Transform>().unwrap().borrow_mut();
+        let mut rigid_bodies = ecs.get_component_set::<RigidBody>().unwrap().borrow_mut();
+
+        for entity in entities {
+            match (transforms.get_mut(&entity), rigid_bodies.get_mut(&entity)) {
+                (Some(transform), Some(rigid_body)) => {
+                    let (mut velocity, mut displacement) = integrate_damped(rigid_body.velocity, rigid_body.acceleration + rigid_body.gravity, rigid_body.damping, delta_time);
+                    rigid_body.acceleration = cgmath::Vector3::zero();
+
+                    // Clamped after integrating so the position never moves faster than the maximum speed either
+                    if let Some(max_speed) = rigid_body.max_speed {
+                        if velocity.magnitude2() > max_speed*max_speed {
+                            velocity = velocity.normalize_to(max_speed);
+                        }
+                        if displacement.magnitude2() > (max_speed*delta_time).powi(2) {
+                            displacement = displacement.normalize_to(max_speed*delta_time);
+                        }
+                    }
+                    rigid_body.velocity = velocity;
+
+                    transform.store_previous();
+                    transform.position += displacement;
+                    transform.rotation = transform.rotation*cgmath::Quaternion::from(
+                        cgmath::Euler {
+                            x: cgmath::Deg(0.0),
+                            y: cgmath::Deg(0.0),
+                            z: cgmath::Deg(rigid_body.angular_velocity*delta_time),
+                        });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl System for PhysicsSystem {
+    fn name(&self) -> &'static str {
+        "physics"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        self.integrate(ecs, entities, delta_time);
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Transform>(entity) && ecs.has_component::<RigidBody>(entity)
+    }
+
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["control"]
+    }
+
+    fn is_fixed_step(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+    use cgmath::InnerSpace;
+
+    // Position and velocity of a body integrated over duration in steps of the same length
+    fn motion_after(steps: usize, duration: f32, gravity: cgmath::Vector3<f32>) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let mut ecs = EntityComponentSystem::new(1, Resources::new());
+        ecs.load_from_json(&serde_json::json!({ "entities": [{
+            "EntityId": { "index": 0, "generation": 0 },
+            "Transform": {},
+            "RigidBody": { "velocity": { "x": 100.0, "y": 50.0, "z": 0.0 }, "damping": 0.3, "gravity": gravity }
+        }]})).unwrap();
+        let entities = ecs.create_entities().into_iter().collect();
+
+        for _ in 0..steps {
+            PhysicsSystem {}.integrate(&ecs, &entities, duration/steps as f32);
+        }
+
+        let entity = EntityId { index: 0, generation: 0 };
+        let position = ecs.get_component_set::<Transform>().unwrap().borrow().get(&entity).unwrap().position;
+        let velocity = ecs.get_component_set::<RigidBody>().unwrap().borrow().get(&entity).unwrap().velocity;
+        (position, velocity)
+    }
+
+    #[test]
+    fn damping_does_not_depend_on_the_step_length() {
+        let (one_step_position, one_step) = motion_after(1, 2.0, cgmath::Vector3::zero());
+        let (many_steps_position, many_steps) = motion_after(240, 2.0, cgmath::Vector3::zero());
+
+        assert!((one_step - many_steps).magnitude() < 1e-2, "{:?} != {:?}", one_step, many_steps);
+        assert!((one_step - cgmath::Vector3::new(9.0, 4.5, 0.0)).magnitude() < 1e-3, "{:?}", one_step);
+        assert!((one_step_position - many_steps_position).magnitude() < 1e-2, "{:?} != {:?}", one_step_position, many_steps_position);
+    }
+
+    #[test]
+    fn damped_acceleration_does_not_depend_on_the_step_length() {
+        let gravity = cgmath::Vector3::new(0.0, -300.0, 0.0);
+        let (one_step_position, one_step) = motion_after(1, 2.0, gravity);
+        let (many_steps_position, many_steps) = motion_after(240, 2.0, gravity);
+
+        assert!((one_step - many_steps).magnitude() < 1e-2, "{:?} != {:?}", one_step, many_steps);
+        assert!((one_step_position - many_steps_position).magnitude() < 1e-2, "{:?} != {:?}", one_step_position, many_steps_position);
+
+        // The velocity decays toward the terminal velocity gravity/ln(1/damping)
+        let terminal = gravity/(1.0f32/0.3).ln();
+        let expected = terminal + (cgmath::Vector3::new(100.0, 50.0, 0.0) - terminal)*0.3f32.powf(2.0);
+        assert!((one_step - expected).magnitude() < 1e-2, "{:?} != {:?}", one_step, expected);
+    }
+}
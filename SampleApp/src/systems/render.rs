@@ -1,102 +1,852 @@
-use crate::graphics::Graphics;
-use crate::graphics::ModelProperties;
+use crate::graphics::{DynamicBuffer, Graphics};
+use crate::graphics::{ModelProperties, FULL_UV_RECT};
+use crate::graphics::MISSING_ASSET;
+use crate::graphics::{BlendMode, ColorSpace, TextureBindGroups, TextureFilter};
+use crate::graphics::{SkinnedVertex, Vertex};
+use crate::texture::Texture;
+use crate::graphics::Frame;
+use crate::graphics::Mesh as GraphicsMesh;
+use crate::graphics::{pack_bone_palettes, SKINNED_PIPELINE, BONE_PALETTE_SIZE};
+use crate::font::{append_text_quads, FONT_TEXTURE};
+use crate::debug_draw::DEBUG_LINES;
 use crate::ecs::EntityComponentSystem;
 use crate::transform::Transform;
-use crate::camera::Camera;
+use crate::transform_2d::Transform2D;
+use crate::camera::{Camera, Frustum};
+use crate::collider::Collider;
 use crate::mesh::Mesh;
+use crate::text::Text;
+use crate::tilemap::Tilemap;
+use crate::light::Light;
+use crate::skeleton::Skeleton;
+use crate::visible::Visible;
 use crate::entity::EntityId;
 use crate::system::System;
 use crate::input::Input;
-use std::collections::HashSet;
-use wgpu::SwapChainTexture;
+use crate::audio::Audio;
+use std::collections::{BTreeSet, HashSet};
+use std::collections::HashMap;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
 
-pub struct RenderSystem {}
+pub struct RenderSystem {
+    // Missing assets are reported only once to avoid flooding the console every frame
+    reported_missing_assets: HashSet<String>,
+    // Geometry of every tilemap with tiles, rebuilt when its tiles change
+    tilemaps: HashMap<EntityId, TilemapGeometry>,
+    // ModelProperties of every instance drawn this frame
+    instance_buffer: DynamicBuffer,
+    // Bone palettes of the skinned meshes drawn this frame, bound by bone_bind_group
+    bone_buffer: DynamicBuffer,
+    // Recreated with the bone buffer, None until a skinned mesh is drawn
+    bone_bind_group: Option<wgpu::BindGroup>,
+}
+
+struct TilemapGeometry {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // Indices of each row of tiles
+    rows: Vec<Range<u32>>,
+}
+
+// Shader, mesh, texture, color space of the texture, filter and blend mode shared by the meshes of a batch
+type BatchKey<'a> = (&'a str, &'a str, &'a str, ColorSpace, TextureFilter, BlendMode);
+
+struct Batch<'a> {
+    layer: i32,
+    order: i32,
+    shader_name: &'a str,
+    mesh_name: &'a str,
+    diffuse_texture: &'a str,
+    diffuse_color_space: ColorSpace,
+    filter: TextureFilter,
+    blend_mode: BlendMode,
+    instances: Range<u32>,
+}
+
+// Skinned meshes are drawn one by one since each has its own bone palette
+struct SkinnedDraw<'a> {
+    layer: i32,
+    order: i32,
+    mesh_name: &'a str,
+    diffuse_texture: &'a str,
+    filter: TextureFilter,
+    blend_mode: BlendMode,
+    instance: u32,
+    palette_index: u32,
+}
+
+// Batches and skinned meshes are drawn in the same list so skinned meshes follow the layer and order of their Mesh component too
+enum MeshDraw<'a> {
+    Batch(Batch<'a>),
+    Skinned(SkinnedDraw<'a>),
+}
+
+impl MeshDraw<'_> {
+    // Layer, order and blend mode, so opaque meshes are drawn before the blended ones of the same layer and order
+    fn sort_key(&self) -> (i32, i32, BlendMode) {
+        match self {
+            MeshDraw::Batch(batch) => (batch.layer, batch.order, batch.blend_mode),
+            MeshDraw::Skinned(draw) => (draw.layer, draw.order, draw.blend_mode),
+        }
+    }
+
+    fn diffuse_texture(&self) -> &str {
+        match self {
+            MeshDraw::Batch(batch) => batch.diffuse_texture,
+            MeshDraw::Skinned(draw) => draw.diffuse_texture,
+        }
+    }
+}
+
+struct TilemapDraw<'a> {
+    entity: EntityId,
+    texture: &'a str,
+    filter: TextureFilter,
+    // Indices of the rows from the first to the last one in view
+    indices: Range<u32>,
+    instance: u32,
+}
+
+struct TextDraw {
+    indices: Range<u32>,
+    instance: u32,
+}
 
 impl RenderSystem {
-    fn render(&self, frame: &SwapChainTexture, ecs: &mut EntityComponentSystem, entities: &HashSet<EntityId>, graphics: &mut Graphics) {
-        for camera_entity in ecs.cameras() {
-            let camera_components = ecs.get_component_set::<Camera>().unwrap().borrow();
-            let camera_component = camera_components.get(camera_entity);
-
-            match camera_component {
-                Some(camera) => {
-                    graphics.uniforms.update_view_proj(camera.build_view_projection_matrix());
-                    graphics.queue.write_buffer(&graphics.uniform_buffer, 0, bytemuck::cast_slice(&[graphics.uniforms]));
-
-                    let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                        label: Some("Render Encoder"),
-                    });
-                
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass"),
-                        color_attachments: &[
-                            wgpu::RenderPassColorAttachment {
-                                view: &frame.view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(camera.clear_color),
-                                    store: true,
-                                }
-                            }
-                        ],
-                        depth_stencil_attachment: None,
-                    });
-
-                    render_pass.set_bind_group(1, &graphics.uniform_bind_group, &[]);
-
-                    let transform_components = ecs.get_component_set::<Transform>().unwrap().borrow();
-                    let mesh_components = ecs.get_component_set::<Mesh>().unwrap().borrow();
-
-                    for entity in entities {
-                        let transform_component = transform_components.get(entity);
-                        let mesh_component = mesh_components.get(entity);
-                        match (transform_component, mesh_component)  {
-                            (Some(transform), Some(mesh_component)) => {
-                                
-                                render_pass.set_pipeline(&graphics.pipelines.get(&mesh_component.shader_name).unwrap());
-                                let model = graphics.models.get(&mesh_component.mesh_name).unwrap();
-                                render_pass.set_bind_group(0, &graphics.textures.get(&mesh_component.diffuse_texture).unwrap(), &[]);
-                                render_pass.set_vertex_buffer(0, model.vertex_buffer.as_ref().unwrap().slice(..));
-                                render_pass.set_index_buffer(model.index_buffer.as_ref().unwrap().slice(..), wgpu::IndexFormat::Uint16);
-
-                                let model_properties = ModelProperties {
-                                    model_matrix: transform.build_model_matrix().into(),
-                                };
-
-                                render_pass.set_push_constants(wgpu_types::ShaderStage::VERTEX, 0, bytemuck::cast_slice(&[model_properties]));
-                                render_pass.draw_indexed(0..model.indices.len() as u32, 0, 0..1);
-                            }
-                            _ => {}
-                        };
+    pub fn new() -> Self {
+        Self {
+            reported_missing_assets: HashSet::new(),
+            tilemaps: HashMap::new(),
+            instance_buffer: DynamicBuffer::new("Instance Buffer", wgpu::BufferUsage::VERTEX),
+            bone_buffer: DynamicBuffer::new("Bone Buffer", wgpu::BufferUsage::UNIFORM),
+            bone_bind_group: None,
+        }
+    }
+
+    fn render(&mut self, frame: &Frame, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, graphics: &mut Graphics) {
+        self.update_cameras_aspect_ratio(ecs, graphics);
+
+        // Hidden entities stay in the system so toggling them doesn't change its entities, they are skipped when drawing instead
+        let visible_components = ecs.get_component_set::<Visible>().unwrap().borrow();
+        let is_hidden = |entity: &EntityId| visible_components.get(entity).map_or(false, |visible| !visible.0);
+
+        let camera_components = ecs.get_component_set::<Camera>().unwrap().borrow();
+        let mut cameras : Vec<(&EntityId, &Camera)> = ecs.cameras().iter()
+            .filter_map(|entity| camera_components.get(entity).map(|camera| (entity, camera)))
+            .collect();
+
+        // Cameras with a render target are drawn first so the cameras drawing meshes that sample their texture see this frame's
+        // contents. Then cameras with a lower order are drawn first, so overlays such as HUD cameras are drawn on top.
+        cameras.sort_by_key(|(entity, camera)| (camera.render_target.is_none(), camera.order, entity.index));
+
+        // The render targets of removed cameras are dropped
+        let target_names : HashSet<&str> = cameras.iter()
+            .filter_map(|(_, camera)| camera.render_target.as_ref().map(|render_target| render_target.name.as_str()))
+            .collect();
+        graphics.retain_texture_targets(|name| target_names.contains(name));
+
+        // Cameras with an empty viewport are not drawn
+        cameras.retain(|(_, camera)| {
+            let (_, _, width, height) = camera.viewport_rect(camera.target_size(graphics.size));
+            width >= 1.0 && height >= 1.0
+        });
+
+        // Render targets are created before the meshes look up their textures
+        for (_, camera) in &cameras {
+            if let Some(render_target) = &camera.render_target {
+                graphics.prepare_texture_target(&render_target.name, (render_target.width, render_target.height));
+            }
+        }
+
+        // Meshes outside of every camera are not drawn
+        let frustums : Vec<Frustum> = cameras.iter().map(|(_, camera)| camera.frustum()).collect();
+
+        let transform_components = ecs.get_component_set::<Transform>().unwrap().borrow();
+        let transform_2d_components = ecs.get_component_set::<Transform2D>().unwrap().borrow();
+        let interpolation_alpha = graphics.interpolation_alpha;
+        // Transform2D entities skip the 3D matrix products. They aren't moved by fixed steps so they aren't interpolated.
+        let entity_model_matrix = |entity: &EntityId| -> Option<[[f32; 4]; 4]> {
+            match (transform_components.get(entity), transform_2d_components.get(entity)) {
+                (Some(transform), _) => Some(transform.interpolated_matrix(interpolation_alpha).into()),
+                (None, Some(transform_2d)) => Some(transform_2d.build_model_matrix().into()),
+                (None, None) => None,
+            }
+        };
+
+        let mesh_components = ecs.get_component_set::<Mesh>().unwrap().borrow();
+        let collider_components = ecs.get_component_set::<Collider>().unwrap().borrow();
+        let skeleton_components = ecs.get_component_set::<Skeleton>().unwrap().borrow();
+        let mut skinned_entities : Vec<([[f32; 4]; 4], &Mesh, &Skeleton)> = Vec::new();
+
+        // Layer and order of the mesh, what its batch shares and entity index, for sorting
+        let mut meshes : Vec<((i32, i32, BatchKey, usize), ModelProperties)> = Vec::new();
+
+        for entity in entities.iter().filter(|entity| !is_hidden(entity)) {
+            match (entity_model_matrix(entity), mesh_components.get(entity)) {
+                (Some(model_matrix), Some(mesh_component)) => {
+                    if let Some(skeleton) = skeleton_components.get(entity) {
+                        skinned_entities.push((model_matrix, mesh_component, skeleton));
+                        continue;
+                    }
+
+                    if !is_visible(&frustums, &model_matrix, collider_components.get(entity)) {
+                        continue;
                     }
-                
-                    drop(render_pass);
-                
-                    // Finish the command buffer, and to submit it to the gpu's render queue.
-                    graphics.queue.submit(std::iter::once(encoder.finish()));
+
+                    let batch_key = (mesh_component.shader_name.as_str(), mesh_component.mesh_name.as_str(), mesh_component.diffuse_texture.as_str(), mesh_component.diffuse_color_space, mesh_component.filter, mesh_component.blend_mode);
+                    meshes.push(((mesh_component.layer, mesh_component.order, batch_key, entity.index), ModelProperties {
+                        model_matrix,
+                        color: mesh_component.color,
+                        uv_rect: [mesh_component.uv_offset[0], mesh_component.uv_offset[1], mesh_component.uv_scale[0], mesh_component.uv_scale[1]],
+                    }));
                 }
-                None => {}
+                _ => {}
+            };
+        }
+
+        // Meshes are drawn by increasing layer and order. Meshes of the same layer and order are grouped so the ones sharing the
+        // same shader, mesh, texture and blend mode are drawn with a single instanced draw call, then sorted by entity index.
+        meshes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut batches : Vec<Batch> = Vec::new();
+        let mut instances : Vec<ModelProperties> = Vec::new();
+        for ((layer, order, batch_key, _), model_properties) in meshes {
+            let instance = instances.len() as u32;
+            instances.push(model_properties);
+
+            match batches.last_mut() {
+                Some(batch) if batch.layer == layer && batch.order == order && (batch.shader_name, batch.mesh_name, batch.diffuse_texture, batch.diffuse_color_space, batch.filter, batch.blend_mode) == batch_key => batch.instances.end += 1,
+                _ => batches.push(Batch {
+                    layer,
+                    order,
+                    shader_name: batch_key.0,
+                    mesh_name: batch_key.1,
+                    diffuse_texture: batch_key.2,
+                    diffuse_color_space: batch_key.3,
+                    filter: batch_key.4,
+                    blend_mode: batch_key.5,
+                    instances: instance..instance + 1,
+                }),
             }
         }
+
+        // The shader of the mesh component is ignored, skinned meshes always use the skinned pipeline
+        let mut skinned_draws : Vec<SkinnedDraw> = Vec::new();
+        let mut palettes : Vec<&[[[f32; 4]; 4]]> = Vec::new();
+        for (model_matrix, mesh_component, skeleton) in skinned_entities {
+            if graphics.assets.find::<GraphicsMesh<SkinnedVertex>>(&mesh_component.mesh_name).is_none() {
+                graphics.load_skinned_mesh(&mesh_component.mesh_name);
+            }
+
+            if graphics.skinned_mesh(&mesh_component.mesh_name).is_none() {
+                self.report_missing_asset("skinned mesh", &mesh_component.mesh_name);
+                continue;
+            }
+
+            if graphics.load_pipeline(SKINNED_PIPELINE, mesh_component.blend_mode).is_err() {
+                self.report_missing_asset("shader", SKINNED_PIPELINE);
+                continue;
+            }
+
+            if graphics.assets.find::<TextureBindGroups>(&mesh_component.diffuse_texture).is_none() {
+                graphics.load_texture(&mesh_component.diffuse_texture, mesh_component.diffuse_color_space);
+            }
+
+            let diffuse_texture = match graphics.texture(&mesh_component.diffuse_texture, mesh_component.filter) {
+                Some(_) => mesh_component.diffuse_texture.as_str(),
+                None => {
+                    self.report_missing_asset("texture", &mesh_component.diffuse_texture);
+                    MISSING_ASSET
+                }
+            };
+
+            skinned_draws.push(SkinnedDraw {
+                layer: mesh_component.layer,
+                order: mesh_component.order,
+                mesh_name: &mesh_component.mesh_name,
+                diffuse_texture,
+                filter: mesh_component.filter,
+                blend_mode: mesh_component.blend_mode,
+                instance: instances.len() as u32,
+                palette_index: palettes.len() as u32,
+            });
+            palettes.push(&skeleton.palette);
+            instances.push(ModelProperties {
+                model_matrix,
+                color: mesh_component.color,
+                uv_rect: [mesh_component.uv_offset[0], mesh_component.uv_offset[1], mesh_component.uv_scale[0], mesh_component.uv_scale[1]],
+            });
+        }
+
+        // All the text is put in a single vertex buffer. Each text is drawn with its own instance for the transform and color.
+        let text_components = ecs.get_component_set::<Text>().unwrap().borrow();
+        let mut text_draws : Vec<TextDraw> = Vec::new();
+        let mut text_vertices : Vec<Vertex> = Vec::new();
+        let mut text_indices : Vec<u32> = Vec::new();
+
+        for entity in entities.iter().filter(|entity| !is_hidden(entity)) {
+            if let (Some(model_matrix), Some(text)) = (entity_model_matrix(entity), text_components.get(entity)) {
+                let start = text_indices.len() as u32;
+                append_text_quads(&text.text, text.size, &mut text_vertices, &mut text_indices);
+
+                text_draws.push(TextDraw {
+                    indices: start..text_indices.len() as u32,
+                    instance: instances.len() as u32,
+                });
+                instances.push(ModelProperties {
+                    model_matrix,
+                    color: text.color,
+                    uv_rect: FULL_UV_RECT,
+                });
+            }
+        }
+
+        let mut screen_text_draws : Vec<TextDraw> = Vec::new();
+        for screen_text in graphics.debug_draw.screen_texts() {
+            let start = text_indices.len() as u32;
+            append_text_quads(&screen_text.text, screen_text.size, &mut text_vertices, &mut text_indices);
+
+            screen_text_draws.push(TextDraw {
+                indices: start..text_indices.len() as u32,
+                instance: instances.len() as u32,
+            });
+            // The screen y axis goes down while the text goes down from its origin along -y
+            let [x, y] = screen_text.position;
+            instances.push(ModelProperties {
+                model_matrix: cgmath::Matrix4::from_translation(cgmath::Vector3::new(x, -y, 0.0)).into(),
+                color: screen_text.color,
+                uv_rect: FULL_UV_RECT,
+            });
+        }
+
+        // Each tilemap is drawn with a single draw call, without the rows out of view at its top and bottom
+        let mut tilemap_components = ecs.get_component_set::<Tilemap>().unwrap().borrow_mut();
+        self.tilemaps.retain(|entity, _| tilemap_components.get(entity).is_some());
+        for entity in entities {
+            if let Some(tilemap) = tilemap_components.get_mut(entity) {
+                if tilemap.take_changed() || !self.tilemaps.contains_key(entity) {
+                    self.update_tilemap_geometry(entity, tilemap, graphics);
+                }
+            }
+        }
+
+        let mut tilemap_draws : Vec<TilemapDraw> = Vec::new();
+        for entity in entities.iter().filter(|entity| !is_hidden(entity)) {
+            if let (Some(model_matrix), Some(tilemap), Some(geometry)) = (entity_model_matrix(entity), tilemap_components.get(entity), self.tilemaps.get(entity)) {
+                let indices = visible_rows(&geometry.rows, tilemap, &model_matrix, &frustums);
+                if indices.is_empty() {
+                    continue;
+                }
+
+                tilemap_draws.push(TilemapDraw {
+                    entity: *entity,
+                    texture: &tilemap.texture,
+                    filter: tilemap.filter,
+                    indices,
+                    instance: instances.len() as u32,
+                });
+                instances.push(ModelProperties {
+                    model_matrix,
+                    color: [1.0, 1.0, 1.0, 1.0],
+                    uv_rect: FULL_UV_RECT,
+                });
+            }
+        }
+
+        // Debug lines are instances of the unit line mesh
+        let debug_lines_start = instances.len() as u32;
+        instances.extend_from_slice(graphics.debug_draw.lines());
+        let debug_lines = debug_lines_start..instances.len() as u32;
+
+        let text_buffers = match text_vertices.is_empty() {
+            true => None,
+            false => Some((
+                graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Text Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&text_vertices),
+                    usage: wgpu::BufferUsage::VERTEX,
+                }),
+                graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Text Index Buffer"),
+                    contents: bytemuck::cast_slice(&text_indices),
+                    usage: wgpu::BufferUsage::INDEX,
+                }),
+            )),
+        };
+
+        // Text and tilemaps reuse the sprite pipeline
+        let sprite_shader_name = match graphics.has_pipeline("sprite", BlendMode::AlphaBlend) {
+            true => "sprite",
+            false => MISSING_ASSET,
+        };
+
+        // Assets used for the first time are loaded. The reference is kept until the game unloads them, usually with the level.
+        for draw in &mut tilemap_draws {
+            if graphics.assets.find::<TextureBindGroups>(draw.texture).is_none() {
+                graphics.load_texture(draw.texture, ColorSpace::Srgb);
+            }
+
+            if graphics.texture(draw.texture, draw.filter).is_none() {
+                self.report_missing_asset("texture", draw.texture);
+                draw.texture = MISSING_ASSET;
+            }
+        }
+
+        for batch in &mut batches {
+            // Meshes are loaded first since loading a gltf primitive also registers the textures of its model
+            if graphics.assets.find::<GraphicsMesh>(batch.mesh_name).is_none() {
+                graphics.load_mesh(batch.mesh_name);
+            }
+
+            if graphics.mesh(batch.mesh_name).is_none() {
+                self.report_missing_asset("mesh", batch.mesh_name);
+                batch.mesh_name = MISSING_ASSET;
+            }
+
+            if graphics.assets.find::<TextureBindGroups>(batch.diffuse_texture).is_none() {
+                graphics.load_texture(batch.diffuse_texture, batch.diffuse_color_space);
+            }
+
+            if graphics.texture(batch.diffuse_texture, batch.filter).is_none() {
+                self.report_missing_asset("texture", batch.diffuse_texture);
+                batch.diffuse_texture = MISSING_ASSET;
+            }
+
+            if graphics.load_pipeline(batch.shader_name, batch.blend_mode).is_err() {
+                self.report_missing_asset("shader", batch.shader_name);
+                batch.shader_name = MISSING_ASSET;
+
+                if graphics.load_pipeline(MISSING_ASSET, batch.blend_mode).is_err() {
+                    batch.blend_mode = BlendMode::AlphaBlend;
+                }
+            }
+        }
+
+        let mut mesh_draws : Vec<MeshDraw> = batches.into_iter().map(MeshDraw::Batch)
+            .chain(skinned_draws.into_iter().map(MeshDraw::Skinned))
+            .collect();
+        sort_mesh_draws(&mut mesh_draws);
+
+        // The buffer is kept between frames and only grows, so the bind group is only recreated with it
+        if !palettes.is_empty() && self.bone_buffer.write(&graphics.device, &graphics.queue, bytemuck::cast_slice(&pack_bone_palettes(&palettes))) {
+            self.bone_bind_group = Some(graphics.create_bone_bind_group(self.bone_buffer.buffer()));
+        }
+
+        self.instance_buffer.write(&graphics.device, &graphics.queue, bytemuck::cast_slice(&instances));
+        let instance_buffer = self.instance_buffer.buffer();
+
+        // Only one directional light is supported
+        let light_components = ecs.get_component_set::<Light>().unwrap().borrow();
+        match light_components.iter().next() {
+            Some((_, light)) => graphics.uniforms.update_light(light),
+            None => graphics.uniforms.clear_light(),
+        }
+
+        if cameras.iter().all(|(_, camera)| camera.render_target.is_some()) {
+            self.clear(frame, graphics);
+        }
+
+        let mut draw_calls = 0;
+
+        // Names of the render targets already drawn to this frame, None for the frame
+        let mut drawn_targets : HashSet<Option<&str>> = HashSet::new();
+        for (_, camera) in &cameras {
+            graphics.uniforms.update_view_proj(camera.build_view_projection_matrix());
+            graphics.queue.write_buffer(&graphics.uniform_buffer, 0, bytemuck::cast_slice(&[graphics.uniforms]));
+
+            let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+            let target_name = camera.render_target.as_ref().map(|render_target| render_target.name.as_str());
+            let ((color_view, resolve_target), depth_view) = match target_name {
+                Some(name) => {
+                    let target = graphics.texture_target(name).unwrap();
+                    (color_attachment(&target.color.view, target.multisampled.as_ref()), &target.depth.view)
+                },
+                None => (color_attachment(frame.view(), graphics.multisampled_texture.as_ref()), &graphics.depth_texture.view),
+            };
+
+            // Only the first camera of each target clears it. Clearing ignores the viewport so it would erase the other cameras.
+            let color_load = match drawn_targets.insert(target_name) {
+                true => wgpu::LoadOp::Clear(camera.clear_color),
+                false => wgpu::LoadOp::Load,
+            };
+        
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    wgpu::RenderPassColorAttachment {
+                        view: color_view,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: color_load,
+                            store: true,
+                        }
+                    }
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            let (x, y, width, height) = camera.viewport_rect(camera.target_size(graphics.size));
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+
+            render_pass.set_bind_group(1, &graphics.uniform_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+            // A texture can't be sampled while it is rendered to, so a camera doesn't see the meshes showing its own target
+            let samples_target = |texture: &str| target_name == Some(texture);
+
+            // Tilemaps are usually backgrounds so they are drawn before the meshes blending over them
+            if !tilemap_draws.is_empty() {
+                render_pass.set_pipeline(&graphics.pipeline(sprite_shader_name, BlendMode::AlphaBlend).unwrap());
+            }
+
+            for draw in tilemap_draws.iter().filter(|draw| !samples_target(draw.texture)) {
+                let geometry = &self.tilemaps[&draw.entity];
+                render_pass.set_bind_group(0, graphics.texture(draw.texture, draw.filter).unwrap(), &[]);
+                render_pass.set_vertex_buffer(0, geometry.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(geometry.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(draw.indices.clone(), 0, draw.instance..draw.instance + 1);
+                draw_calls += 1;
+            }
+
+            for mesh_draw in mesh_draws.iter().filter(|mesh_draw| !samples_target(mesh_draw.diffuse_texture())) {
+                match mesh_draw {
+                    MeshDraw::Batch(batch) => {
+                        render_pass.set_pipeline(&graphics.pipeline(batch.shader_name, batch.blend_mode).unwrap());
+                        let model = graphics.mesh(batch.mesh_name).unwrap();
+                        render_pass.set_bind_group(0, graphics.texture(batch.diffuse_texture, batch.filter).unwrap(), &[]);
+                        render_pass.set_vertex_buffer(0, model.vertex_buffer.as_ref().unwrap().slice(..));
+                        render_pass.set_index_buffer(model.index_buffer.as_ref().unwrap().slice(..), model.indices.format());
+                        render_pass.draw_indexed(0..model.indices.len() as u32, 0, batch.instances.clone());
+                    },
+                    MeshDraw::Skinned(draw) => {
+                        render_pass.set_pipeline(&graphics.pipeline(SKINNED_PIPELINE, draw.blend_mode).unwrap());
+                        let model = graphics.skinned_mesh(draw.mesh_name).unwrap();
+                        render_pass.set_bind_group(0, graphics.texture(draw.diffuse_texture, draw.filter).unwrap(), &[]);
+                        render_pass.set_bind_group(2, self.bone_bind_group.as_ref().unwrap(), &[draw.palette_index*BONE_PALETTE_SIZE as u32]);
+                        render_pass.set_vertex_buffer(0, model.vertex_buffer.as_ref().unwrap().slice(..));
+                        render_pass.set_index_buffer(model.index_buffer.as_ref().unwrap().slice(..), model.indices.format());
+                        render_pass.draw_indexed(0..model.indices.len() as u32, 0, draw.instance..draw.instance + 1);
+                    },
+                }
+                draw_calls += 1;
+            }
+
+            if let Some((text_vertex_buffer, text_index_buffer)) = &text_buffers {
+                render_pass.set_pipeline(&graphics.pipeline(sprite_shader_name, BlendMode::AlphaBlend).unwrap());
+                render_pass.set_bind_group(0, graphics.texture(FONT_TEXTURE, TextureFilter::Nearest).unwrap(), &[]);
+                render_pass.set_vertex_buffer(0, text_vertex_buffer.slice(..));
+                render_pass.set_index_buffer(text_index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+                for text_draw in &text_draws {
+                    render_pass.draw_indexed(text_draw.indices.clone(), 0, text_draw.instance..text_draw.instance + 1);
+                }
+                draw_calls += text_draws.len();
+            }
+
+            if !debug_lines.is_empty() {
+                render_pass.set_pipeline(&graphics.pipeline(DEBUG_LINES, BlendMode::AlphaBlend).unwrap());
+                let model = graphics.mesh(DEBUG_LINES).unwrap();
+                render_pass.set_bind_group(0, graphics.texture(DEBUG_LINES, TextureFilter::Nearest).unwrap(), &[]);
+                render_pass.set_vertex_buffer(0, model.vertex_buffer.as_ref().unwrap().slice(..));
+                render_pass.set_index_buffer(model.index_buffer.as_ref().unwrap().slice(..), model.indices.format());
+                render_pass.draw_indexed(0..model.indices.len() as u32, 0, debug_lines.clone());
+                draw_calls += 1;
+            }
+        
+            drop(render_pass);
+        
+            // Finish the command buffer, and to submit it to the gpu's render queue.
+            graphics.queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        if let (false, Some((text_vertex_buffer, text_index_buffer))) = (screen_text_draws.is_empty(), &text_buffers) {
+            // Pixels from the top left corner of the window, with y going up so the text is drawn like world text
+            let (width, height) = (graphics.size.0 as f32, graphics.size.1 as f32);
+            graphics.uniforms.update_view_proj(cgmath::ortho(0.0, width, -height, 0.0, -1.0, 1.0));
+            graphics.queue.write_buffer(&graphics.uniform_buffer, 0, bytemuck::cast_slice(&[graphics.uniforms]));
+
+            let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screen Text Encoder"),
+            });
+
+            let (color_view, resolve_target) = color_attachment(frame.view(), graphics.multisampled_texture.as_ref());
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Screen Text Pass"),
+                color_attachments: &[
+                    wgpu::RenderPassColorAttachment {
+                        view: color_view,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }
+                    }
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &graphics.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&graphics.pipeline(sprite_shader_name, BlendMode::AlphaBlend).unwrap());
+            render_pass.set_bind_group(0, graphics.texture(FONT_TEXTURE, TextureFilter::Nearest).unwrap(), &[]);
+            render_pass.set_bind_group(1, &graphics.uniform_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, text_vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_index_buffer(text_index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+            for text_draw in &screen_text_draws {
+                render_pass.draw_indexed(text_draw.indices.clone(), 0, text_draw.instance..text_draw.instance + 1);
+            }
+            draw_calls += screen_text_draws.len();
+
+            drop(render_pass);
+            graphics.queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        graphics.draw_calls = draw_calls;
+    }
+
+    // Clears the frame when no camera draws it, so it never shows the contents of a previous frame
+    fn clear(&self, frame: &Frame, graphics: &Graphics) {
+        let mut encoder = graphics.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Clear Encoder"),
+        });
+
+        // The multisampled texture is cleared too since passes drawn over the frame load it
+        let (color_view, resolve_target) = color_attachment(frame.view(), graphics.multisampled_texture.as_ref());
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[
+                wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(graphics.clear_color()),
+                        store: true,
+                    }
+                }
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        graphics.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // Tilemaps without tiles have no geometry
+    fn update_tilemap_geometry(&mut self, entity: &EntityId, tilemap: &Tilemap, graphics: &Graphics) {
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        let rows = tilemap.build_geometry(&mut vertices, &mut indices);
+        if indices.is_empty() {
+            self.tilemaps.remove(entity);
+            return;
+        }
+
+        self.tilemaps.insert(*entity, TilemapGeometry {
+            vertex_buffer: graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tilemap Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsage::VERTEX,
+            }),
+            index_buffer: graphics.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tilemap Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsage::INDEX,
+            }),
+            rows,
+        });
+    }
+
+    fn report_missing_asset(&mut self, asset_type: &str, name: &str) {
+        if self.reported_missing_assets.insert(format!("{}:{}", asset_type, name)) {
+            eprintln!("Missing {} {}. Using a placeholder instead.", asset_type, name);
+        }
+    }
+
+    // Keeps the projection of every camera in sync with the window so a resize never distorts the scene
+    fn update_cameras_aspect_ratio(&self, ecs: &EntityComponentSystem, graphics: &Graphics) {
+        if graphics.size.1 == 0 {
+            return;
+        }
+
+        let mut camera_components = ecs.get_component_set::<Camera>().unwrap().borrow_mut();
+        for camera_entity in ecs.cameras() {
+            if let Some(camera) = camera_components.get_mut(camera_entity) {
+                let (_, _, width, height) = camera.viewport_rect(camera.target_size(graphics.size));
+                if width > 0.0 && height > 0.0 {
+                    camera.set_aspect_ratio(width/height);
+                }
+            }
+        }
+    }
+}
+
+// The view to render to and the one to resolve to. When multisampling the multisampled texture is rendered to and resolved to
+// the view, which keeps a single sample per pixel.
+fn color_attachment<'a>(view: &'a wgpu::TextureView, multisampled: Option<&'a Texture>) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+    match multisampled {
+        Some(multisampled) => (&multisampled.view, Some(view)),
+        None => (view, None),
+    }
+}
+
+// Radius of the sphere around a mesh of unit size, the quad or a unit cube, used when the entity has no collider
+const DEFAULT_BOUNDS_RADIUS: f32 = 0.87;
+
+// The bounds are a sphere around the position of the entity. Its radius is the default one scaled by the largest scale of
+// the transform, grown to contain the collider if there is one.
+fn is_visible(frustums: &[Frustum], model_matrix: &[[f32; 4]; 4], collider: Option<&Collider>) -> bool {
+    use cgmath::InnerSpace;
+
+    let center = cgmath::Vector3::new(model_matrix[3][0], model_matrix[3][1], model_matrix[3][2]);
+    let scale = largest_scale(model_matrix);
+    let radius = match collider {
+        Some(collider) => (DEFAULT_BOUNDS_RADIUS*scale).max(collider.half_extents.magnitude()),
+        None => DEFAULT_BOUNDS_RADIUS*scale,
+    };
+
+    frustums.iter().any(|frustum| frustum.intersects_sphere(center, radius))
+}
+
+fn largest_scale(model_matrix: &[[f32; 4]; 4]) -> f32 {
+    use cgmath::InnerSpace;
+
+    model_matrix[..3].iter()
+        .map(|axis| cgmath::Vector3::new(axis[0], axis[1], axis[2]).magnitude())
+        .fold(0.0, f32::max)
+}
+
+// Indices from the first to the last row of the tilemap inside a frustum, each row bounded by the sphere around it.
+// Empty if no row is in view.
+fn visible_rows(rows: &[Range<u32>], tilemap: &Tilemap, model_matrix: &[[f32; 4]; 4], frustums: &[Frustum]) -> Range<u32> {
+    use cgmath::InnerSpace;
+
+    let matrix = cgmath::Matrix4::from(*model_matrix);
+    let half_extents = cgmath::Vector2::new(tilemap.width as f32*tilemap.tile_size/2.0, tilemap.tile_size/2.0);
+    let radius = half_extents.magnitude()*largest_scale(model_matrix);
+
+    let mut visible = rows.iter().enumerate()
+        .filter(|(_, indices)| !indices.is_empty())
+        .filter(|(row, _)| {
+            let center = matrix*cgmath::Vector4::new(half_extents.x, -(*row as f32 + 0.5)*tilemap.tile_size, 0.0, 1.0);
+            frustums.iter().any(|frustum| frustum.intersects_sphere(center.truncate(), radius))
+        })
+        .map(|(_, indices)| indices);
+
+    match visible.next() {
+        Some(first) => first.start..visible.last().unwrap_or(first).end,
+        None => 0..0,
     }
 }
 
+// Sorted by layer, then order, then blend mode so the blended meshes blend with the opaque ones of their layer and order.
+// The sort is stable, so batches keep their entity order and skinned meshes come after the batches with the same key.
+fn sort_mesh_draws(mesh_draws: &mut [MeshDraw]) {
+    mesh_draws.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+}
+
 impl System for RenderSystem {
-    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &HashSet<EntityId>, graphics: &mut Graphics, _input: &Input, _delta_time: f32) {
-        let frame = graphics
-        .swap_chain
-        .get_current_frame();
-
-        match frame {
-            Ok(value) => { self.render(&value.output, ecs, entities, graphics) },
-            // Recreate the swap_chain if lost
-            Err(wgpu::SwapChainError::Lost) => graphics.resize(graphics.size),
-            // All other errors (OOM, Outdated, Timeout) should be resolved by the next frame
-            Err(e) => eprintln!("{:?}", e),
-        };
+    fn name(&self) -> &'static str {
+        "render"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, _delta_time: f32) {
+        graphics.draw_calls = 0;
+
+        // The frame is taken out of graphics while rendering since rendering needs to modify graphics
+        if let Some(frame) = graphics.current_frame.take() {
+            match graphics.take_capture_request() {
+                // The swap chain can't be read, so the frame is rendered to a texture that is shown and saved
+                Some(path) => {
+                    let texture = graphics.create_capture_texture();
+                    let capture = Frame::Offscreen(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+                    self.render(&capture, ecs, entities, graphics);
+
+                    if let Err(error) = graphics.copy_to_frame(&texture, &frame) {
+                        eprintln!("Failed to copy the captured frame to the window. Error: {:?}", error);
+                    }
+                    graphics.save_texture_png(&texture, &path);
+                },
+                None => self.render(&frame, ecs, entities, graphics),
+            }
+
+            graphics.current_frame = Some(frame);
+        }
+
+        graphics.debug_draw.clear();
     }
 
     fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
-        ecs.has_component::<Transform>(entity) && ecs.has_component::<Mesh>(entity)
+        (ecs.has_component::<Transform>(entity) || ecs.has_component::<Transform2D>(entity)) && (ecs.has_component::<Mesh>(entity) || ecs.has_component::<Text>(entity) || ecs.has_component::<Tilemap>(entity))
+    }
+
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["camera_control", "animation", "skeletal_animation", "debug_overlay"]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(layer: i32, order: i32, blend_mode: BlendMode, mesh_name: &'static str) -> MeshDraw<'static> {
+        MeshDraw::Batch(Batch {
+            layer,
+            order,
+            shader_name: "sprite",
+            mesh_name,
+            diffuse_texture: "ship.png",
+            diffuse_color_space: ColorSpace::Srgb,
+            filter: TextureFilter::Linear,
+            blend_mode,
+            instances: 0..1,
+        })
+    }
+
+    fn mesh_names<'a>(mesh_draws: &[MeshDraw<'a>]) -> Vec<&'a str> {
+        mesh_draws.iter().map(|mesh_draw| match mesh_draw {
+            MeshDraw::Batch(batch) => batch.mesh_name,
+            MeshDraw::Skinned(draw) => draw.mesh_name,
+        }).collect()
+    }
+
+    #[test]
+    fn blended_meshes_do_not_move_before_a_lower_order() {
+        let mut mesh_draws = vec![
+            batch(0, 1, BlendMode::Opaque, "player"),
+            batch(0, 0, BlendMode::AlphaBlend, "clouds"),
+            batch(0, 0, BlendMode::Opaque, "background"),
+            batch(0, 2, BlendMode::Additive, "glow"),
+        ];
+        sort_mesh_draws(&mut mesh_draws);
+
+        assert_eq!(mesh_names(&mesh_draws), ["background", "clouds", "player", "glow"]);
+    }
+
+    #[test]
+    fn layers_are_sorted_before_the_order() {
+        let mut mesh_draws = vec![
+            batch(1, -1, BlendMode::Opaque, "cockpit"),
+            batch(-1, 5, BlendMode::AlphaBlend, "stars"),
+            batch(0, 10, BlendMode::Opaque, "ship"),
+        ];
+        sort_mesh_draws(&mut mesh_draws);
+
+        assert_eq!(mesh_names(&mesh_draws), ["stars", "ship", "cockpit"]);
+    }
+}
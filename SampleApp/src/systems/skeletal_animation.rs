@@ -0,0 +1,32 @@
+use crate::skeleton::Skeleton;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+
+// Samples the clip playing on every skeleton. The RenderSystem uploads the resulting palettes for the skinning shader.
+pub struct SkeletalAnimationSystem {}
+
+impl System for SkeletalAnimationSystem {
+    fn name(&self) -> &'static str {
+        "skeletal_animation"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        let mut skeletons = ecs.get_component_set::<Skeleton>().unwrap().borrow_mut();
+
+        for entity in entities {
+            let skeleton = skeletons.get_mut(entity).unwrap();
+            skeleton.advance(delta_time);
+            skeleton.update_palette();
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Skeleton>(entity)
+    }
+
+}
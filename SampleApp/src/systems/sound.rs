@@ -0,0 +1,93 @@
+use crate::sound_emitter::SoundEmitter;
+use crate::collision::CollisionEvent;
+use crate::audio::Audio;
+use crate::input::Input;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::{BTreeSet, HashSet};
+
+pub struct AudioSystem {
+    // Emitters that were colliding on the previous step, so a sound isn't replayed while the entities keep overlapping
+    colliding: HashSet<EntityId>,
+}
+
+impl AudioSystem {
+    pub fn new() -> Self {
+        Self {
+            colliding: HashSet::new(),
+        }
+    }
+
+    // Returns the emitters that started colliding
+    fn update_collisions(&mut self, ecs: &EntityComponentSystem, entities: &BTreeSet<EntityId>) -> Vec<EntityId> {
+        let colliding : HashSet<EntityId> = ecs.events::<CollisionEvent>().iter()
+            .flat_map(|collision| vec!(collision.a, collision.b))
+            .filter(|entity| entities.contains(entity))
+            .collect();
+
+        let started = colliding.difference(&self.colliding).copied().collect();
+        self.colliding = colliding;
+        started
+    }
+}
+
+impl System for AudioSystem {
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, audio: &mut Audio, _delta_time: f32) {
+        let started = self.update_collisions(ecs, entities);
+
+        let emitters = ecs.get_component_set::<SoundEmitter>().unwrap().borrow();
+        for entity in started {
+            audio.play_sound(&emitters.get(&entity).unwrap().collision_sound);
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<SoundEmitter>(entity)
+    }
+
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["collision"]
+    }
+
+    // An emitter detached while colliding plays its sound again on its first collision once it is back
+    fn on_entity_removed(&mut self, entity: &EntityId, _ecs: &EntityComponentSystem) {
+        self.colliding.remove(entity);
+    }
+
+    // Runs after the collision system on every fixed step. The collision events of the frame accumulate over its fixed steps,
+    // so an entity that stops colliding on a step is only noticed on the next frame.
+    fn is_fixed_step(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+
+    #[test]
+    fn sound_plays_when_collision_starts() {
+        let mut ecs = EntityComponentSystem::new(4, Resources::new());
+        let mut system = AudioSystem::new();
+        let emitter = EntityId { index: 0, generation: 0 };
+        let other = EntityId { index: 1, generation: 0 };
+        let entities : BTreeSet<EntityId> = vec!(emitter).into_iter().collect();
+
+        ecs.send_event(CollisionEvent { a: other, b: emitter });
+        assert_eq!(system.update_collisions(&ecs, &entities), vec!(emitter));
+        assert!(system.update_collisions(&ecs, &entities).is_empty());
+
+        ecs.clear_events();
+        assert!(system.update_collisions(&ecs, &entities).is_empty());
+        ecs.send_event(CollisionEvent { a: other, b: emitter });
+        assert_eq!(system.update_collisions(&ecs, &entities), vec!(emitter));
+    }
+}
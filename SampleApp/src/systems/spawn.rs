@@ -0,0 +1,84 @@
+use crate::spawner::Spawner;
+use crate::transform::Transform;
+use crate::input::Input;
+use crate::audio::Audio;
+use crate::ecs::EntityComponentSystem;
+use crate::system::System;
+use crate::entity::EntityId;
+use crate::graphics::Graphics;
+use std::collections::BTreeSet;
+
+pub struct SpawnSystem {}
+
+impl SpawnSystem {
+    // Returns the prefabs to create and where, several times per spawner if delta_time spans more than one interval
+    fn tick(ecs: &EntityComponentSystem, entities: &BTreeSet<EntityId>, delta_time: f32) -> Vec<(String, cgmath::Vector3<f32>)> {
+        let mut spawners = ecs.get_component_set::<Spawner>().unwrap().borrow_mut();
+        let transforms = ecs.get_component_set::<Transform>().unwrap().borrow();
+
+        let mut spawns = Vec::new();
+        for entity in entities {
+            let spawner = spawners.get_mut(entity).unwrap();
+            let position = transforms.get(entity).unwrap().position;
+
+            // A spawner without a positive interval would never stop spawning
+            if spawner.interval <= 0.0 {
+                continue;
+            }
+
+            spawner.accumulator += delta_time;
+            while spawner.accumulator >= spawner.interval {
+                spawner.accumulator -= spawner.interval;
+                spawns.push((spawner.prefab.clone(), position));
+            }
+        }
+
+        spawns
+    }
+}
+
+impl System for SpawnSystem {
+    fn name(&self) -> &'static str {
+        "spawn"
+    }
+
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, delta_time: f32) {
+        for (prefab, position) in SpawnSystem::tick(ecs, entities, delta_time) {
+            ecs.add_entity_at(prefab, position);
+        }
+    }
+
+    fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool {
+        ecs.has_component::<Spawner>(entity) && ecs.has_component::<Transform>(entity)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+
+    #[test]
+    fn spawns_once_per_elapsed_interval() {
+        let mut ecs = EntityComponentSystem::new(4, Resources::new());
+        ecs.load_from_json(&serde_json::json!({
+            "entities": [{
+                "EntityId": { "index": 0, "generation": 0 },
+                "Transform": {
+                    "position": { "x": 1.0, "y": 2.0, "z": 0.0 },
+                    "scale": { "x": 1.0, "y": 1.0, "z": 1.0 },
+                    "rotation": { "v": { "x": 0.0, "y": 0.0, "z": 0.0 }, "s": 1.0 }
+                },
+                "Spawner": { "prefab": "enemy.json", "interval": 0.5 }
+            }]
+        })).unwrap();
+        let entities : BTreeSet<EntityId> = ecs.create_entities().into_iter().collect();
+
+        assert!(SpawnSystem::tick(&ecs, &entities, 0.4).is_empty());
+
+        let spawns = SpawnSystem::tick(&ecs, &entities, 0.7);
+        assert_eq!(spawns.len(), 2);
+        assert_eq!(spawns[0], ("enemy.json".to_owned(), cgmath::Vector3::new(1.0, 2.0, 0.0)));
+    }
+}
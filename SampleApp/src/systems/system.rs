@@ -1,54 +1,270 @@
 use crate::ecs::EntityComponentSystem;
-use crate::entity::EntityId;
+use crate::entity::{AllocatorStats, EntityId};
 use crate::input::Input;
+use crate::audio::Audio;
 use crate::graphics::Graphics;
 use crate::render::RenderSystem;
 use crate::control::ControlSystem;
-use std::collections::HashSet;
+use crate::physics::PhysicsSystem;
+use crate::collision::CollisionSystem;
+use crate::camera_control::CameraControlSystem;
+use crate::camera_shake_system::CameraShakeSystem;
+use crate::expiry::LifetimeSystem;
+use crate::spawn::SpawnSystem;
+use crate::animation::AnimationSystem;
+use crate::skeletal_animation::SkeletalAnimationSystem;
+use crate::sound::AudioSystem;
+use crate::debug_overlay::DebugOverlaySystem;
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+#[cfg(feature = "profiling")]
+use std::time::Instant;
 
 pub trait System {
-    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &HashSet<EntityId>, graphics: &mut Graphics, input: &Input, delta_time: f32);
+    // Identifies the system in diagnostics
+    fn name(&self) -> &'static str;
+    fn run(&mut self, ecs: &mut EntityComponentSystem, entities: &BTreeSet<EntityId>, graphics: &mut Graphics, input: &Input, audio: &mut Audio, delta_time: f32);
     fn is_system_entity(&self, entity: &EntityId, ecs: &EntityComponentSystem) -> bool;
+
+    // Called once by SystemManager::init, before the first frame, e.g. to create gpu resources
+    fn init(&mut self, _graphics: &mut Graphics) {}
+
+    // Called when the entity starts being processed by the system
+    fn on_entity_added(&mut self, _entity: &EntityId, _ecs: &EntityComponentSystem) {}
+
+    // Called when the entity stops being processed by the system. The components of a destroyed entity are already cleared.
+    fn on_entity_removed(&mut self, _entity: &EntityId, _ecs: &EntityComponentSystem) {}
+
+    // Fixed step systems are run by run_fixed_step with a constant delta time, possibly several times per frame.
+    fn is_fixed_step(&self) -> bool {
+        false
+    }
+
+    // Names of the systems this one runs after
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+pub struct SystemStats {
+    pub name: &'static str,
+    pub entities: usize,
+}
+
+// Snapshot of the size of the world, e.g. for a debug overlay or to find entities that are never destroyed
+pub struct Diagnostics {
+    pub entities: usize,
+    pub allocator: AllocatorStats,
+    pub systems: Vec<SystemStats>,
 }
 
 pub struct SystemManager {
-    systems: Vec<(Box<dyn System>, HashSet<EntityId>)>,
+    systems: Vec<(Box<dyn System>, BTreeSet<EntityId>)>,
+    // Disabled systems keep their entities up to date but aren't run
+    enabled: Vec<bool>,
+    // Time spent in each system during the current and the previous frame, fixed steps included. Only measured with the
+    // profiling feature.
+    timings: Vec<Duration>,
+    last_frame_timings: Vec<Duration>,
+}
+
+// Adds the time the function took to the timing
+#[cfg(feature = "profiling")]
+fn measure(timing: &mut Duration, function: impl FnOnce()) {
+    let start = Instant::now();
+    function();
+    *timing += start.elapsed();
+}
+
+#[cfg(not(feature = "profiling"))]
+fn measure(_timing: &mut Duration, function: impl FnOnce()) {
+    function();
+}
+
+fn sort_by_dependencies(systems: Vec<Box<dyn System>>) -> Vec<Box<dyn System>> {
+    let names : HashSet<&str> = systems.iter().map(|system| system.name()).collect();
+    for system in &systems {
+        if let Some(missing) = system.dependencies().iter().find(|dependency| !names.contains(*dependency)) {
+            panic!("The system {} depends on the system {} which isn't registered", system.name(), missing);
+        }
+    }
+
+    let mut remaining : Vec<Option<Box<dyn System>>> = systems.into_iter().map(Some).collect();
+    let mut sorted : Vec<Box<dyn System>> = Vec::with_capacity(remaining.len());
+    let mut sorted_names : HashSet<&str> = HashSet::new();
+
+    while sorted.len() < remaining.len() {
+        let next = remaining.iter().position(|system| match system {
+            Some(system) => system.dependencies().iter().all(|dependency| sorted_names.contains(dependency)),
+            None => false,
+        });
+
+        match next {
+            Some(index) => {
+                let system = remaining[index].take().unwrap();
+                sorted_names.insert(system.name());
+                sorted.push(system);
+            },
+            None => {
+                let cycle : Vec<&str> = remaining.iter().flatten().map(|system| system.name()).collect();
+                panic!("The dependencies of the systems {} form a cycle", cycle.join(", "));
+            }
+        }
+    }
+
+    sorted
 }
 
 impl SystemManager {
     pub fn new() -> Self {
-        let mut systems : Vec<(Box<dyn System>, HashSet<EntityId>)> = Vec::new();
+        Self::with_systems(vec!(
+            Box::new(ControlSystem{}),
+            Box::new(PhysicsSystem{}),
+            Box::new(CollisionSystem{}),
+            Box::new(AudioSystem::new()),
+            Box::new(CameraControlSystem{}),
+            Box::new(CameraShakeSystem{}),
+            Box::new(LifetimeSystem{}),
+            Box::new(SpawnSystem{}),
+            Box::new(AnimationSystem::new()),
+            Box::new(SkeletalAnimationSystem{}),
+            Box::new(DebugOverlaySystem::new()),
+            Box::new(RenderSystem::new()),
+        ))
+    }
 
-        // Systems are executed in order
-        systems.push((Box::new(ControlSystem{}), HashSet::new()));
-        systems.push((Box::new(RenderSystem{}), HashSet::new()));
+    // Systems are executed after their dependencies. Systems that don't depend on each other run in registration order.
+    // Panics if a dependency isn't registered or if the dependencies form a cycle.
+    fn with_systems(systems: Vec<Box<dyn System>>) -> Self {
+        let systems : Vec<(Box<dyn System>, BTreeSet<EntityId>)> = sort_by_dependencies(systems).into_iter()
+            .map(|system| (system, BTreeSet::new()))
+            .collect();
 
         Self {
-            systems
+            enabled: vec!(true; systems.len()),
+            timings: vec!(Duration::ZERO; systems.len()),
+            last_frame_timings: vec!(Duration::ZERO; systems.len()),
+            systems,
+        }
+    }
+
+    pub fn init(&mut self, graphics: &mut Graphics) {
+        for (system, _) in &mut self.systems {
+            system.init(graphics);
         }
     }
 
-    pub fn run(&mut self, ecs: &mut EntityComponentSystem, graphics: &mut Graphics, input: &Input, delta_time: f32) {
+    // Must be called once at the beginning of each frame, before any fixed step.
+    pub fn begin_frame(&mut self, ecs: &mut EntityComponentSystem) {
+        std::mem::swap(&mut self.timings, &mut self.last_frame_timings);
+        self.timings.iter_mut().for_each(|timing| *timing = Duration::ZERO);
+
+        ecs.clear_events();
+        ecs.clear_changed_components();
         self.remove_entities_from_systems(&ecs.destroy_entities(), ecs);
         self.add_entities_to_systems(&ecs.create_entities(), ecs);
+        self.refresh_changed_entities(ecs);
+    }
+
+    pub fn run(&mut self, ecs: &mut EntityComponentSystem, graphics: &mut Graphics, input: &Input, audio: &mut Audio, delta_time: f32) {
+        // Components attached or detached by the fixed step systems take effect before the other systems run
+        self.refresh_changed_entities(ecs);
 
-        for (system, entities) in &mut self.systems {
-            system.run(ecs, &entities, graphics, input, delta_time);
+        for (((system, entities), timing), enabled) in self.systems.iter_mut().zip(&mut self.timings).zip(&self.enabled) {
+            if *enabled && !system.is_fixed_step() {
+                measure(timing, || system.run(ecs, &entities, graphics, input, audio, delta_time));
+            }
         } 
     }
 
+    pub fn run_fixed_step(&mut self, ecs: &mut EntityComponentSystem, graphics: &mut Graphics, input: &Input, audio: &mut Audio, fixed_delta: f32) {
+        for (((system, entities), timing), enabled) in self.systems.iter_mut().zip(&mut self.timings).zip(&self.enabled) {
+            if *enabled && system.is_fixed_step() {
+                measure(timing, || system.run(ecs, &entities, graphics, input, audio, fixed_delta));
+            }
+        }
+    }
+
+    // Returns false if no system has that name
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.systems.iter().position(|(system, _)| system.name() == name) {
+            Some(index) => {
+                self.enabled[index] = enabled;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // False for unknown systems
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.systems.iter().zip(&self.enabled).any(|((system, _), enabled)| system.name() == name && *enabled)
+    }
+
+    // Wall clock time each system ran during the last complete frame, in execution order. Empty without the profiling feature.
+    pub fn last_frame_timings(&self) -> Vec<(&'static str, Duration)> {
+        match cfg!(feature = "profiling") {
+            true => self.systems.iter().map(|(system, _)| system.name()).zip(self.last_frame_timings.iter().copied()).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    // Number of entities each system processes, in execution order
+    pub fn system_stats(&self) -> Vec<SystemStats> {
+        self.systems.iter()
+            .map(|(system, entities)| SystemStats { name: system.name(), entities: entities.len() })
+            .collect()
+    }
+
+    pub fn diagnostics(&self, ecs: &EntityComponentSystem) -> Diagnostics {
+        Diagnostics {
+            entities: ecs.entity_count(),
+            allocator: ecs.allocator_stats(),
+            systems: self.system_stats(),
+        }
+    }
+
+    // Adds the entity to the systems it is compatible with and removes it from the others.
+    // Must be called after components of a live entity are added or removed.
+    pub fn refresh_entity(&mut self, entity: &EntityId, ecs: &EntityComponentSystem) {
+        let compatible_systems = self.compatible_systems_indexes(entity, ecs);
+        for (index, (system, system_entities)) in self.systems.iter_mut().enumerate() {
+            match compatible_systems.contains(&index) {
+                true => if system_entities.insert(*entity) {
+                    system.on_entity_added(entity, ecs);
+                },
+                false => if system_entities.remove(entity) {
+                    system.on_entity_removed(entity, ecs);
+                },
+            }
+        }
+    }
+
+    // Refreshes the entities changed by attach_component and detach_component
+    fn refresh_changed_entities(&mut self, ecs: &mut EntityComponentSystem) {
+        for entity in ecs.take_changed_entities() {
+            self.refresh_entity(&entity, ecs);
+        }
+    }
+
     fn add_entities_to_systems(&mut self, entities: &Vec<EntityId>, ecs: &EntityComponentSystem) {
         for entity in entities {
             for index in self.compatible_systems_indexes(&entity, ecs) {
-                self.systems[index].1.insert(*entity);
+                let (system, system_entities) = &mut self.systems[index];
+                if system_entities.insert(*entity) {
+                    system.on_entity_added(entity, ecs);
+                }
             }
         }
     }
 
+    // The components of destroyed entities are already cleared, so the entities are removed from every system
     fn remove_entities_from_systems(&mut self, entities: &Vec<EntityId>, ecs: &EntityComponentSystem) {
         for entity in entities {
-            for index in self.compatible_systems_indexes(&entity, ecs) {
-                self.systems[index].1.remove(entity);
+            for (system, system_entities) in &mut self.systems {
+                if system_entities.remove(entity) {
+                    system.on_entity_removed(entity, ecs);
+                }
             }
         }
     }
@@ -59,4 +275,105 @@ impl SystemManager {
             .filter_map(|(index, system_entities)| if system_entities.0.is_system_entity(&entity, ecs) { Some(index) } else { None })
             .collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+    use crate::lifetime::Lifetime;
+
+    #[test]
+    fn destroyed_entity_leaves_every_system() {
+        let mut ecs = EntityComponentSystem::new(4, Resources::new());
+        let mut systems = SystemManager::new();
+        ecs.load_from_json(&serde_json::json!({
+            "entities": [{ "EntityId": { "index": 0, "generation": 0 }, "Lifetime": { "remaining": 1.0 } }]
+        })).unwrap();
+        systems.begin_frame(&mut ecs);
+
+        let entity = EntityId { index: 0, generation: 0 };
+        assert!(systems.systems.iter().any(|(_, entities)| entities.contains(&entity)));
+
+        ecs.remove_entity(entity);
+        systems.begin_frame(&mut ecs);
+
+        assert!(systems.systems.iter().all(|(_, entities)| !entities.contains(&entity)));
+        assert!(!ecs.has_component::<Lifetime>(&entity));
+    }
+
+    struct TestSystem {
+        name: &'static str,
+        dependencies: &'static [&'static str],
+    }
+
+    impl System for TestSystem {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn run(&mut self, _ecs: &mut EntityComponentSystem, _entities: &BTreeSet<EntityId>, _graphics: &mut Graphics, _input: &Input, _audio: &mut Audio, _delta_time: f32) {}
+
+        fn is_system_entity(&self, _entity: &EntityId, _ecs: &EntityComponentSystem) -> bool {
+            false
+        }
+
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.dependencies
+        }
+    }
+
+    fn test_systems(systems: &[(&'static str, &'static [&'static str])]) -> Vec<Box<dyn System>> {
+        systems.iter()
+            .map(|&(name, dependencies)| Box::new(TestSystem { name, dependencies }) as Box<dyn System>)
+            .collect()
+    }
+
+    #[test]
+    fn systems_run_after_their_dependencies() {
+        let systems = SystemManager::with_systems(test_systems(&[("render", &["animation"]), ("control", &[]), ("animation", &["control"]), ("audio", &[])]));
+
+        let names : Vec<&str> = systems.system_stats().iter().map(|stats| stats.name).collect();
+        assert_eq!(names, vec!("control", "animation", "render", "audio"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The dependencies of the systems a, b form a cycle")]
+    fn dependency_cycle_panics() {
+        SystemManager::with_systems(test_systems(&[("a", &["b"]), ("b", &["a"]), ("c", &[])]));
+    }
+
+    #[test]
+    fn systems_can_be_disabled_by_name() {
+        let mut systems = SystemManager::new();
+        assert!(systems.is_enabled("render"));
+
+        assert!(systems.set_enabled("render", false));
+        assert!(!systems.is_enabled("render"));
+        assert!(systems.is_enabled("physics"));
+
+        assert!(!systems.set_enabled("unknown", false));
+        assert!(!systems.is_enabled("unknown"));
+    }
+
+    #[test]
+    fn diagnostics_count_entities_per_system() {
+        let mut ecs = EntityComponentSystem::new(4, Resources::new());
+        let mut systems = SystemManager::new();
+        ecs.load_from_json(&serde_json::json!({
+            "entities": [
+                { "EntityId": { "index": 0, "generation": 0 }, "Lifetime": { "remaining": 1.0 } },
+                { "EntityId": { "index": 1, "generation": 0 }, "Lifetime": { "remaining": 1.0 } }
+            ]
+        })).unwrap();
+        systems.begin_frame(&mut ecs);
+
+        let diagnostics = systems.diagnostics(&ecs);
+        assert_eq!(diagnostics.entities, 2);
+        assert_eq!(diagnostics.allocator.active, 2);
+        assert_eq!(ecs.component_count::<Lifetime>(), 2);
+
+        let lifetime = diagnostics.systems.iter().find(|stats| stats.name == "lifetime").unwrap();
+        assert_eq!(lifetime.entities, 2);
+    }
+}
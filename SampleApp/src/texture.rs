@@ -1,27 +1,43 @@
-use image::GenericImageView;
 use std::fs;
 use anyhow::*;
+use crate::graphics::ColorSpace;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
     pub fn load_texture(
         texture_name: &str,
+        color_space: ColorSpace,
         device: &wgpu::Device,
         queue: &wgpu::Queue
     ) -> Result<Self> {
+        let rgba = Self::read_rgba(texture_name)?;
+
+        Ok(Self::from_rgba(&rgba, rgba.dimensions(), texture_name, color_space, device, queue))
+    }
+
+    // Reads and decodes a texture of the textures directory. Doesn't need the gpu so it can run on any thread.
+    pub fn read_rgba(texture_name: &str) -> Result<image::RgbaImage> {
         let mut path = std::env::current_dir().unwrap();
         path.push("src\\resources\\textures");
         path.push(texture_name);
-        let bytes = fs::read(path).unwrap();
-        let image = image::load_from_memory(&bytes).unwrap();
-        let rgba = image.as_rgba8().unwrap();
-        let dimensions = image.dimensions();
+        let bytes = fs::read(&path).with_context(|| format!("Failed to read the file: {:?}", path))?;
+        let image = image::load_from_memory(&bytes)?;
 
+        Ok(image.to_rgba8())
+    }
+
+    pub fn from_rgba(
+        rgba: &[u8],
+        dimensions: (u32, u32),
+        label: &str,
+        color_space: ColorSpace,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue
+    ) -> Self {
         let texture_size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -36,11 +52,11 @@ impl Texture {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: color_space.texture_format(),
                 // SAMPLED tells wgpu that we want to use this texture in shaders
                 // COPY_DST means that we want to copy data to this texture
                 usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-                label: None,
+                label: Some(label),
             }
         );
 
@@ -65,16 +81,52 @@ impl Texture {
         // We don't need to configure the texture view much, so let's
         // let wgpu define it.
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        Self {texture, view}
+    }
+
+    // Color texture a camera renders into and meshes sample, in the format of the pipelines. Multisampled ones are only rendered
+    // into and resolved to a texture with a single sample.
+    pub fn create_render_target(device: &wgpu::Device, swap_chain_descriptor: &wgpu::SwapChainDescriptor, sample_count: u32, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: swap_chain_descriptor.width,
+                height: swap_chain_descriptor.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: swap_chain_descriptor.format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
         });
 
-        Ok(Self {texture, view, sampler})
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {texture, view}
+    }
+
+    // The sample count must match the one of the color attachment it is used with
+    pub fn create_depth_texture(device: &wgpu::Device, swap_chain_descriptor: &wgpu::SwapChainDescriptor, sample_count: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: swap_chain_descriptor.width,
+            height: swap_chain_descriptor.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: crate::graphics::DEPTH_FORMAT,
+                // RENDER_ATTACHMENT so the render pass can write depth into it
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            }
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {texture, view}
     }
 }
\ No newline at end of file